@@ -1,2 +1,18 @@
 // TODO: Fail compilation unless client XOR server
-pub struct Wat {}
+use lotsa::{block_registry::BlockRegistry, chunk_map::ChunkMap, game::Game, life, sim::Simulator};
+
+/// This is where the game's block types, rules, and starting world go. For
+/// now it's just Conway's Game of Life.
+pub struct TemplateGame;
+
+impl Game for TemplateGame {
+  fn register_blocks(&self, registry: &mut BlockRegistry) { life::register_blocks(registry); }
+
+  fn init_simulator(&self, sim: &mut Simulator, registry: &BlockRegistry) {
+    life::init(sim, registry);
+  }
+
+  fn generate_world(&self, registry: &BlockRegistry) -> ChunkMap {
+    life::Life.generate_world(registry)
+  }
+}
@@ -1,6 +1,15 @@
+use std::time::Duration;
+
+use lotsa::server::{Server, ServerConfig};
+
 mod game;
 
-pub fn main() {
-  let server = lotsa::server::Server::new();
-  server.start();
+pub fn main() -> std::io::Result<()> {
+  let config = ServerConfig::with_game(game::TemplateGame)
+    .bind_address("0.0.0.0:8000")
+    .pkg_dir("pkg/")
+    .www_dir("www/")
+    .tick_interval(Duration::from_millis(100))
+    .save_dir("world/");
+  Server::with_config(config).start()
 }
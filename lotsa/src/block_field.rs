@@ -0,0 +1,154 @@
+use std::marker::PhantomData;
+
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::unique_descrip::UniqueDescrip;
+
+pub const MAX_BLOCK_FIELDS: usize = 8;
+
+/// Identifies a per-block field. Games declare their fields as `BlockField`
+/// constants, each with a distinct id below `MAX_BLOCK_FIELDS`. Ids are
+/// always in range, so chunks never see one they can't store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct FieldId(u8);
+
+impl FieldId {
+  /// None unless `id < MAX_BLOCK_FIELDS`
+  pub fn new(id: u8) -> Option<FieldId> {
+    if (id as usize) < MAX_BLOCK_FIELDS {
+      Some(FieldId(id))
+    } else {
+      None
+    }
+  }
+
+  /// Every id, in order
+  pub fn all() -> impl Iterator<Item = FieldId> { (0..MAX_BLOCK_FIELDS as u8).map(FieldId) }
+
+  pub fn index(self) -> usize { self.0 as usize }
+}
+
+// Deserializing checks the range too, since ids come from saved chunks and
+// the network
+impl<'de> Deserialize<'de> for FieldId {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let id = u8::deserialize(deserializer)?;
+    FieldId::new(id).ok_or_else(|| de::Error::custom("field id out of range"))
+  }
+}
+
+impl UniqueDescrip for FieldId {
+  fn unique_descrip(&self) -> String { format!("FieldId{}", self.0) }
+}
+
+/// Types that can be stored in a per-block field. Every field is stored as a
+/// u16, and defaults to whatever a raw value of zero converts to.
+pub trait FieldValue: Copy {
+  fn to_raw(self) -> u16;
+  fn from_raw(raw: u16) -> Self;
+}
+
+impl FieldValue for bool {
+  fn to_raw(self) -> u16 { u16::from(self) }
+
+  fn from_raw(raw: u16) -> Self { raw != 0 }
+}
+
+impl FieldValue for u8 {
+  fn to_raw(self) -> u16 { u16::from(self) }
+
+  fn from_raw(raw: u16) -> Self { raw as u8 }
+}
+
+impl FieldValue for u16 {
+  fn to_raw(self) -> u16 { self }
+
+  fn from_raw(raw: u16) -> Self { raw }
+}
+
+impl FieldValue for i8 {
+  fn to_raw(self) -> u16 { u16::from(self as u8) }
+
+  fn from_raw(raw: u16) -> Self { raw as u8 as i8 }
+}
+
+impl FieldValue for i16 {
+  fn to_raw(self) -> u16 { self as u16 }
+
+  fn from_raw(raw: u16) -> Self { raw as i16 }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct BlockField<T> {
+  id: FieldId,
+  _phantom: PhantomData<T>,
+}
+
+impl<T> BlockField<T> {
+  /// Fails to compile when declaring a constant with an id that is too big,
+  /// and panics when called at runtime with one.
+  #[allow(clippy::no_effect)]
+  pub const fn new(id: u8) -> BlockField<T> {
+    // Out of bounds unless id < MAX_BLOCK_FIELDS
+    [(); MAX_BLOCK_FIELDS][id as usize];
+    BlockField {
+      id: FieldId(id),
+      _phantom: PhantomData,
+    }
+  }
+
+  pub fn id(&self) -> FieldId { self.id }
+}
+
+impl<T> Clone for BlockField<T> {
+  fn clone(&self) -> Self { *self }
+}
+
+impl<T> Copy for BlockField<T> {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_round_trips() {
+    assert_eq!(bool::from_raw(true.to_raw()), true);
+    assert_eq!(bool::from_raw(false.to_raw()), false);
+    assert_eq!(u8::from_raw(200u8.to_raw()), 200);
+    assert_eq!(u16::from_raw(60000u16.to_raw()), 60000);
+    assert_eq!(i8::from_raw((-100i8).to_raw()), -100);
+    assert_eq!(i16::from_raw((-30000i16).to_raw()), -30000);
+  }
+
+  #[test]
+  fn test_zero_defaults() {
+    assert_eq!(bool::from_raw(0), false);
+    assert_eq!(u8::from_raw(0), 0);
+    assert_eq!(i16::from_raw(0), 0);
+  }
+
+  #[test]
+  fn test_new() {
+    const LAST: BlockField<u8> = BlockField::new(MAX_BLOCK_FIELDS as u8 - 1);
+    assert_eq!(LAST.id(), FieldId(7));
+  }
+
+  #[test]
+  fn test_field_id() {
+    assert_eq!(FieldId::new(7), Some(FieldId(7)));
+    assert_eq!(FieldId::new(MAX_BLOCK_FIELDS as u8), None);
+    assert_eq!(FieldId::all().count(), MAX_BLOCK_FIELDS);
+
+    let bytes = bincode::serialize(&FieldId(7)).unwrap();
+    assert_eq!(bincode::deserialize::<FieldId>(&bytes).unwrap(), FieldId(7));
+    let too_big = bincode::serialize(&(MAX_BLOCK_FIELDS as u8)).unwrap();
+    assert!(bincode::deserialize::<FieldId>(&too_big).is_err());
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_new_rejects_big_ids() {
+    let id = MAX_BLOCK_FIELDS as u8;
+    BlockField::<u8>::new(id);
+  }
+}
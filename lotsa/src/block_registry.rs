@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::block::{BlockType, EMPTY, UNKNOWN};
+
+/// Everything the engine knows about a block type besides its id
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockMeta {
+  pub name: String,
+  /// A CSS colour, used by the client renderer
+  pub color: String,
+  /// Used by the `Debugger` to draw and load chunks as text
+  pub debug_char: char,
+  pub solid: bool,
+}
+
+impl BlockMeta {
+  pub fn new(name: &str, color: &str, debug_char: char) -> BlockMeta {
+    BlockMeta {
+      name: name.to_string(),
+      color: color.to_string(),
+      debug_char,
+      solid: false,
+    }
+  }
+
+  pub fn solid(mut self) -> BlockMeta {
+    self.solid = true;
+    self
+  }
+}
+
+/// Maps block type names to ids, and keeps metadata for each block type.
+///
+/// Ids are allocated in registration order, starting right after the built-in
+/// `UNKNOWN` and `EMPTY` types, so a game that registers its block types in
+/// the same order always gets the same ids.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Vec<Option<BlockMeta>>", into = "Vec<Option<BlockMeta>>")]
+pub struct BlockRegistry {
+  blocks: Vec<Option<BlockMeta>>,
+  by_name: HashMap<String, BlockType>,
+}
+
+impl BlockRegistry {
+  pub fn new() -> BlockRegistry {
+    let mut registry = BlockRegistry {
+      blocks: vec![None],
+      by_name: HashMap::new(),
+    };
+    let unknown = registry.register(BlockMeta::new("unknown", "#f00", 'X').solid());
+    let empty = registry.register(BlockMeta::new("empty", "#fff", '.'));
+    debug_assert_eq!(unknown, UNKNOWN);
+    debug_assert_eq!(empty, EMPTY);
+    registry
+  }
+
+  /// Panics if a block type with the same name has already been registered
+  pub fn register(&mut self, meta: BlockMeta) -> BlockType {
+    if self.by_name.contains_key(&meta.name) {
+      panic!("Block type {} registered twice", meta.name);
+    }
+    let block_type = BlockType(self.blocks.len() as u16);
+    self.by_name.insert(meta.name.clone(), block_type);
+    self.blocks.push(Some(meta));
+    block_type
+  }
+
+  pub fn get(&self, block_type: BlockType) -> Option<&BlockMeta> {
+    self
+      .blocks
+      .get(block_type.0 as usize)
+      .and_then(|meta| meta.as_ref())
+  }
+
+  pub fn lookup(&self, name: &str) -> Option<BlockType> { self.by_name.get(name).cloned() }
+
+  pub fn name(&self, block_type: BlockType) -> Option<&str> {
+    self.get(block_type).map(|meta| meta.name.as_str())
+  }
+
+  pub fn len(&self) -> usize { self.by_name.len() }
+
+  pub fn is_empty(&self) -> bool { self.by_name.is_empty() }
+
+  pub fn iter<'a>(&'a self) -> impl Iterator<Item = (BlockType, &'a BlockMeta)> + 'a {
+    self
+      .blocks
+      .iter()
+      .enumerate()
+      .filter_map(|(i, meta)| meta.as_ref().map(|meta| (BlockType(i as u16), meta)))
+  }
+}
+
+impl Default for BlockRegistry {
+  fn default() -> BlockRegistry { BlockRegistry::new() }
+}
+
+impl From<Vec<Option<BlockMeta>>> for BlockRegistry {
+  fn from(blocks: Vec<Option<BlockMeta>>) -> Self {
+    let by_name = blocks
+      .iter()
+      .enumerate()
+      .filter_map(|(i, meta)| {
+        meta
+          .as_ref()
+          .map(|meta| (meta.name.clone(), BlockType(i as u16)))
+      })
+      .collect();
+    BlockRegistry { blocks, by_name }
+  }
+}
+
+impl From<BlockRegistry> for Vec<Option<BlockMeta>> {
+  fn from(registry: BlockRegistry) -> Self { registry.blocks }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_builtins() {
+    let registry = BlockRegistry::new();
+    assert_eq!(registry.lookup("unknown"), Some(UNKNOWN));
+    assert_eq!(registry.lookup("empty"), Some(EMPTY));
+    assert_eq!(registry.name(EMPTY), Some("empty"));
+    assert!(registry.get(UNKNOWN).unwrap().solid);
+    assert_eq!(registry.get(BlockType(0)), None);
+    assert_eq!(registry.len(), 2);
+  }
+
+  #[test]
+  fn test_register() {
+    let mut registry = BlockRegistry::new();
+    let cobble = registry.register(BlockMeta::new("cobble", "#888", 'C').solid());
+    let water = registry.register(BlockMeta::new("water", "#00f", 'W'));
+
+    assert_eq!(cobble, BlockType(3));
+    assert_eq!(water, BlockType(4));
+    assert_eq!(registry.lookup("cobble"), Some(cobble));
+    assert_eq!(registry.lookup("lava"), None);
+    assert_eq!(registry.get(water).unwrap().color, "#00f");
+    assert!(!registry.get(water).unwrap().solid);
+    assert_eq!(registry.get(BlockType(5)), None);
+    assert_eq!(
+      registry
+        .iter()
+        .map(|(bt, meta)| (bt, meta.debug_char))
+        .collect::<Vec<_>>(),
+      vec![(UNKNOWN, 'X'), (EMPTY, '.'), (cobble, 'C'), (water, 'W')]
+    );
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_register_twice() {
+    let mut registry = BlockRegistry::new();
+    registry.register(BlockMeta::new("cobble", "#888", 'C'));
+    registry.register(BlockMeta::new("cobble", "#999", 'D'));
+  }
+
+  #[test]
+  fn test_serialization() {
+    let mut registry = BlockRegistry::new();
+    let cobble = registry.register(BlockMeta::new("cobble", "#888", 'C'));
+
+    let bytes = bincode::serialize(&registry).unwrap();
+    let deserialized: BlockRegistry = bincode::deserialize(&bytes).unwrap();
+
+    assert_eq!(deserialized, registry);
+    assert_eq!(deserialized.lookup("cobble"), Some(cobble));
+  }
+}
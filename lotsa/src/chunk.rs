@@ -7,7 +7,9 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
   block::{BlockType, UNKNOWN},
+  block_field::{BlockField, FieldId, FieldValue, MAX_BLOCK_FIELDS},
   chunk_pos::ChunkPos,
+  palette::PalettedBlockTypes,
   query::BlockInfo,
 };
 
@@ -37,31 +39,148 @@ impl IndexMut<ChunkPos> for BlockTypesArray {
   }
 }
 
+/// How a chunk stores its block types
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkStorage {
+  /// A block type for every position, quickest to read and write
+  Flat,
+  /// A palette with bit-packed indices, much smaller for chunks with only a
+  /// few block types
+  Paletted,
+}
+
+// Flat storage is kept inline to avoid an extra indirection on every lookup
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Serialize, Deserialize)]
+enum BlockTypes {
+  Flat(#[serde(with = "BigArray")] BlockTypesArray),
+  Paletted(PalettedBlockTypes),
+}
+
+impl BlockTypes {
+  fn get(&self, pos: ChunkPos) -> BlockType {
+    match self {
+      BlockTypes::Flat(block_types) => block_types[pos],
+      BlockTypes::Paletted(block_types) => block_types.get(pos.raw_n() as usize),
+    }
+  }
+
+  fn set(&mut self, pos: ChunkPos, block_type: BlockType) {
+    match self {
+      BlockTypes::Flat(block_types) => block_types[pos] = block_type,
+      BlockTypes::Paletted(block_types) => block_types.set(pos.raw_n() as usize, block_type),
+    }
+  }
+
+  fn fill(&mut self, block_type: BlockType) {
+    match self {
+      BlockTypes::Flat(block_types) => *block_types = [block_type; CHUNK_WIDTH_E3],
+      BlockTypes::Paletted(block_types) => block_types.fill(block_type),
+    }
+  }
+}
+
+// The serde derive is what version 1 of `chunk_format` stored, so changing
+// the layout here breaks reading those saves. New code should go through
+// `chunk_format` instead.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Chunk {
-  #[serde(with = "BigArray")]
-  block_types: BlockTypesArray,
+  block_types: BlockTypes,
+  // Indexed by FieldId, each either empty if every value is zero, or holding
+  // a value for every position in the chunk
+  field_values: Vec<Vec<u16>>,
 }
 
 impl Chunk {
-  pub fn new() -> Chunk {
+  pub fn new() -> Chunk { Chunk::with_storage(ChunkStorage::Flat) }
+
+  pub fn with_storage(storage: ChunkStorage) -> Chunk {
+    let block_types = match storage {
+      ChunkStorage::Flat => BlockTypes::Flat([UNKNOWN; CHUNK_WIDTH_E3]),
+      ChunkStorage::Paletted => BlockTypes::Paletted(PalettedBlockTypes::new(UNKNOWN)),
+    };
     Chunk {
-      block_types: [UNKNOWN; CHUNK_WIDTH_E3],
+      block_types,
+      field_values: Vec::new(),
     }
   }
 
+  pub fn storage(&self) -> ChunkStorage {
+    match self.block_types {
+      BlockTypes::Flat(_) => ChunkStorage::Flat,
+      BlockTypes::Paletted(_) => ChunkStorage::Paletted,
+    }
+  }
+
+  /// Shrinks the palette of a paletted chunk down to the block types still in
+  /// use
+  pub fn compact(&mut self) {
+    if let BlockTypes::Paletted(block_types) = &mut self.block_types {
+      block_types.compact();
+    }
+  }
+
+  /// Whether the field values are laid out the way the other methods expect,
+  /// which deserializing doesn't check
+  pub(crate) fn has_valid_fields(&self) -> bool {
+    self.field_values.len() <= MAX_BLOCK_FIELDS
+      && self
+        .field_values
+        .iter()
+        .all(|values| values.is_empty() || values.len() == CHUNK_WIDTH_E3)
+  }
+
   pub fn get_block(&self, pos: ChunkPos) -> BlockInfo {
-    BlockInfo {
-      block_type: self.block_types[pos],
+    let mut block = BlockInfo::new(self.block_types.get(pos));
+    for (i, values) in self.field_values.iter().enumerate() {
+      if !values.is_empty() {
+        block.fields[i] = values[pos.raw_n() as usize];
+      }
+    }
+    block
+  }
+
+  pub fn get_field_raw(&self, pos: ChunkPos, field_id: FieldId) -> u16 {
+    match self.field_values.get(field_id.index()) {
+      Some(values) if !values.is_empty() => values[pos.raw_n() as usize],
+      _ => 0,
+    }
+  }
+
+  pub fn get_field<T: FieldValue>(&self, pos: ChunkPos, field: BlockField<T>) -> T {
+    T::from_raw(self.get_field_raw(pos, field.id()))
+  }
+
+  pub fn set_field_raw(&mut self, pos: ChunkPos, field_id: FieldId, value: u16) {
+    let index = field_id.index();
+    assert!(
+      index < MAX_BLOCK_FIELDS,
+      "FieldId must be < {}",
+      MAX_BLOCK_FIELDS
+    );
+    if self.field_values.len() <= index {
+      self.field_values.resize(index + 1, Vec::new());
     }
+    let values = &mut self.field_values[index];
+    if values.is_empty() {
+      if value == 0 {
+        return;
+      }
+      values.resize(CHUNK_WIDTH_E3, 0);
+    }
+    values[pos.raw_n() as usize] = value;
+  }
+
+  pub fn set_field<T: FieldValue>(&mut self, pos: ChunkPos, field: BlockField<T>, value: T) {
+    self.set_field_raw(pos, field.id(), value.to_raw());
   }
 
   pub fn set_block_type(&mut self, pos: ChunkPos, block_type: BlockType) {
-    self.block_types[pos] = block_type;
+    self.block_types.set(pos, block_type);
   }
 
   pub fn fill_with_block_type(&mut self, block_type: BlockType) {
-    self.block_types = [block_type; CHUNK_WIDTH_E3];
+    self.block_types.fill(block_type);
   }
 
   pub fn blocks_iter(&self) -> ChunkBlocksIterator<'_> { ChunkBlocksIterator::new(self) }
@@ -132,9 +251,12 @@ impl<'a> FusedIterator for ChunkBlocksIterator<'a> {}
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::block::EMPTY;
   use test::Bencher;
 
   const COBBLE: BlockType = BlockType(37);
+  const AGE: BlockField<u8> = BlockField::new(0);
+  const CHARGE: BlockField<i16> = BlockField::new(3);
 
   #[test]
   fn test_get_block() {
@@ -152,6 +274,57 @@ mod tests {
     assert_eq!(c.get_block(p).block_type, COBBLE);
   }
 
+  #[test]
+  fn test_storages_match() {
+    let mut flat = Chunk::with_storage(ChunkStorage::Flat);
+    let mut paletted = Chunk::with_storage(ChunkStorage::Paletted);
+    assert_eq!(flat.storage(), ChunkStorage::Flat);
+    assert_eq!(paletted.storage(), ChunkStorage::Paletted);
+
+    for c in [&mut flat, &mut paletted].iter_mut() {
+      c.fill_with_block_type(EMPTY);
+      for n in 0..40u8 {
+        c.set_block_type(
+          ChunkPos::new(n % 32, n / 2, n % 3),
+          BlockType(40 + u16::from(n % 5)),
+        );
+      }
+      c.set_block_type(ChunkPos::new(31, 31, 31), COBBLE);
+    }
+
+    assert!(flat
+      .blocks_iter()
+      .zip(paletted.blocks_iter())
+      .all(|((_, a), (_, b))| a == b));
+  }
+
+  #[test]
+  fn test_paletted_serialization_size() {
+    let mut flat = Chunk::with_storage(ChunkStorage::Flat);
+    let mut paletted = Chunk::with_storage(ChunkStorage::Paletted);
+    flat.fill_with_block_type(EMPTY);
+    paletted.fill_with_block_type(EMPTY);
+
+    let flat_bytes = bincode::serialize(&flat).unwrap();
+    let paletted_bytes = bincode::serialize(&paletted).unwrap();
+    assert!(paletted_bytes.len() < 100);
+    assert!(flat_bytes.len() > CHUNK_WIDTH_E3);
+
+    paletted.set_block_type(ChunkPos::new(1, 2, 3), COBBLE);
+    let paletted_bytes = bincode::serialize(&paletted).unwrap();
+    assert!(paletted_bytes.len() < CHUNK_WIDTH_E3 / 4);
+
+    let deserialized: Chunk = bincode::deserialize(&paletted_bytes).unwrap();
+    assert_eq!(
+      deserialized.get_block(ChunkPos::new(1, 2, 3)).block_type(),
+      COBBLE
+    );
+    assert_eq!(
+      deserialized.get_block(ChunkPos::new(1, 2, 4)).block_type(),
+      EMPTY
+    );
+  }
+
   #[test]
   fn test_fill_with_block_type() {
     let mut c = Chunk::new();
@@ -161,6 +334,36 @@ mod tests {
     assert_eq!(c.get_block(p).block_type, COBBLE);
   }
 
+  #[test]
+  fn test_fields() {
+    let mut c = Chunk::new();
+    let p = ChunkPos::new(1, 2, 3);
+    let q = ChunkPos::new(3, 2, 1);
+
+    assert_eq!(c.get_field(p, AGE), 0);
+    assert_eq!(c.get_field(p, CHARGE), 0);
+
+    c.set_field(p, AGE, 7);
+    c.set_field(q, CHARGE, -12);
+    assert_eq!(c.get_field(p, AGE), 7);
+    assert_eq!(c.get_field(p, CHARGE), 0);
+    assert_eq!(c.get_field(q, AGE), 0);
+    assert_eq!(c.get_field(q, CHARGE), -12);
+
+    assert_eq!(c.get_block(p).field(AGE), 7);
+    assert_eq!(c.get_block(q).field(CHARGE), -12);
+    assert_eq!(c.get_block(q).block_type(), UNKNOWN);
+  }
+
+  #[test]
+  fn test_set_block_type_keeps_fields() {
+    let mut c = Chunk::new();
+    let p = ChunkPos::new(1, 2, 3);
+    c.set_field(p, AGE, 7);
+    c.set_block_type(p, COBBLE);
+    assert_eq!(c.get_block(p).field(AGE), 7);
+  }
+
   #[test]
   fn test_blocks_iter() {
     let c = three_cobble_chunk();
@@ -218,8 +421,54 @@ mod tests {
     });
   }
 
-  fn three_cobble_chunk() -> Chunk {
-    let mut c = Chunk::new();
+  #[bench]
+  fn bench_get_blocks_paletted(b: &mut Bencher) {
+    let c = three_cobble_chunk_with_storage(ChunkStorage::Paletted);
+
+    b.iter(|| {
+      let mut iter = c.blocks_iter().filter(|(_, b)| b.block_type == COBBLE);
+      iter.next();
+      iter.next();
+      iter.next();
+      iter.next();
+    });
+  }
+
+  fn bench_set_block_types(b: &mut Bencher, storage: ChunkStorage) {
+    let mut c = three_cobble_chunk_with_storage(storage);
+    let mut n: u16 = 0;
+
+    b.iter(|| {
+      n = n.wrapping_add(1);
+      let pos = ChunkPos::new((n % 32) as u8, (n / 32 % 32) as u8, 0);
+      c.set_block_type(pos, BlockType(40 + n % 4));
+    });
+  }
+
+  #[bench]
+  fn bench_set_block_types_paletted(b: &mut Bencher) {
+    bench_set_block_types(b, ChunkStorage::Paletted);
+  }
+
+  #[bench]
+  fn bench_set_block_types_flat(b: &mut Bencher) { bench_set_block_types(b, ChunkStorage::Flat); }
+
+  #[bench]
+  fn bench_serialize_paletted(b: &mut Bencher) {
+    let c = three_cobble_chunk_with_storage(ChunkStorage::Paletted);
+    b.iter(|| bincode::serialize(&c).unwrap());
+  }
+
+  #[bench]
+  fn bench_serialize_flat(b: &mut Bencher) {
+    let c = three_cobble_chunk_with_storage(ChunkStorage::Flat);
+    b.iter(|| bincode::serialize(&c).unwrap());
+  }
+
+  fn three_cobble_chunk() -> Chunk { three_cobble_chunk_with_storage(ChunkStorage::Flat) }
+
+  fn three_cobble_chunk_with_storage(storage: ChunkStorage) -> Chunk {
+    let mut c = Chunk::with_storage(storage);
     c.set_block_type(ChunkPos::new(1, 1, 0), COBBLE);
     c.set_block_type(ChunkPos::new(2, 2, 0), COBBLE);
     c.set_block_type(ChunkPos::new(3, 3, 0), COBBLE);
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(
+  Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct ChunkCoord {
+  pub x: i32,
+  pub y: i32,
+  pub z: i32,
+}
+
+impl ChunkCoord {
+  pub fn new(x: i32, y: i32, z: i32) -> ChunkCoord { ChunkCoord { x, y, z } }
+
+  pub fn origin() -> ChunkCoord { ChunkCoord::new(0, 0, 0) }
+
+  pub fn offset(self, other: ChunkCoord) -> ChunkCoord {
+    ChunkCoord::new(self.x + other.x, self.y + other.y, self.z + other.z)
+  }
+}
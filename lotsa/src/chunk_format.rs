@@ -0,0 +1,364 @@
+use std::{
+  collections::HashMap,
+  fmt,
+  io::{self, Cursor, Read, Write},
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  block::{BlockType, UNKNOWN},
+  block_field::FieldId,
+  block_registry::BlockRegistry,
+  chunk::{Chunk, ChunkStorage, CHUNK_WIDTH_E3},
+  chunk_pos::ChunkPos,
+};
+
+/// Starts every chunk written in format version 2 or later
+pub const MAGIC: [u8; 4] = *b"LTSC";
+
+/// The version that `write` produces. The versions so far:
+///
+/// 1. A little-endian u16 version, then the zlib-compressed bincode of
+///    `Chunk`'s serde derive, with block types stored as ids. No magic.
+/// 2. The magic, a little-endian u16 version, then a zlib-compressed bincode
+///    `ChunkRecordV2`, with block types stored by name.
+pub const FORMAT_VERSION: u16 = 2;
+
+#[derive(Debug)]
+pub enum ChunkFormatError {
+  Io(io::Error),
+  Malformed(bincode::Error),
+  /// Doesn't start with the magic, and isn't an older version either
+  NotAChunk,
+  UnknownVersion(u16),
+}
+
+impl fmt::Display for ChunkFormatError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ChunkFormatError::Io(err) => write!(f, "io error: {}", err),
+      ChunkFormatError::Malformed(err) => write!(f, "malformed chunk: {}", err),
+      ChunkFormatError::NotAChunk => write!(f, "not a chunk"),
+      ChunkFormatError::UnknownVersion(version) => write!(
+        f,
+        "chunk has format version {}, expected at most {}",
+        version, FORMAT_VERSION
+      ),
+    }
+  }
+}
+
+impl std::error::Error for ChunkFormatError {}
+
+impl From<io::Error> for ChunkFormatError {
+  fn from(err: io::Error) -> ChunkFormatError { ChunkFormatError::Io(err) }
+}
+
+fn malformed(reason: &str) -> ChunkFormatError {
+  ChunkFormatError::Malformed(Box::new(bincode::ErrorKind::Custom(reason.to_string())))
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChunkRecordV2 {
+  /// Block type names, so that saved chunks survive block types being
+  /// registered in a different order
+  palette: Vec<String>,
+  /// An index into the palette for every block, in `ChunkPos::raw_n` order
+  block_types: Vec<u16>,
+  /// A value for every block, for each field that isn't all zero
+  fields: Vec<(u8, Vec<u16>)>,
+}
+
+pub fn encode(chunk: &Chunk, registry: &BlockRegistry) -> Vec<u8> {
+  let mut bytes = Vec::new();
+  write(&mut bytes, chunk, registry).expect("write chunk to memory");
+  bytes
+}
+
+pub fn decode(bytes: &[u8], registry: &BlockRegistry) -> Result<Chunk, ChunkFormatError> {
+  read(bytes, registry)
+}
+
+/// Block types that aren't in the registry are written as unknown
+pub fn write<W: Write>(
+  mut writer: W,
+  chunk: &Chunk,
+  registry: &BlockRegistry,
+) -> Result<(), ChunkFormatError> {
+  writer.write_all(&MAGIC)?;
+  writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+  let mut encoder = ZlibEncoder::new(writer, Compression::default());
+  bincode::serialize_into(&mut encoder, &to_record(chunk, registry))
+    .map_err(ChunkFormatError::Malformed)?;
+  encoder.finish()?.flush()?;
+  Ok(())
+}
+
+/// Reads any version of the format. Block type names that aren't in the
+/// registry are read as unknown.
+pub fn read<R: Read>(mut reader: R, registry: &BlockRegistry) -> Result<Chunk, ChunkFormatError> {
+  let mut header = [0; 4];
+  reader.read_exact(&mut header)?;
+  if header != MAGIC {
+    // Version 1 had no magic, so the header is its version and the start of
+    // the compressed chunk
+    if u16::from_le_bytes([header[0], header[1]]) == 1 {
+      return read_v1(Cursor::new(header[2..].to_vec()).chain(reader));
+    }
+    return Err(ChunkFormatError::NotAChunk);
+  }
+
+  let mut version = [0; 2];
+  reader.read_exact(&mut version)?;
+  match u16::from_le_bytes(version) {
+    2 => read_v2(reader, registry),
+    version => Err(ChunkFormatError::UnknownVersion(version)),
+  }
+}
+
+fn read_v1<R: Read>(reader: R) -> Result<Chunk, ChunkFormatError> {
+  let chunk: Chunk =
+    bincode::deserialize_from(ZlibDecoder::new(reader)).map_err(ChunkFormatError::Malformed)?;
+  if !chunk.has_valid_fields() {
+    return Err(malformed("bad field values"));
+  }
+  Ok(chunk)
+}
+
+fn read_v2<R: Read>(reader: R, registry: &BlockRegistry) -> Result<Chunk, ChunkFormatError> {
+  let record: ChunkRecordV2 =
+    bincode::deserialize_from(ZlibDecoder::new(reader)).map_err(ChunkFormatError::Malformed)?;
+  from_record(record, registry)
+}
+
+fn to_record(chunk: &Chunk, registry: &BlockRegistry) -> ChunkRecordV2 {
+  let mut palette = Vec::new();
+  let mut palette_indices: HashMap<BlockType, u16> = HashMap::new();
+  let block_types = (0..CHUNK_WIDTH_E3)
+    .map(|n| {
+      let block_type = chunk
+        .get_block(ChunkPos::new_from_raw_n(n as u16))
+        .block_type;
+      *palette_indices.entry(block_type).or_insert_with(|| {
+        let name = registry.name(block_type).or_else(|| registry.name(UNKNOWN));
+        palette.push(name.expect("unknown is always registered").to_string());
+        (palette.len() - 1) as u16
+      })
+    })
+    .collect();
+
+  let fields = FieldId::all()
+    .filter_map(|field_id| {
+      let values: Vec<u16> = (0..CHUNK_WIDTH_E3)
+        .map(|n| chunk.get_field_raw(ChunkPos::new_from_raw_n(n as u16), field_id))
+        .collect();
+      if values.iter().all(|&value| value == 0) {
+        None
+      } else {
+        Some((field_id.index() as u8, values))
+      }
+    })
+    .collect();
+
+  ChunkRecordV2 {
+    palette,
+    block_types,
+    fields,
+  }
+}
+
+fn from_record(record: ChunkRecordV2, registry: &BlockRegistry) -> Result<Chunk, ChunkFormatError> {
+  if record.block_types.len() != CHUNK_WIDTH_E3 {
+    return Err(malformed("wrong number of block types"));
+  }
+  let palette: Vec<BlockType> = record
+    .palette
+    .iter()
+    .map(|name| registry.lookup(name).unwrap_or(UNKNOWN))
+    .collect();
+
+  let mut chunk = Chunk::with_storage(ChunkStorage::Paletted);
+  let fill = palette.first().cloned().unwrap_or(UNKNOWN);
+  chunk.fill_with_block_type(fill);
+  for (n, &index) in record.block_types.iter().enumerate() {
+    let block_type = *palette
+      .get(index as usize)
+      .ok_or_else(|| malformed("palette index out of range"))?;
+    if block_type != fill {
+      chunk.set_block_type(ChunkPos::new_from_raw_n(n as u16), block_type);
+    }
+  }
+
+  for (id, values) in record.fields {
+    let field_id = match FieldId::new(id) {
+      Some(field_id) if values.len() == CHUNK_WIDTH_E3 => field_id,
+      _ => return Err(malformed("bad field values")),
+    };
+    for (n, value) in values.into_iter().enumerate() {
+      if value != 0 {
+        chunk.set_field_raw(ChunkPos::new_from_raw_n(n as u16), field_id, value);
+      }
+    }
+  }
+
+  Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    block::EMPTY,
+    block_field::MAX_BLOCK_FIELDS,
+    block_registry::BlockMeta,
+    life::{self, life_registry},
+  };
+
+  const V1_FIXTURE: &[u8] = include_bytes!("../fixtures/chunk_v1.bin");
+  const V2_FIXTURE: &[u8] = include_bytes!("../fixtures/chunk_v2.bin");
+
+  // The chunk that both fixtures hold, with the block types of
+  // `life_registry`
+  fn fixture_chunk() -> Chunk {
+    let mut chunk = Chunk::with_storage(ChunkStorage::Paletted);
+    chunk.fill_with_block_type(EMPTY);
+    let life = life::life_block_type(&life_registry());
+    chunk.set_block_type(ChunkPos::new(1, 2, 0), life);
+    chunk.set_block_type(ChunkPos::new(3, 4, 5), UNKNOWN);
+    chunk.set_field_raw(ChunkPos::new(1, 2, 0), FieldId::new(2).unwrap(), 7);
+    chunk
+  }
+
+  fn assert_same_blocks(a: &Chunk, b: &Chunk) {
+    assert!(a.blocks_iter().eq(b.blocks_iter()));
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let registry = life_registry();
+    let chunk = fixture_chunk();
+    let bytes = encode(&chunk, &registry);
+    assert_eq!(&bytes[..4], &MAGIC);
+    assert_same_blocks(&decode(&bytes, &registry).unwrap(), &chunk);
+
+    let flat = Chunk::new();
+    assert_same_blocks(
+      &decode(&encode(&flat, &registry), &registry).unwrap(),
+      &flat,
+    );
+  }
+
+  #[test]
+  fn test_v1_fixture() {
+    let registry = life_registry();
+    let chunk = decode(V1_FIXTURE, &registry).unwrap();
+    assert_same_blocks(&chunk, &fixture_chunk());
+
+    let upgraded = encode(&chunk, &registry);
+    assert_same_blocks(&decode(&upgraded, &registry).unwrap(), &fixture_chunk());
+  }
+
+  #[test]
+  fn test_v2_fixture() {
+    let registry = life_registry();
+    let chunk = decode(V2_FIXTURE, &registry).unwrap();
+    assert_same_blocks(&chunk, &fixture_chunk());
+
+    let rewritten = encode(&chunk, &registry);
+    assert_same_blocks(&decode(&rewritten, &registry).unwrap(), &fixture_chunk());
+  }
+
+  #[test]
+  fn test_block_types_by_name() {
+    let mut registry = BlockRegistry::new();
+    let cobble = registry.register(BlockMeta::new("cobble", "#888", 'C'));
+    let life = registry.register(BlockMeta::new("life", "#00f", 'L'));
+    assert_ne!(life, life::life_block_type(&life_registry()));
+
+    let chunk = decode(V2_FIXTURE, &registry).unwrap();
+    assert_eq!(chunk.get_block(ChunkPos::new(1, 2, 0)).block_type, life);
+    assert!(chunk
+      .blocks_iter()
+      .all(|(_, block)| block.block_type != cobble));
+
+    // Without "life" registered, those blocks come back as unknown
+    let chunk = decode(V2_FIXTURE, &BlockRegistry::new()).unwrap();
+    assert_eq!(chunk.get_block(ChunkPos::new(1, 2, 0)).block_type, UNKNOWN);
+    assert_eq!(chunk.get_block(ChunkPos::new(0, 0, 0)).block_type, EMPTY);
+  }
+
+  #[test]
+  fn test_bad_input() {
+    let registry = life_registry();
+    match decode(b"nope, not a chunk", &registry) {
+      Err(ChunkFormatError::NotAChunk) => (),
+      _ => panic!("expected NotAChunk"),
+    }
+
+    let mut bytes = encode(&fixture_chunk(), &registry);
+    bytes[4] = 3;
+    match decode(&bytes, &registry) {
+      Err(ChunkFormatError::UnknownVersion(3)) => (),
+      _ => panic!("expected UnknownVersion"),
+    }
+
+    assert!(decode(&MAGIC, &registry).is_err());
+    let truncated = encode(&fixture_chunk(), &registry);
+    assert!(decode(&truncated[..truncated.len() / 2], &registry).is_err());
+  }
+
+  #[test]
+  fn test_v1_bad_palette_index() {
+    let mut chunk = Chunk::with_storage(ChunkStorage::Paletted);
+    chunk.fill_with_block_type(EMPTY);
+    chunk.set_block_type(ChunkPos::new(1, 0, 0), UNKNOWN);
+    chunk.set_block_type(ChunkPos::new(2, 0, 0), BlockType(3));
+    let mut record = bincode::serialize(&chunk).unwrap();
+    // Past the storage variant, the 3 entry palette and the index size, the
+    // first word of 2 bit indices, which can't all be in range
+    let first_word = 4 + 8 + 3 * 2 + 1 + 8;
+    for byte in record[first_word..first_word + 8].iter_mut() {
+      *byte = !0;
+    }
+
+    assert_v1_malformed(&record);
+  }
+
+  #[test]
+  fn test_v1_bad_field_values() {
+    let mut chunk = Chunk::with_storage(ChunkStorage::Paletted);
+    chunk.fill_with_block_type(EMPTY);
+    let record = bincode::serialize(&chunk).unwrap();
+    // The record ends with the u64 length of the empty field values
+    let without_fields = &record[..record.len() - 8];
+
+    // One field with only 2 values
+    let mut short_field = without_fields.to_vec();
+    short_field.extend_from_slice(&1u64.to_le_bytes());
+    short_field.extend_from_slice(&2u64.to_le_bytes());
+    short_field.extend_from_slice(&[7, 0, 7, 0]);
+    assert_v1_malformed(&short_field);
+
+    // More fields than there can be, even though they're all empty
+    let mut too_many_fields = without_fields.to_vec();
+    too_many_fields.extend_from_slice(&(MAX_BLOCK_FIELDS as u64 + 1).to_le_bytes());
+    for _ in 0..=MAX_BLOCK_FIELDS {
+      too_many_fields.extend_from_slice(&0u64.to_le_bytes());
+    }
+    assert_v1_malformed(&too_many_fields);
+  }
+
+  // Wraps a bincode `Chunk` as version 1 and checks that it's rejected
+  fn assert_v1_malformed(record: &[u8]) {
+    let mut bytes = 1u16.to_le_bytes().to_vec();
+    let mut encoder = ZlibEncoder::new(&mut bytes, Compression::default());
+    encoder.write_all(record).unwrap();
+    encoder.finish().unwrap();
+    match decode(&bytes, &life_registry()) {
+      Err(ChunkFormatError::Malformed(_)) => (),
+      _ => panic!("expected Malformed"),
+    }
+  }
+}
@@ -1,6 +1,11 @@
+use std::cmp::min;
+
 use roaring::RoaringBitmap;
 
-use crate::{chunk_pos::ChunkPos, relative_pos::RelativePos};
+use crate::{
+  chunk_coord::ChunkCoord, chunk_pos::ChunkPos, query::MAX_NEIGHBORHOOD_DISTANCE,
+  relative_pos::RelativePos, topology::Topology, world_pos::WorldPos,
+};
 
 #[derive(Clone, Debug)]
 pub struct ChunkIndex {
@@ -16,21 +21,98 @@ impl ChunkIndex {
 
   pub fn mark(&mut self, pos: ChunkPos) { self.index.insert(pos.raw_n() as u32); }
 
-  pub fn mark_chebyshev_neighborhood(&mut self, pos: ChunkPos, distance: u8) {
-    let d = distance as i8;
-    for y_offset in -d..=d {
-      for x_offset in -d..=d {
-        for z_offset in -d..=d {
-          let relative_pos = RelativePos::new(x_offset, y_offset, z_offset);
-          match pos.offset(relative_pos) {
-            None => (), // TODO: Propagate to neighboring chunk?
-            Some(offset_pos) => self.mark(offset_pos),
-          }
+  /// Marks the neighborhood of a position in the chunk at `coord`, as laid out
+  /// by the given topology. Positions that belong to other chunks are not
+  /// marked, but are instead returned so they can be routed to their owners.
+  pub fn mark_chebyshev_neighborhood(
+    &mut self,
+    coord: ChunkCoord,
+    pos: ChunkPos,
+    distance: u8,
+    topology: &Topology,
+  ) -> Vec<(ChunkCoord, ChunkPos)> {
+    let d = min(distance, MAX_NEIGHBORHOOD_DISTANCE) as i8;
+    let offsets = (-d..=d).flat_map(move |y_offset| {
+      (-d..=d).flat_map(move |x_offset| {
+        (-d..=d).map(move |z_offset| RelativePos::new(x_offset, y_offset, z_offset))
+      })
+    });
+    self.mark_offsets_from(coord, pos, offsets, topology)
+  }
+
+  /// Like `mark_chebyshev_neighborhood`, but within a Manhattan distance
+  pub fn mark_von_neumann_neighborhood(
+    &mut self,
+    coord: ChunkCoord,
+    pos: ChunkPos,
+    distance: u8,
+    topology: &Topology,
+  ) -> Vec<(ChunkCoord, ChunkPos)> {
+    let d = min(distance, MAX_NEIGHBORHOOD_DISTANCE) as i8;
+    let offsets = (-d..=d).flat_map(move |y_offset| {
+      (-d..=d).flat_map(move |x_offset| {
+        (-d..=d)
+          .filter(move |z_offset| {
+            i16::from(x_offset.abs()) + i16::from(y_offset.abs()) + i16::from(z_offset.abs())
+              <= i16::from(d)
+          })
+          .map(move |z_offset| RelativePos::new(x_offset, y_offset, z_offset))
+      })
+    });
+    self.mark_offsets_from(coord, pos, offsets, topology)
+  }
+
+  /// Marks every position that reads `pos` through one of the offsets, i.e.
+  /// `pos - offset`. Otherwise like `mark_chebyshev_neighborhood`.
+  pub fn mark_offsets(
+    &mut self,
+    coord: ChunkCoord,
+    pos: ChunkPos,
+    offsets: &[RelativePos],
+    topology: &Topology,
+  ) -> Vec<(ChunkCoord, ChunkPos)> {
+    let reversed = offsets.iter().filter_map(|offset| {
+      Some(RelativePos::new(
+        offset.x.checked_neg()?,
+        offset.y.checked_neg()?,
+        offset.z.checked_neg()?,
+      ))
+    });
+    self.mark_offsets_from(coord, pos, reversed, topology)
+  }
+
+  fn mark_offsets_from<I: Iterator<Item = RelativePos>>(
+    &mut self,
+    coord: ChunkCoord,
+    pos: ChunkPos,
+    offsets: I,
+    topology: &Topology,
+  ) -> Vec<(ChunkCoord, ChunkPos)> {
+    let mut spilled = Vec::new();
+    let origin = WorldPos::from_parts(coord, pos);
+    for relative_pos in offsets {
+      if let Some(target) = topology.resolve(origin.offset(relative_pos)) {
+        let (target_coord, target_pos) = target.split();
+        if target_coord == coord {
+          self.mark(target_pos);
+        } else {
+          spilled.push((target_coord, target_pos));
         }
       }
     }
+    spilled
   }
 
+  pub fn unmark(&mut self, pos: ChunkPos) { self.index.remove(pos.raw_n() as u32); }
+
+  /// Marks everything that is marked in `other`
+  pub fn union_with(&mut self, other: &ChunkIndex) { self.index |= &other.index; }
+
+  /// Unmarks everything that isn't marked in `other`
+  pub fn intersect_with(&mut self, other: &ChunkIndex) { self.index &= &other.index; }
+
+  pub fn is_empty(&self) -> bool { self.index.is_empty() }
+
   pub fn consider(&self, pos: ChunkPos) -> bool { self.index.contains(pos.raw_n() as u32) }
 
   pub fn clear(&mut self) { self.index.clear(); }
@@ -50,6 +132,7 @@ impl Default for ChunkIndex {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::chunk::CHUNK_WIDTH_E3;
 
   #[test]
   fn test_consider() {
@@ -104,7 +187,13 @@ mod tests {
   fn test_mark_chebyshev() {
     let mut index = ChunkIndex::new();
 
-    index.mark_chebyshev_neighborhood(ChunkPos::new(5, 5, 5), 2);
+    let spilled = index.mark_chebyshev_neighborhood(
+      ChunkCoord::origin(),
+      ChunkPos::new(5, 5, 5),
+      2,
+      &Topology::Infinite,
+    );
+    assert!(spilled.is_empty());
 
     assert_eq!(index.consider(ChunkPos::new(5, 5, 5)), true);
     assert_eq!(index.consider(ChunkPos::new(4, 4, 5)), true);
@@ -120,4 +209,135 @@ mod tests {
     assert_eq!(index.consider(ChunkPos::new(5, 8, 5)), false);
     assert_eq!(index.consider(ChunkPos::new(7, 7, 8)), false);
   }
+
+  #[test]
+  fn test_mark_chebyshev_spilled() {
+    let mut index = ChunkIndex::new();
+
+    let spilled = index.mark_chebyshev_neighborhood(
+      ChunkCoord::new(3, 0, 0),
+      ChunkPos::new(0, 5, 31),
+      1,
+      &Topology::Infinite,
+    );
+
+    assert_eq!(index.consider(ChunkPos::new(0, 5, 31)), true);
+    assert_eq!(index.consider(ChunkPos::new(1, 4, 30)), true);
+    assert_eq!(index.consider(ChunkPos::new(31, 5, 31)), false);
+    assert_eq!(index.consider(ChunkPos::new(0, 5, 0)), false);
+
+    // 27 positions in the neighborhood, 12 of which are within this chunk
+    assert_eq!(spilled.len(), 27 - 12);
+    assert!(spilled.contains(&(ChunkCoord::new(2, 0, 0), ChunkPos::new(31, 5, 31))));
+    assert!(spilled.contains(&(ChunkCoord::new(3, 0, 1), ChunkPos::new(0, 5, 0))));
+    assert!(spilled.contains(&(ChunkCoord::new(2, 0, 1), ChunkPos::new(31, 4, 0))));
+  }
+
+  #[test]
+  fn test_mark_chebyshev_bounded() {
+    let mut index = ChunkIndex::new();
+    let topology = Topology::bounded(WorldPos::new(0, 0, 0), WorldPos::new(32, 32, 32));
+
+    let spilled =
+      index.mark_chebyshev_neighborhood(ChunkCoord::origin(), ChunkPos::new(0, 0, 0), 1, &topology);
+
+    assert!(spilled.is_empty());
+    assert_eq!(index.iter().count(), 8);
+  }
+
+  #[test]
+  fn test_mark_chebyshev_clamps_distance() {
+    let mut index = ChunkIndex::new();
+    let topology = Topology::bounded(WorldPos::new(0, 0, 0), WorldPos::new(32, 32, 32));
+
+    // Far enough to reach the opposite corner of the chunk, but no further
+    let spilled = index.mark_chebyshev_neighborhood(
+      ChunkCoord::origin(),
+      ChunkPos::new(0, 0, 0),
+      200,
+      &topology,
+    );
+
+    assert!(spilled.is_empty());
+    assert_eq!(index.iter().count(), CHUNK_WIDTH_E3);
+  }
+
+  #[test]
+  fn test_mark_chebyshev_toroidal() {
+    let mut index = ChunkIndex::new();
+    let topology = Topology::torus_2d(10, 10);
+
+    let spilled =
+      index.mark_chebyshev_neighborhood(ChunkCoord::origin(), ChunkPos::new(9, 9, 0), 1, &topology);
+
+    assert!(spilled.is_empty());
+    assert_eq!(index.iter().count(), 9);
+    assert_eq!(index.consider(ChunkPos::new(9, 9, 0)), true);
+    assert_eq!(index.consider(ChunkPos::new(0, 0, 0)), true);
+    assert_eq!(index.consider(ChunkPos::new(8, 0, 0)), true);
+    assert_eq!(index.consider(ChunkPos::new(10, 10, 0)), false);
+    assert_eq!(index.consider(ChunkPos::new(9, 9, 1)), false);
+  }
+
+  #[test]
+  fn test_mark_von_neumann() {
+    let mut index = ChunkIndex::new();
+
+    let spilled = index.mark_von_neumann_neighborhood(
+      ChunkCoord::origin(),
+      ChunkPos::new(5, 5, 5),
+      2,
+      &Topology::Infinite,
+    );
+    assert!(spilled.is_empty());
+
+    assert_eq!(index.iter().count(), 25);
+    assert_eq!(index.consider(ChunkPos::new(5, 5, 5)), true);
+    assert_eq!(index.consider(ChunkPos::new(7, 5, 5)), true);
+    assert_eq!(index.consider(ChunkPos::new(4, 6, 5)), true);
+    assert_eq!(index.consider(ChunkPos::new(5, 4, 4)), true);
+    assert_eq!(index.consider(ChunkPos::new(6, 6, 6)), false);
+    assert_eq!(index.consider(ChunkPos::new(7, 6, 5)), false);
+
+    let spilled = index.mark_von_neumann_neighborhood(
+      ChunkCoord::origin(),
+      ChunkPos::new(0, 10, 10),
+      1,
+      &Topology::Infinite,
+    );
+    assert_eq!(
+      spilled,
+      vec![(ChunkCoord::new(-1, 0, 0), ChunkPos::new(31, 10, 10))]
+    );
+  }
+
+  #[test]
+  fn test_mark_offsets() {
+    let mut index = ChunkIndex::new();
+    // Something that reads the block below it
+    let below = [RelativePos::new(0, 0, -1)];
+
+    let spilled = index.mark_offsets(
+      ChunkCoord::origin(),
+      ChunkPos::new(5, 5, 5),
+      &below,
+      &Topology::Infinite,
+    );
+    assert!(spilled.is_empty());
+    assert_eq!(
+      index.iter().collect::<Vec<_>>(),
+      vec![ChunkPos::new(5, 5, 6)]
+    );
+
+    let spilled = index.mark_offsets(
+      ChunkCoord::origin(),
+      ChunkPos::new(5, 5, 31),
+      &below,
+      &Topology::Infinite,
+    );
+    assert_eq!(
+      spilled,
+      vec![(ChunkCoord::new(0, 0, 1), ChunkPos::new(5, 5, 0))]
+    );
+  }
 }
@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+
+use crate::{
+  block::BlockType,
+  block_field::{BlockField, FieldId, FieldValue},
+  chunk::Chunk,
+  chunk_coord::ChunkCoord,
+  chunk_pos::ChunkPos,
+  loaded_chunk::{LoadedChunk, SpilledMark},
+  mutation::{BlockChange, BlockWrite},
+  query::BlockInfo,
+  topology::Topology,
+  world_pos::WorldPos,
+};
+
+// Chunks are boxed because they are large, and BTreeMap nodes store their
+// values inline
+#[derive(Clone, Default)]
+pub struct ChunkMap {
+  chunks: BTreeMap<ChunkCoord, Box<LoadedChunk>>,
+  topology: Topology,
+}
+
+impl ChunkMap {
+  pub fn new() -> ChunkMap {
+    ChunkMap {
+      chunks: BTreeMap::new(),
+      topology: Topology::Infinite,
+    }
+  }
+
+  pub fn with_topology(topology: Topology) -> ChunkMap {
+    ChunkMap {
+      chunks: BTreeMap::new(),
+      topology,
+    }
+  }
+
+  pub fn topology(&self) -> &Topology { &self.topology }
+
+  pub fn load(&mut self, coord: ChunkCoord, chunk: Chunk) -> Option<LoadedChunk> {
+    self
+      .chunks
+      .insert(coord, Box::new(LoadedChunk::new(coord, chunk)))
+      .map(|loaded_chunk| *loaded_chunk)
+  }
+
+  pub fn unload(&mut self, coord: ChunkCoord) -> Option<LoadedChunk> {
+    self.chunks.remove(&coord).map(|loaded_chunk| *loaded_chunk)
+  }
+
+  pub fn is_loaded(&self, coord: ChunkCoord) -> bool { self.chunks.contains_key(&coord) }
+
+  pub fn len(&self) -> usize { self.chunks.len() }
+
+  pub fn is_empty(&self) -> bool { self.chunks.is_empty() }
+
+  pub fn get(&self, coord: ChunkCoord) -> Option<&LoadedChunk> {
+    self.chunks.get(&coord).map(|loaded_chunk| &**loaded_chunk)
+  }
+
+  pub fn get_mut(&mut self, coord: ChunkCoord) -> Option<&mut LoadedChunk> {
+    self
+      .chunks
+      .get_mut(&coord)
+      .map(|loaded_chunk| &mut **loaded_chunk)
+  }
+
+  pub fn iter<'a>(&'a self) -> impl Iterator<Item = (ChunkCoord, &'a LoadedChunk)> + 'a {
+    self
+      .chunks
+      .iter()
+      .map(|(&coord, loaded_chunk)| (coord, &**loaded_chunk))
+  }
+
+  pub fn iter_mut<'a>(
+    &'a mut self,
+  ) -> impl Iterator<Item = (ChunkCoord, &'a mut LoadedChunk)> + 'a {
+    self
+      .chunks
+      .iter_mut()
+      .map(|(&coord, loaded_chunk)| (coord, &mut **loaded_chunk))
+  }
+
+  /// Returns None if the position is outside of the world or not in a loaded
+  /// chunk.
+  pub fn get_block(&self, pos: WorldPos) -> Option<BlockInfo> {
+    let (coord, chunk_pos) = self.topology.resolve(pos)?.split();
+    self
+      .get(coord)
+      .map(|loaded_chunk| loaded_chunk.get().get_block(chunk_pos))
+  }
+
+  /// Returns false if the position is outside of the world or not in a loaded
+  /// chunk.
+  pub fn set_block_type(&mut self, pos: WorldPos, block_type: BlockType) -> bool {
+    self.modify(pos, false, |loaded_chunk, chunk_pos, topology| {
+      loaded_chunk.set_block_type(chunk_pos, block_type, topology)
+    })
+  }
+
+  /// Returns false if the position is outside of the world or not in a loaded
+  /// chunk.
+  pub fn set_field_raw(&mut self, pos: WorldPos, field_id: FieldId, value: u16) -> bool {
+    self.modify(pos, false, |loaded_chunk, chunk_pos, topology| {
+      loaded_chunk.set_field_raw(chunk_pos, field_id, value, topology)
+    })
+  }
+
+  pub fn set_field<T: FieldValue>(
+    &mut self,
+    pos: WorldPos,
+    field: BlockField<T>,
+    value: T,
+  ) -> bool {
+    self.set_field_raw(pos, field.id(), value.to_raw())
+  }
+
+  /// Applies a write from a simulator step. With `wrap_around`, blocks at the
+  /// edge of a chunk read the opposite edge of their own chunk instead of an
+  /// unloaded neighbor, so that is where their cache busters get marked.
+  pub(crate) fn apply_write(&mut self, write: &BlockWrite, wrap_around: bool) -> bool {
+    match write.change {
+      BlockChange::BlockType(block_type) => self.modify(
+        write.pos,
+        wrap_around,
+        |loaded_chunk, chunk_pos, topology| {
+          loaded_chunk.set_block_type(chunk_pos, block_type, topology)
+        },
+      ),
+      BlockChange::Field(field_id, value) => self.modify(
+        write.pos,
+        wrap_around,
+        |loaded_chunk, chunk_pos, topology| {
+          loaded_chunk.set_field_raw(chunk_pos, field_id, value, topology)
+        },
+      ),
+    }
+  }
+
+  fn modify<F>(&mut self, pos: WorldPos, wrap_around: bool, modify_fn: F) -> bool
+  where
+    F: FnOnce(&mut LoadedChunk, ChunkPos, &Topology) -> Vec<SpilledMark>,
+  {
+    let (coord, chunk_pos) = match self.topology.resolve(pos) {
+      None => return false,
+      Some(resolved) => resolved.split(),
+    };
+    let topology = self.topology;
+    match self.get_mut(coord) {
+      None => false,
+      Some(loaded_chunk) => {
+        for mark in modify_fn(loaded_chunk, chunk_pos, &topology) {
+          let target = if wrap_around && !self.is_loaded(mark.coord) {
+            coord
+          } else {
+            mark.coord
+          };
+          if let Some(other) = self.get_mut(target) {
+            other.mark_cache_buster(&mark.cacheability, mark.pos);
+          }
+        }
+        true
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    block::EMPTY,
+    chunk::CHUNK_WIDTH_E3,
+    query::{Cacheability, CacheabilitySet, CacheableField},
+  };
+
+  const COBBLE: BlockType = BlockType(37);
+
+  #[test]
+  fn test_load_and_unload() {
+    let mut map = ChunkMap::new();
+    assert!(map.is_empty());
+
+    map.load(ChunkCoord::new(0, 0, 0), Chunk::new());
+    map.load(ChunkCoord::new(-1, 0, 2), Chunk::new());
+    assert_eq!(map.len(), 2);
+    assert!(map.is_loaded(ChunkCoord::new(-1, 0, 2)));
+    assert!(!map.is_loaded(ChunkCoord::new(1, 0, 0)));
+
+    assert!(map.unload(ChunkCoord::new(-1, 0, 2)).is_some());
+    assert!(map.unload(ChunkCoord::new(-1, 0, 2)).is_none());
+    assert_eq!(map.len(), 1);
+  }
+
+  #[test]
+  fn test_get_and_set_block() {
+    let mut map = ChunkMap::new();
+    let mut chunk = Chunk::new();
+    chunk.fill_with_block_type(EMPTY);
+    map.load(ChunkCoord::new(-1, 0, 0), chunk);
+
+    let pos = WorldPos::new(-2, 3, 4);
+    assert_eq!(map.get_block(pos).unwrap().block_type(), EMPTY);
+    assert!(map.set_block_type(pos, COBBLE));
+    assert_eq!(map.get_block(pos).unwrap().block_type(), COBBLE);
+    assert_eq!(
+      map
+        .get(ChunkCoord::new(-1, 0, 0))
+        .unwrap()
+        .get()
+        .get_block(pos.chunk_pos())
+        .block_type(),
+      COBBLE
+    );
+
+    let unloaded_pos = WorldPos::new(2, 3, 4);
+    assert!(map.get_block(unloaded_pos).is_none());
+    assert!(!map.set_block_type(unloaded_pos, COBBLE));
+  }
+
+  #[test]
+  fn test_set_block_type_marks_neighbor_chunks() {
+    let west = ChunkCoord::new(-1, 0, 0);
+    let east = ChunkCoord::new(0, 0, 0);
+    let cacheability = Cacheability::UntilChangeInChebyshevNeighborhood {
+      fields: vec![CacheableField::CacheableBlockType],
+      distance: 1,
+    };
+
+    let mut map = ChunkMap::new();
+    map.load(west, Chunk::new());
+    map.load(east, Chunk::new());
+    for (_, loaded_chunk) in map.iter_mut() {
+      loaded_chunk.reset_cache_busters(vec![cacheability.clone()].iter());
+    }
+
+    map.set_block_type(WorldPos::new(-1, 5, 5), COBBLE);
+
+    let east_considerables: Vec<ChunkPos> = map
+      .get(east)
+      .unwrap()
+      .considerable_blocks_iter(&cacheability.clone().into())
+      .map(|(pos, _)| pos)
+      .collect();
+    assert_eq!(east_considerables.len(), 9);
+    assert!(east_considerables.contains(&ChunkPos::new(0, 4, 4)));
+    assert!(east_considerables.contains(&ChunkPos::new(0, 6, 6)));
+
+    let west_considerables: Vec<ChunkPos> = map
+      .get(west)
+      .unwrap()
+      .considerable_blocks_iter(&cacheability.clone().into())
+      .map(|(pos, _)| pos)
+      .collect();
+    assert_eq!(west_considerables.len(), 18);
+  }
+
+  #[test]
+  fn test_set_field_marks_only_caches_reading_that_field() {
+    const AGE: BlockField<u8> = BlockField::new(0);
+    const CHARGE: BlockField<i16> = BlockField::new(1);
+    let coord = ChunkCoord::origin();
+    let reads_age = Cacheability::UntilChangeInSelf {
+      fields: vec![CacheableField::CacheableBlockField(AGE.id())],
+    };
+    let reads_block_type = Cacheability::UntilChangeInChebyshevNeighborhood {
+      fields: vec![CacheableField::CacheableBlockType],
+      distance: 1,
+    };
+
+    let mut map = ChunkMap::new();
+    map.load(coord, Chunk::new());
+    map
+      .get_mut(coord)
+      .unwrap()
+      .reset_cache_busters(vec![reads_age.clone(), reads_block_type.clone()].iter());
+
+    assert!(map.set_field(WorldPos::new(3, 3, 3), AGE, 12));
+    assert!(map.set_field(WorldPos::new(5, 5, 5), CHARGE, -3));
+    assert_eq!(
+      map.get_block(WorldPos::new(3, 3, 3)).unwrap().field(AGE),
+      12
+    );
+
+    let loaded_chunk = map.get(coord).unwrap();
+    let considerables: Vec<ChunkPos> = loaded_chunk
+      .considerable_blocks_iter(&reads_age.clone().into())
+      .map(|(pos, _)| pos)
+      .collect();
+    assert_eq!(considerables, vec![ChunkPos::new(3, 3, 3)]);
+    assert_eq!(
+      loaded_chunk
+        .considerable_blocks_iter(&reads_block_type.clone().into())
+        .count(),
+      0
+    );
+  }
+
+  #[test]
+  fn test_considerable_targets_only_include_target_type() {
+    let coord = ChunkCoord::origin();
+    let cacheability: CacheabilitySet = Cacheability::UntilChangeInChebyshevNeighborhood {
+      fields: vec![CacheableField::CacheableBlockType],
+      distance: 1,
+    }
+    .into();
+    let targets = |map: &ChunkMap, target| -> Vec<ChunkPos> {
+      map
+        .get(coord)
+        .unwrap()
+        .considerable_targets_iter(&cacheability, target)
+        .map(|(pos, _)| pos)
+        .collect()
+    };
+
+    let mut chunk = Chunk::new();
+    chunk.fill_with_block_type(EMPTY);
+    chunk.set_block_type(ChunkPos::new(1, 1, 1), COBBLE);
+    let mut map = ChunkMap::new();
+    map.load(coord, chunk);
+
+    // Before the first full pass, every block of the type is considered
+    assert_eq!(targets(&map, COBBLE), vec![ChunkPos::new(1, 1, 1)]);
+    assert_eq!(targets(&map, EMPTY).len(), CHUNK_WIDTH_E3 - 1);
+
+    let cacheabilities: Vec<Cacheability> = cacheability.iter().cloned().collect();
+    map
+      .get_mut(coord)
+      .unwrap()
+      .reset_cache_busters(cacheabilities.iter());
+    assert!(targets(&map, COBBLE).is_empty());
+
+    // The neighborhood of the change is marked, but only the cobble in it is
+    // a target
+    map.set_block_type(WorldPos::new(5, 5, 5), COBBLE);
+    map.set_block_type(WorldPos::new(5, 6, 5), COBBLE);
+    map.set_block_type(WorldPos::new(1, 1, 1), EMPTY);
+    assert_eq!(
+      targets(&map, COBBLE),
+      vec![ChunkPos::new(5, 5, 5), ChunkPos::new(5, 6, 5)]
+    );
+    assert_eq!(targets(&map, EMPTY).len(), 27 + 36 - 2);
+  }
+
+  #[test]
+  fn test_toroidal_get_and_set_block() {
+    let mut map = ChunkMap::with_topology(Topology::torus_2d(40, 20));
+    map.load(ChunkCoord::new(0, 0, 0), Chunk::new());
+    map.load(ChunkCoord::new(1, 0, 0), Chunk::new());
+
+    assert!(map.set_block_type(WorldPos::new(-1, -1, 0), COBBLE));
+    assert_eq!(
+      map
+        .get_block(WorldPos::new(39, 39, 0))
+        .unwrap()
+        .block_type(),
+      COBBLE
+    );
+    assert_eq!(
+      map
+        .get_block(WorldPos::new(79, -41, 0))
+        .unwrap()
+        .block_type(),
+      COBBLE
+    );
+
+    assert!(map.get_block(WorldPos::new(5, 5, 1)).is_none());
+    assert!(!map.set_block_type(WorldPos::new(5, 5, 1), COBBLE));
+  }
+
+  #[test]
+  fn test_iteration_order() {
+    let mut map = ChunkMap::new();
+    map.load(ChunkCoord::new(1, 0, 0), Chunk::new());
+    map.load(ChunkCoord::new(-1, 0, 0), Chunk::new());
+    map.load(ChunkCoord::new(0, 5, 0), Chunk::new());
+
+    let coords: Vec<ChunkCoord> = map.iter().map(|(coord, _)| coord).collect();
+    assert_eq!(
+      coords,
+      vec![
+        ChunkCoord::new(-1, 0, 0),
+        ChunkCoord::new(0, 5, 0),
+        ChunkCoord::new(1, 0, 0)
+      ]
+    );
+  }
+}
@@ -1,9 +1,11 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{
   chunk::{CHUNK_WIDTH, CHUNK_WIDTH_E2, CHUNK_WIDTH_E3},
   relative_pos::RelativePos,
 };
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct ChunkPos {
   n: u16,
 }
@@ -1,21 +1,33 @@
-use std::rc::Rc;
+use std::{
+  cell::{Cell, RefCell},
+  collections::HashMap,
+  rc::Rc,
+};
 
 use js_sys::{ArrayBuffer, JsString, Uint8Array};
 use wasm_bindgen::{prelude::*, JsCast};
 use web_sys::{
-  BinaryType, CanvasRenderingContext2d, HtmlCanvasElement, MessageEvent, Url, WebSocket,
+  BinaryType, CanvasRenderingContext2d, Document, Event, EventTarget, HtmlCanvasElement,
+  HtmlSelectElement, MessageEvent, MouseEvent, TouchEvent, Url, WebSocket,
 };
 
 use crate::{
-  block::{EMPTY, UNKNOWN},
-  chunk::{Chunk, CHUNK_WIDTH},
-  life::LIFE,
+  block::{BlockType, EMPTY, UNKNOWN},
+  block_registry::BlockRegistry,
+  chunk::{Chunk, ChunkStorage, CHUNK_WIDTH},
+  chunk_coord::ChunkCoord,
+  chunk_pos::ChunkPos,
+  protocol::{
+    self, ChunkRegion, ClientCommand, SeqCheck, SeqTracker, ServerEvent, SimControl, SimState,
+  },
+  world_pos::WorldPos,
 };
 
 #[wasm_bindgen]
 pub struct LotsaClientWrapper {
   client: Rc<LotsaClient>,
   _rx_closure: Closure<dyn Fn(MessageEvent)>,
+  _input_closures: Vec<Closure<dyn Fn(Event)>>,
 }
 
 #[wasm_bindgen]
@@ -41,24 +53,200 @@ impl LotsaClientWrapper {
         .expect("closure can always be ref"),
     ));
 
+    let input_closures = listen_for_input(&client);
+
     LotsaClientWrapper {
       client,
       _rx_closure: rx_closure,
+      _input_closures: input_closures,
     }
   }
 
-  pub fn send_message(&self, data: &str) { self.client.ws.send_with_str(data).unwrap(); }
+  pub fn set_block(&self, x: i32, y: i32, z: i32, block_type: u16) {
+    self.client.send(ClientCommand::SetBlock {
+      pos: WorldPos::new(x, y, z),
+      block_type: BlockType(block_type),
+    });
+  }
+
+  pub fn pause(&self) { self.client.control(SimControl::Pause); }
+
+  pub fn resume(&self) { self.client.control(SimControl::Resume); }
+
+  pub fn step(&self, ticks: u32) { self.client.control(SimControl::Step { ticks }); }
+
+  pub fn set_tick_interval(&self, millis: u32) {
+    self.client.control(SimControl::SetTickInterval { millis });
+  }
+
+  /// As of the last state the server sent
+  pub fn paused(&self) -> bool { self.client.sim_state.get().paused }
+
+  pub fn subscribe(&self, min_x: i32, min_y: i32, min_z: i32, max_x: i32, max_y: i32, max_z: i32) {
+    self.client.send(ClientCommand::Subscribe(ChunkRegion {
+      min: ChunkCoord::new(min_x, min_y, min_z),
+      max: ChunkCoord::new(max_x, max_y, max_z),
+    }));
+  }
+
+  pub fn ping(&self, nonce: u32) { self.client.send(ClientCommand::Ping { nonce }); }
+}
+
+fn listen_for_input(client: &Rc<LotsaClient>) -> Vec<Closure<dyn Fn(Event)>> {
+  let mut closures = Vec::new();
+  {
+    let mut listen = |target: &EventTarget, event_name: &str, handler: fn(&LotsaClient, Event)| {
+      let client = client.clone();
+      let closure =
+        Closure::wrap(Box::new(move |event: Event| handler(&client, event)) as Box<dyn Fn(Event)>);
+      target
+        .add_event_listener_with_callback(event_name, closure.as_ref().unchecked_ref())
+        .expect("add event listener");
+      closures.push(closure);
+    };
+
+    let canvas: &EventTarget = client.canvas.as_ref();
+    listen(canvas, "mousedown", LotsaClient::handle_pointer_down);
+    listen(canvas, "mousemove", LotsaClient::handle_pointer_move);
+    listen(canvas, "mouseup", LotsaClient::handle_pointer_up);
+    listen(canvas, "mouseleave", LotsaClient::handle_pointer_up);
+    listen(canvas, "touchstart", LotsaClient::handle_pointer_down);
+    listen(canvas, "touchmove", LotsaClient::handle_pointer_move);
+    listen(canvas, "touchend", LotsaClient::handle_pointer_up);
+    listen(
+      client.block_picker.as_ref(),
+      "change",
+      LotsaClient::handle_block_picked,
+    );
+  }
+  closures
 }
 
 struct LotsaClient {
+  /// Only has the built-in block types until the server sends the game's
+  registry: RefCell<BlockRegistry>,
+  chunks: RefCell<HashMap<ChunkCoord, Chunk>>,
+  seq_tracker: RefCell<SeqTracker>,
   ws: WebSocket,
+  canvas: HtmlCanvasElement,
   canvas_ctx: CanvasRenderingContext2d,
   canvas_width: u32,
   canvas_height: u32,
+  layout: CanvasLayout,
+  block_picker: HtmlSelectElement,
+  paint_block_type: Cell<BlockType>,
+  painting: Cell<bool>,
+  last_painted: Cell<Option<ChunkPos>>,
+  sim_state: Cell<SimState>,
 }
 
 const GRID: f64 = 2.0;
 
+/// Where each block is drawn on the canvas
+#[derive(Clone, Copy)]
+struct CanvasLayout {
+  cell_width: f64,
+  cell_height: f64,
+}
+
+impl CanvasLayout {
+  fn new(canvas_width: u32, canvas_height: u32) -> CanvasLayout {
+    CanvasLayout {
+      cell_width: (canvas_width as f64 - GRID) / (CHUNK_WIDTH as f64) - GRID,
+      cell_height: (canvas_height as f64 - GRID) / (CHUNK_WIDTH as f64) - GRID,
+    }
+  }
+
+  fn cell_origin(&self, pos: ChunkPos) -> (f64, f64) {
+    (
+      GRID + (pos.x() as f64) * (self.cell_width + GRID),
+      GRID + (pos.y() as f64) * (self.cell_height + GRID),
+    )
+  }
+
+  /// The block drawn at the given pixel on the z = 0 layer. The grid lines
+  /// above and to the left of a cell count as part of it.
+  fn pos_at(&self, x: f64, y: f64) -> Option<ChunkPos> {
+    let cell_x = (x / (self.cell_width + GRID)).floor();
+    let cell_y = (y / (self.cell_height + GRID)).floor();
+    let width = CHUNK_WIDTH as f64;
+    if cell_x >= 0.0 && cell_x < width && cell_y >= 0.0 && cell_y < width {
+      Some(ChunkPos::new(cell_x as u8, cell_y as u8, 0))
+    } else {
+      None
+    }
+  }
+}
+
+/// Where the pointers are in an input event, in client coordinates
+fn pointer_positions(event: &Event) -> Vec<(f64, f64)> {
+  if let Some(event) = event.dyn_ref::<TouchEvent>() {
+    let touches = event.changed_touches();
+    (0..touches.length())
+      .filter_map(|i| touches.get(i))
+      .map(|touch| (touch.client_x() as f64, touch.client_y() as f64))
+      .collect()
+  } else if let Some(event) = event.dyn_ref::<MouseEvent>() {
+    vec![(event.client_x() as f64, event.client_y() as f64)]
+  } else {
+    Vec::new()
+  }
+}
+
+/// The first block type the game registered, to start off painting with
+fn first_game_block_type(registry: &BlockRegistry) -> BlockType {
+  registry
+    .iter()
+    .map(|(bt, _)| bt)
+    .find(|&bt| bt != UNKNOWN && bt != EMPTY)
+    .unwrap_or(EMPTY)
+}
+
+fn create_block_picker(
+  document: &Document,
+  canvas: &HtmlCanvasElement,
+  registry: &BlockRegistry,
+  selected: BlockType,
+) -> HtmlSelectElement {
+  let picker = document
+    .create_element("select")
+    .expect("create select element")
+    .dyn_into::<HtmlSelectElement>()
+    .expect("select element must be a select");
+  picker.set_id("block-picker");
+  fill_block_picker(document, &picker, registry, selected);
+
+  document
+    .body()
+    .expect("dom document must have body")
+    .insert_before(&picker, Some(canvas))
+    .expect("add block picker to page");
+  picker
+}
+
+/// Replaces the picker's options with the registry's block types
+fn fill_block_picker(
+  document: &Document,
+  picker: &HtmlSelectElement,
+  registry: &BlockRegistry,
+  selected: BlockType,
+) {
+  picker.set_length(0);
+  for (block_type, meta) in registry.iter().filter(|&(bt, _)| bt != UNKNOWN) {
+    let option = document
+      .create_element("option")
+      .expect("create option element");
+    option
+      .set_attribute("value", &block_type.0.to_string())
+      .expect("set option value");
+    option.set_text_content(Some(&meta.name));
+    picker
+      .append_child(&option)
+      .expect("add option to block picker");
+  }
+  picker.set_value(&selected.0.to_string());
+}
+
 impl LotsaClient {
   pub fn new() -> LotsaClient {
     let window = web_sys::window().expect("dom window must exist");
@@ -94,11 +282,25 @@ impl LotsaClient {
       .dyn_into::<CanvasRenderingContext2d>()
       .expect("get_context on canvas must return a canvas context");
 
+    let registry = BlockRegistry::new();
+    let paint_block_type = first_game_block_type(&registry);
+    let block_picker = create_block_picker(&document, &canvas, &registry, paint_block_type);
+
     LotsaClient {
+      registry: RefCell::new(registry),
+      chunks: RefCell::new(HashMap::new()),
+      seq_tracker: RefCell::new(SeqTracker::new()),
       ws,
+      canvas,
       canvas_ctx,
       canvas_width,
       canvas_height,
+      layout: CanvasLayout::new(canvas_width, canvas_height),
+      block_picker,
+      paint_block_type: Cell::new(paint_block_type),
+      painting: Cell::new(false),
+      last_painted: Cell::new(None),
+      sim_state: Cell::new(SimState::new()),
     }
   }
 
@@ -108,11 +310,113 @@ impl LotsaClient {
     let mut buf: Vec<u8> = vec![0; js_a.length() as usize];
     js_a.copy_to(&mut buf[..]);
 
-    let decoder = flate2::read::ZlibDecoder::new(&buf[..]);
-    let chunk: Chunk =
-      bincode::deserialize_from(decoder).expect("message is valid gzipped bincode Chunk"); // TODO
+    let update = match ServerEvent::decode(&buf[..]) {
+      Ok(ServerEvent::Registry(registry)) => {
+        info!("got {} block types", registry.len());
+        self.set_registry(registry);
+        return;
+      },
+      Ok(ServerEvent::Chunk(update)) => update,
+      Ok(ServerEvent::Pong { nonce }) => {
+        info!("pong {}", nonce);
+        return;
+      },
+      Ok(ServerEvent::SimState(state)) => {
+        info!("sim state {:?}", state);
+        self.sim_state.set(state);
+        return;
+      },
+      Err(err) => {
+        warn!("ignoring bad server message: {}", err);
+        return;
+      },
+    };
+
+    let coord = update.coord();
+    let check = self.seq_tracker.borrow_mut().check(&update);
+    match check {
+      SeqCheck::Apply => {
+        let mut chunks = self.chunks.borrow_mut();
+        let chunk = chunks
+          .entry(coord)
+          .or_insert_with(|| Chunk::with_storage(ChunkStorage::Paletted));
+        if let Err(err) = protocol::apply(update, chunk, &self.registry.borrow()) {
+          warn!("ignoring bad chunk update for {:?}: {}", coord, err);
+          return;
+        }
+        if coord == ChunkCoord::origin() {
+          self.draw(chunk);
+        }
+      },
+      SeqCheck::Ignore => (),
+      SeqCheck::Resync => {
+        warn!("missed an update to chunk {:?}, resyncing", coord);
+        self.send(ClientCommand::Resync(coord));
+      },
+    }
+  }
+
+  fn set_registry(&self, registry: BlockRegistry) {
+    let paint_block_type = first_game_block_type(&registry);
+    let document = web_sys::window()
+      .expect("dom window must exist")
+      .document()
+      .expect("dom document must exist");
+    fill_block_picker(&document, &self.block_picker, &registry, paint_block_type);
+    self.paint_block_type.set(paint_block_type);
+    *self.registry.borrow_mut() = registry;
+  }
+
+  fn handle_pointer_down(&self, event: Event) {
+    self.painting.set(true);
+    self.last_painted.set(None);
+    self.handle_pointer_move(event);
+  }
+
+  fn handle_pointer_move(&self, event: Event) {
+    if !self.painting.get() {
+      return;
+    }
+    // Keeps touches from scrolling the page
+    event.prevent_default();
 
-    self.draw(&chunk);
+    let rect = self.canvas.get_bounding_client_rect();
+    for (x, y) in pointer_positions(&event) {
+      if let Some(pos) = self.layout.pos_at(x - rect.left(), y - rect.top()) {
+        self.paint(pos);
+      }
+    }
+  }
+
+  fn handle_pointer_up(&self, _event: Event) { self.painting.set(false); }
+
+  fn handle_block_picked(&self, _event: Event) {
+    match self.block_picker.value().parse::<u16>() {
+      Ok(id) => self.paint_block_type.set(BlockType(id)),
+      Err(_) => warn!("bad block picker value {}", self.block_picker.value()),
+    }
+  }
+
+  fn paint(&self, pos: ChunkPos) {
+    // Dragging within a cell shouldn't send the same command over and over
+    if self.last_painted.get() == Some(pos) {
+      return;
+    }
+    self.last_painted.set(Some(pos));
+    self.send(ClientCommand::SetBlock {
+      pos: WorldPos::from_parts(ChunkCoord::origin(), pos),
+      block_type: self.paint_block_type.get(),
+    });
+  }
+
+  fn control(&self, control: SimControl) { self.send(ClientCommand::Control(control)); }
+
+  fn send(&self, command: ClientCommand) {
+    let mut bytes = command.encode();
+    self
+      .ws
+      .send_with_u8_array(&mut bytes)
+      .expect("send command to server");
   }
 
   fn draw(&self, chunk: &Chunk) {
@@ -128,28 +432,24 @@ impl LotsaClient {
       self.canvas_height as f64,
     );
 
-    let cell_width: f64 = ((self.canvas_width as f64 - GRID) / (CHUNK_WIDTH as f64) - GRID) as f64;
-    let cell_height: f64 =
-      ((self.canvas_height as f64 - GRID) / (CHUNK_WIDTH as f64) - GRID) as f64;
-
+    let registry = self.registry.borrow();
     for (pos, block) in chunk.blocks_iter() {
       if pos.z() > 0 {
         continue;
       }
 
-      let color_str = match block.block_type() {
-        EMPTY => "#fff",
-        UNKNOWN => "#f00",
-        LIFE => "#00f",
-        _ => "#0f0",
+      let color_str = match registry.get(block.block_type()) {
+        Some(meta) => meta.color.as_str(),
+        None => "#0f0",
       };
       self
         .canvas_ctx
         .set_fill_style(JsString::from(color_str).as_ref());
 
-      let x: f64 = GRID + (pos.x() as f64) * (cell_width + GRID);
-      let y: f64 = GRID + (pos.y() as f64) * (cell_height + GRID);
-      self.canvas_ctx.fill_rect(x, y, cell_width, cell_height);
+      let (x, y) = self.layout.cell_origin(pos);
+      self
+        .canvas_ctx
+        .fill_rect(x, y, self.layout.cell_width, self.layout.cell_height);
     }
 
     self.canvas_ctx.stroke();
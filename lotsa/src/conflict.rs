@@ -0,0 +1,355 @@
+use std::{
+  collections::{hash_map::DefaultHasher, HashMap},
+  fmt,
+  hash::{Hash, Hasher},
+};
+
+use crate::{
+  mutation::{BlockChange, BlockWrite},
+  world_pos::WorldPos,
+};
+
+/// How the simulator decides what happens when more than one updater run
+/// wants to write to the same block in a single step.
+///
+/// Except for `Merge`, all the writes made by a single updater run are kept or
+/// dropped together, so that e.g. a swap is never only half applied.
+pub enum ConflictPolicy {
+  /// The updater run that happened first gets its writes
+  FirstWins,
+  /// The updater run that happened last gets its writes
+  LastWins,
+  /// The updater with the highest priority gets its writes, with ties going
+  /// to whichever ran first
+  Priority,
+  /// A pseudo-random but deterministic winner, chosen by hashing where each
+  /// updater run happened
+  HashTieBreak,
+  /// Contested blocks get whatever the function returns, and everything else
+  /// is written as usual
+  Merge(Box<MergeFn>),
+}
+
+pub type MergeFn = dyn Fn(WorldPos, &[Proposal]) -> Vec<BlockChange>;
+
+impl Default for ConflictPolicy {
+  fn default() -> ConflictPolicy { ConflictPolicy::FirstWins }
+}
+
+impl fmt::Debug for ConflictPolicy {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ConflictPolicy::FirstWins => write!(f, "FirstWins"),
+      ConflictPolicy::LastWins => write!(f, "LastWins"),
+      ConflictPolicy::Priority => write!(f, "Priority"),
+      ConflictPolicy::HashTieBreak => write!(f, "HashTieBreak"),
+      ConflictPolicy::Merge(_) => write!(f, "Merge"),
+    }
+  }
+}
+
+/// The writes made by one run of an updater
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Proposal {
+  /// Index of the updater, in the order it was added to the simulator
+  pub updater: usize,
+  pub priority: i32,
+  /// The block the updater was running on
+  pub origin: WorldPos,
+  pub writes: Vec<BlockWrite>,
+}
+
+impl Proposal {
+  fn changes_at(&self, pos: WorldPos) -> Vec<BlockChange> {
+    self
+      .writes
+      .iter()
+      .filter(|write| write.pos == pos)
+      .map(|write| write.change)
+      .collect()
+  }
+
+  fn hash_key(&self) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    self.origin.hash(&mut hasher);
+    self.updater.hash(&mut hasher);
+    hasher.finish()
+  }
+}
+
+/// A block that more than one updater run tried to write to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Conflict {
+  pub pos: WorldPos,
+  /// The competing runs, in the order they happened, with only their writes
+  /// to this block
+  pub proposals: Vec<Proposal>,
+  /// What actually got written to this block
+  pub resolution: Vec<BlockChange>,
+}
+
+/// Returns the writes to apply, and if `report` is set, the conflicts found
+pub fn resolve(
+  policy: &ConflictPolicy,
+  proposals: Vec<Proposal>,
+  report: bool,
+) -> (Vec<BlockWrite>, Vec<Conflict>) {
+  let mut writers: HashMap<WorldPos, Vec<usize>> = HashMap::new();
+  for (i, proposal) in proposals.iter().enumerate() {
+    for write in proposal.writes.iter() {
+      let pos_writers = writers.entry(write.pos).or_default();
+      if pos_writers.last() != Some(&i) {
+        pos_writers.push(i);
+      }
+    }
+  }
+
+  let mut contested: Vec<(WorldPos, Vec<usize>)> = writers
+    .into_iter()
+    .filter(|(_, pos_writers)| pos_writers.len() > 1)
+    .collect();
+  if contested.is_empty() {
+    let writes = proposals.into_iter().flat_map(|p| p.writes).collect();
+    return (writes, Vec::new());
+  }
+  contested.sort_unstable_by_key(|&(pos, _)| pos);
+
+  let writes = match policy {
+    ConflictPolicy::Merge(merge_fn) => merge(merge_fn, &proposals, &contested),
+    _ => pick_winners(policy, &proposals),
+  };
+
+  let conflicts = if report {
+    contested
+      .into_iter()
+      .map(|(pos, pos_writers)| Conflict {
+        pos,
+        proposals: pos_writers
+          .iter()
+          .map(|&i| Proposal {
+            writes: proposals[i]
+              .writes
+              .iter()
+              .filter(|write| write.pos == pos)
+              .cloned()
+              .collect(),
+            ..proposals[i].clone()
+          })
+          .collect(),
+        resolution: writes
+          .iter()
+          .filter(|write| write.pos == pos)
+          .map(|write| write.change)
+          .collect(),
+      })
+      .collect()
+  } else {
+    Vec::new()
+  };
+
+  (writes, conflicts)
+}
+
+fn pick_winners(policy: &ConflictPolicy, proposals: &[Proposal]) -> Vec<BlockWrite> {
+  let mut order: Vec<usize> = (0..proposals.len()).collect();
+  match policy {
+    ConflictPolicy::LastWins => order.reverse(),
+    ConflictPolicy::Priority => order.sort_by_key(|&i| -i64::from(proposals[i].priority)),
+    ConflictPolicy::HashTieBreak => order.sort_by_key(|&i| proposals[i].hash_key()),
+    _ => (),
+  }
+
+  let mut claimed: HashMap<WorldPos, usize> = HashMap::new();
+  let mut accepted = vec![false; proposals.len()];
+  for i in order {
+    let proposal = &proposals[i];
+    let blocked = proposal
+      .writes
+      .iter()
+      .any(|write| claimed.get(&write.pos).map_or(false, |&owner| owner != i));
+    if !blocked {
+      for write in proposal.writes.iter() {
+        claimed.insert(write.pos, i);
+      }
+      accepted[i] = true;
+    }
+  }
+
+  proposals
+    .iter()
+    .zip(accepted)
+    .filter(|(_, accepted)| *accepted)
+    .flat_map(|(proposal, _)| proposal.writes.iter().cloned())
+    .collect()
+}
+
+fn merge(
+  merge_fn: &MergeFn,
+  proposals: &[Proposal],
+  contested: &[(WorldPos, Vec<usize>)],
+) -> Vec<BlockWrite> {
+  let contested_positions: HashMap<WorldPos, &Vec<usize>> =
+    contested.iter().map(|(pos, w)| (*pos, w)).collect();
+
+  let mut writes: Vec<BlockWrite> = proposals
+    .iter()
+    .flat_map(|proposal| proposal.writes.iter().cloned())
+    .filter(|write| !contested_positions.contains_key(&write.pos))
+    .collect();
+
+  for (pos, pos_writers) in contested.iter() {
+    let competing: Vec<Proposal> = pos_writers
+      .iter()
+      .map(|&i| Proposal {
+        writes: proposals[i]
+          .changes_at(*pos)
+          .into_iter()
+          .map(|change| BlockWrite { pos: *pos, change })
+          .collect(),
+        ..proposals[i].clone()
+      })
+      .collect();
+    for change in merge_fn(*pos, &competing) {
+      writes.push(BlockWrite { pos: *pos, change });
+    }
+  }
+
+  writes
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::block::BlockType;
+
+  const RED: BlockType = BlockType(40);
+  const BLUE: BlockType = BlockType(41);
+  const EMPTY: BlockType = crate::block::EMPTY;
+
+  fn write(x: i32, block_type: BlockType) -> BlockWrite {
+    BlockWrite {
+      pos: WorldPos::new(x, 0, 0),
+      change: BlockChange::BlockType(block_type),
+    }
+  }
+
+  // Red moves from 1 to 2 and blue moves from 3 to 2
+  fn competing_moves(red_priority: i32, blue_priority: i32) -> Vec<Proposal> {
+    vec![
+      Proposal {
+        updater: 0,
+        priority: red_priority,
+        origin: WorldPos::new(1, 0, 0),
+        writes: vec![write(2, RED), write(1, EMPTY)],
+      },
+      Proposal {
+        updater: 1,
+        priority: blue_priority,
+        origin: WorldPos::new(3, 0, 0),
+        writes: vec![write(2, BLUE), write(3, EMPTY)],
+      },
+      Proposal {
+        updater: 0,
+        priority: red_priority,
+        origin: WorldPos::new(7, 0, 0),
+        writes: vec![write(8, RED)],
+      },
+    ]
+  }
+
+  #[test]
+  fn test_no_conflicts() {
+    let proposals = vec![competing_moves(0, 0)[2].clone()];
+    let (writes, conflicts) = resolve(&ConflictPolicy::FirstWins, proposals, true);
+    assert_eq!(writes, vec![write(8, RED)]);
+    assert!(conflicts.is_empty());
+  }
+
+  #[test]
+  fn test_first_wins() {
+    let (writes, conflicts) = resolve(&ConflictPolicy::FirstWins, competing_moves(0, 0), false);
+    assert_eq!(writes, vec![write(2, RED), write(1, EMPTY), write(8, RED)]);
+    assert!(conflicts.is_empty());
+  }
+
+  #[test]
+  fn test_last_wins() {
+    let (writes, _) = resolve(&ConflictPolicy::LastWins, competing_moves(0, 0), false);
+    assert_eq!(writes, vec![write(2, BLUE), write(3, EMPTY), write(8, RED)]);
+  }
+
+  #[test]
+  fn test_priority() {
+    let (writes, _) = resolve(&ConflictPolicy::Priority, competing_moves(0, 5), false);
+    assert_eq!(writes, vec![write(2, BLUE), write(3, EMPTY), write(8, RED)]);
+
+    let (writes, _) = resolve(&ConflictPolicy::Priority, competing_moves(5, 0), false);
+    assert_eq!(writes, vec![write(2, RED), write(1, EMPTY), write(8, RED)]);
+
+    let (writes, _) = resolve(&ConflictPolicy::Priority, competing_moves(3, 3), false);
+    assert_eq!(writes, vec![write(2, RED), write(1, EMPTY), write(8, RED)]);
+  }
+
+  #[test]
+  fn test_hash_tie_break() {
+    let (writes, _) = resolve(&ConflictPolicy::HashTieBreak, competing_moves(0, 0), false);
+    assert_eq!(writes.len(), 3);
+    assert!(writes.contains(&write(8, RED)));
+    assert_eq!(
+      writes
+        .iter()
+        .filter(|w| w.pos == WorldPos::new(2, 0, 0))
+        .count(),
+      1
+    );
+
+    let (writes_again, _) = resolve(&ConflictPolicy::HashTieBreak, competing_moves(0, 0), false);
+    assert_eq!(writes, writes_again);
+  }
+
+  #[test]
+  fn test_merge() {
+    const PURPLE: BlockType = BlockType(42);
+    let policy = ConflictPolicy::Merge(Box::new(|_pos, proposals| {
+      assert_eq!(proposals.len(), 2);
+      vec![BlockChange::BlockType(PURPLE)]
+    }));
+
+    let (writes, _) = resolve(&policy, competing_moves(0, 0), false);
+    assert_eq!(
+      writes,
+      vec![
+        write(1, EMPTY),
+        write(3, EMPTY),
+        write(8, RED),
+        write(2, PURPLE)
+      ]
+    );
+  }
+
+  #[test]
+  fn test_report() {
+    let (_, conflicts) = resolve(&ConflictPolicy::FirstWins, competing_moves(0, 0), true);
+    assert_eq!(
+      conflicts,
+      vec![Conflict {
+        pos: WorldPos::new(2, 0, 0),
+        proposals: vec![
+          Proposal {
+            updater: 0,
+            priority: 0,
+            origin: WorldPos::new(1, 0, 0),
+            writes: vec![write(2, RED)],
+          },
+          Proposal {
+            updater: 1,
+            priority: 0,
+            origin: WorldPos::new(3, 0, 0),
+            writes: vec![write(2, BLUE)],
+          },
+        ],
+        resolution: vec![BlockChange::BlockType(RED)],
+      }]
+    );
+  }
+}
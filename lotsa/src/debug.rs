@@ -2,6 +2,7 @@ use std::collections::HashMap;
 
 use crate::{
   block::{BlockType, EMPTY, UNKNOWN},
+  block_registry::BlockRegistry,
   chunk::Chunk,
   chunk_pos::ChunkPos,
 };
@@ -27,6 +28,16 @@ impl Debugger {
     }
   }
 
+  /// Uses the debug characters of every registered block type
+  pub fn from_registry(registry: &BlockRegistry) -> Debugger {
+    Debugger::new(
+      registry
+        .iter()
+        .map(|(bt, meta)| (bt, meta.debug_char))
+        .collect(),
+    )
+  }
+
   pub fn bounds(&self, c: &Chunk) -> ChunkPos {
     let mut r = ChunkPos::new(0, 0, 0);
 
@@ -103,6 +114,7 @@ mod tests {
 
   use crate::{
     block::{EMPTY, UNKNOWN},
+    block_registry::BlockMeta,
     chunk::Chunk,
   };
 
@@ -189,4 +201,24 @@ mod tests {
       )
     )
   }
+
+  #[test]
+  fn test_from_registry() {
+    let mut registry = BlockRegistry::new();
+    let cobble = registry.register(BlockMeta::new("cobble", "#888", 'C'));
+    let debugger = Debugger::from_registry(&registry);
+
+    let mut c = Chunk::new();
+    debugger.load(
+      &mut c,
+      ".C
+       X.",
+    );
+    assert_eq!(c.get_block(ChunkPos::new(1, 0, 0)).block_type(), cobble);
+    debugger.assert_match(
+      &c,
+      ".C
+       X.",
+    );
+  }
 }
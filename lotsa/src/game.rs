@@ -0,0 +1,20 @@
+use crate::{
+  block_registry::BlockRegistry, chunk_map::ChunkMap, sim::Simulator, topology::Topology,
+};
+
+/// The rules of a particular game: what blocks it has, how they behave, and
+/// what the world looks like when the server starts
+pub trait Game: Send + Sync + 'static {
+  /// Called first, on a registry that only has the built-in block types
+  fn register_blocks(&self, registry: &mut BlockRegistry);
+
+  /// Called with the registry from `register_blocks`, to look up the game's
+  /// block types
+  fn init_simulator(&self, sim: &mut Simulator, registry: &BlockRegistry);
+
+  /// Used to load saved worlds, so it should match `generate_world`
+  fn topology(&self) -> Topology { Topology::Infinite }
+
+  /// Only called when there's no saved world to load
+  fn generate_world(&self, registry: &BlockRegistry) -> ChunkMap;
+}
@@ -23,16 +23,31 @@ extern crate serde_big_array;
 extern crate test;
 
 pub mod block;
+pub mod block_field;
+pub mod block_registry;
 pub mod chunk;
+pub mod chunk_coord;
+pub mod chunk_format;
 pub mod chunk_index;
+pub mod chunk_map;
 pub mod chunk_pos;
+pub mod conflict;
 pub mod debug;
+pub mod game;
 pub mod life;
+pub mod life_like;
 pub mod loaded_chunk;
+pub mod mutation;
+pub mod palette;
+pub mod persist;
+pub mod protocol;
 pub mod query;
 pub mod relative_pos;
+pub mod rules;
 pub mod sim;
+pub mod topology;
 pub mod unique_descrip;
+pub mod world_pos;
 
 #[cfg(feature = "client")]
 pub mod client;
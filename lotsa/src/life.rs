@@ -1,53 +1,79 @@
 use crate::{
   block::{BlockType, EMPTY},
-  query::{Chebyshev2DNeighbors, GetBlockType},
-  sim::{Simulator, UpdaterHandle},
+  block_registry::{BlockMeta, BlockRegistry},
+  chunk::{Chunk, ChunkStorage},
+  chunk_coord::ChunkCoord,
+  chunk_map::ChunkMap,
+  debug::Debugger,
+  game::Game,
+  life_like::LifeLikeRule,
+  sim::Simulator,
 };
 
-pub const LIFE: BlockType = BlockType(3);
+/// Returns the type of the live cell block
+pub fn register_blocks(registry: &mut BlockRegistry) -> BlockType {
+  registry.register(BlockMeta::new("life", "#00f", 'L'))
+}
 
-fn live_blocks_here(neighbors: &mut dyn Iterator<Item = BlockType>) -> usize {
-  neighbors.filter(|&b| b == LIFE).count()
+/// Finds the live cell block that `register_blocks` added to the registry
+pub fn life_block_type(registry: &BlockRegistry) -> BlockType {
+  registry
+    .lookup("life")
+    .expect("life::register_blocks must be called first")
 }
 
-pub fn init(sim: &mut Simulator) {
-  sim.add_updater(LIFE, |updater| {
-    let neighbor_block_types =
-      updater.prepare_query(&Chebyshev2DNeighbors::new(1, &GetBlockType::new()));
-    updater.implement(move |handle: &UpdaterHandle| {
-      let nearby = live_blocks_here(Box::leak(handle.query(&neighbor_block_types))) - 1;
-      if nearby >= 2 && nearby <= 4 {
-        None
-      } else {
-        Some(EMPTY)
-      }
-    });
-  });
-
-  sim.add_updater(EMPTY, |updater| {
-    let neighbor_block_types =
-      updater.prepare_query(&Chebyshev2DNeighbors::new(1, &GetBlockType::new()));
-    updater.implement(move |handle: &UpdaterHandle| {
-      let nearby = live_blocks_here(Box::leak(handle.query(&neighbor_block_types)));
-      if nearby == 3 {
-        Some(LIFE)
-      } else {
-        None
-      }
-    });
-  });
+pub fn init(sim: &mut Simulator, registry: &BlockRegistry) {
+  LifeLikeRule::conway().init(sim, life_block_type(registry));
+}
+
+/// The built-in block types plus life, for tests all over the crate
+#[cfg(test)]
+pub(crate) fn life_registry() -> BlockRegistry {
+  let mut registry = BlockRegistry::new();
+  register_blocks(&mut registry);
+  registry
+}
+
+/// Conway's Game of Life, starting with a couple of blinkers
+pub struct Life;
+
+impl Game for Life {
+  fn register_blocks(&self, registry: &mut BlockRegistry) { register_blocks(registry); }
+
+  fn init_simulator(&self, sim: &mut Simulator, registry: &BlockRegistry) { init(sim, registry); }
+
+  fn generate_world(&self, registry: &BlockRegistry) -> ChunkMap {
+    // Paletted storage keeps the chunk small in memory
+    let mut chunk = Chunk::with_storage(ChunkStorage::Paletted);
+    chunk.fill_with_block_type(EMPTY);
+
+    Debugger::from_registry(registry).load(
+      &mut chunk,
+      ".....
+       .LLL.
+       .....
+       .....
+       .....
+       .LLL.
+       .....",
+    );
+
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.load(ChunkCoord::origin(), chunk);
+    chunk_map
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
-  use crate::{block::UNKNOWN, chunk::Chunk, debug::Debugger, loaded_chunk::LoadedChunk};
   use test::Bencher;
 
   #[test]
   fn test_blinker() {
     let mut chunk = Chunk::new();
-    let debugger = Debugger::new(hashmap!(UNKNOWN => 'X', EMPTY => '.', LIFE => 'L'));
+    let registry = life_registry();
+    let debugger = Debugger::from_registry(&registry);
 
     debugger.load(
       &mut chunk,
@@ -58,14 +84,16 @@ mod tests {
        .....",
     );
 
-    let mut loaded_chunk = LoadedChunk::new(chunk);
+    let origin = ChunkCoord::origin();
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.load(origin, chunk);
 
     let mut sim = Simulator::new();
-    init(&mut sim);
+    init(&mut sim, &registry);
 
-    sim.step(&mut loaded_chunk);
+    sim.step(&mut chunk_map);
     debugger.assert_match(
-      loaded_chunk.get(),
+      chunk_map.get(origin).unwrap().get(),
       ".....
        ..L..
        ..L..
@@ -73,21 +101,80 @@ mod tests {
        .....",
     );
 
-    sim.step(&mut loaded_chunk);
+    sim.step(&mut chunk_map);
     debugger.assert_match(
-      loaded_chunk.get(),
+      chunk_map.get(origin).unwrap().get(),
+      ".....
+       .....
+       .LLL.
+       .....
+       .....",
+    );
+  }
+
+  #[test]
+  fn test_blinkers_in_separate_chunks() {
+    let registry = life_registry();
+    let debugger = Debugger::from_registry(&registry);
+    let west = ChunkCoord::new(-1, 0, 0);
+    let east = ChunkCoord::new(1, 0, 0);
+
+    let mut chunk = Chunk::new();
+    debugger.load(
+      &mut chunk,
       ".....
        .....
        .LLL.
        .....
        .....",
     );
+
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.load(west, chunk.clone());
+    chunk_map.load(east, chunk);
+
+    let mut sim = Simulator::new();
+    init(&mut sim, &registry);
+
+    sim.step(&mut chunk_map);
+    for &coord in [west, east].iter() {
+      debugger.assert_match(
+        chunk_map.get(coord).unwrap().get(),
+        ".....
+         ..L..
+         ..L..
+         ..L..
+         .....",
+      );
+    }
+  }
+
+  #[test]
+  fn test_game() {
+    let mut registry = BlockRegistry::new();
+    Life.register_blocks(&mut registry);
+    let mut chunk_map = Life.generate_world(&registry);
+    let mut sim = Simulator::new();
+    Life.init_simulator(&mut sim, &registry);
+
+    sim.step(&mut chunk_map);
+    Debugger::from_registry(&registry).assert_match(
+      chunk_map.get(ChunkCoord::origin()).unwrap().get(),
+      "..L..
+       ..L..
+       ..L..
+       .....
+       ..L..
+       ..L..
+       ..L..",
+    );
   }
 
   #[bench]
   fn bench_blinker(b: &mut Bencher) {
     let mut base_chunk = Chunk::new();
-    let debugger = Debugger::new(hashmap!(UNKNOWN => 'X', EMPTY => '.', LIFE => 'L'));
+    let registry = life_registry();
+    let debugger = Debugger::from_registry(&registry);
 
     debugger.load(
       &mut base_chunk,
@@ -99,16 +186,18 @@ mod tests {
     );
 
     let mut sim = Simulator::new();
-    init(&mut sim);
+    init(&mut sim, &registry);
 
     b.iter(|| {
-      let mut loaded_chunk = LoadedChunk::new(base_chunk.clone());
+      let origin = ChunkCoord::origin();
+      let mut chunk_map = ChunkMap::new();
+      chunk_map.load(origin, base_chunk.clone());
 
       for _ in 1..20 {
-        sim.step(&mut loaded_chunk);
+        sim.step(&mut chunk_map);
       }
 
-      debugger.dump(loaded_chunk.get())
+      debugger.dump(chunk_map.get(origin).unwrap().get())
     });
   }
 }
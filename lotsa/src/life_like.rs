@@ -0,0 +1,360 @@
+use std::fmt;
+
+use crate::{
+  block::{BlockType, EMPTY},
+  query::{Constant, Count, Equals, GetBlockType, InSet, Not, OffsetNeighbors},
+  relative_pos::RelativePos,
+  sim::{Simulator, UpdaterHandle},
+};
+
+/// A cellular automaton like Conway's Game of Life, where a dead cell comes
+/// alive and a live cell survives depending only on how many of its 8
+/// neighbors are alive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LifeLikeRule {
+  /// Indexed by the number of live neighbors
+  birth: [bool; 9],
+  survival: [bool; 9],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RulestringError {
+  pub rulestring: String,
+}
+
+impl fmt::Display for RulestringError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "bad rulestring {:?}, expected something like B3/S23",
+      self.rulestring
+    )
+  }
+}
+
+impl std::error::Error for RulestringError {}
+
+impl LifeLikeRule {
+  /// Reads B/S notation, e.g. `B3/S23` for Conway's Game of Life, `B36/S23`
+  /// for HighLife or `B2/S` for Seeds. The halves can come in either order.
+  pub fn parse(rulestring: &str) -> Result<LifeLikeRule, RulestringError> {
+    let error = || RulestringError {
+      rulestring: rulestring.to_string(),
+    };
+
+    let mut birth = None;
+    let mut survival = None;
+    for part in rulestring.trim().split('/') {
+      let mut chars = part.chars();
+      let counts = match chars.next().map(|c| c.to_ascii_uppercase()) {
+        Some('B') if birth.is_none() => &mut birth,
+        Some('S') if survival.is_none() => &mut survival,
+        _ => return Err(error()),
+      };
+      let mut neighbors = [false; 9];
+      for c in chars {
+        match c.to_digit(10) {
+          Some(count) if count <= 8 => neighbors[count as usize] = true,
+          _ => return Err(error()),
+        }
+      }
+      *counts = Some(neighbors);
+    }
+
+    match (birth, survival) {
+      (Some(birth), Some(survival)) => Ok(LifeLikeRule { birth, survival }),
+      _ => Err(error()),
+    }
+  }
+
+  pub fn conway() -> LifeLikeRule { LifeLikeRule::parse("B3/S23").unwrap() }
+
+  /// False for more than 8 neighbors, which a cell can't have
+  pub fn is_born(&self, live_neighbors: u32) -> bool {
+    self.birth.get(live_neighbors as usize) == Some(&true)
+  }
+
+  /// False for more than 8 neighbors, like `is_born`
+  pub fn survives(&self, live_neighbors: u32) -> bool {
+    self.survival.get(live_neighbors as usize) == Some(&true)
+  }
+
+  /// Adds updaters that apply the rule to `life` and `EMPTY` blocks in the XY
+  /// plane
+  pub fn init(&self, sim: &mut Simulator, life: BlockType) {
+    let birth = counts(&self.birth);
+    let survival = counts(&self.survival);
+
+    sim.add_updater(life, move |updater| {
+      let dies = updater.prepare_query(&Not::new(&InSet::new(&live_neighbors(life), &survival)));
+      updater.implement(move |handle: &UpdaterHandle| {
+        if handle.query(&dies) {
+          Some(EMPTY)
+        } else {
+          None
+        }
+      });
+    });
+
+    sim.add_updater(EMPTY, move |updater| {
+      let born = updater.prepare_query(&InSet::new(&live_neighbors(life), &birth));
+      updater.implement(move |handle: &UpdaterHandle| {
+        if handle.query(&born) {
+          Some(life)
+        } else {
+          None
+        }
+      });
+    });
+  }
+}
+
+impl fmt::Display for LifeLikeRule {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let digits = |neighbors: &[bool; 9]| -> String {
+      counts(neighbors)
+        .iter()
+        .map(|count| count.to_string())
+        .collect()
+    };
+    write!(f, "B{}/S{}", digits(&self.birth), digits(&self.survival))
+  }
+}
+
+fn counts(neighbors: &[bool; 9]) -> Vec<u32> {
+  (0..9).filter(|&count| neighbors[count as usize]).collect()
+}
+
+type LiveNeighbors =
+  Count<OffsetNeighbors<bool, Equals<BlockType, GetBlockType, Constant<BlockType>>>>;
+
+fn live_neighbors(life: BlockType) -> LiveNeighbors {
+  let mut moore = Vec::new();
+  for y in -1..=1 {
+    for x in -1..=1 {
+      if x != 0 || y != 0 {
+        moore.push(RelativePos::new(x, y, 0));
+      }
+    }
+  }
+  let is_life = Equals::new(&GetBlockType::new(), &Constant::new(life));
+  Count::new(&OffsetNeighbors::new(&moore, &is_life))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    chunk::Chunk,
+    chunk_coord::ChunkCoord,
+    chunk_map::ChunkMap,
+    debug::Debugger,
+    life::{self, life_registry},
+  };
+
+  // Loads `start`, then checks the chunk against each of `steps` after each
+  // step of the rule
+  fn assert_steps(rulestring: &str, start: &str, steps: &[&str]) {
+    let registry = life_registry();
+    let life = life::life_block_type(&registry);
+    let debugger = Debugger::from_registry(&registry);
+    let mut chunk = Chunk::new();
+    debugger.load(&mut chunk, start);
+
+    let origin = ChunkCoord::origin();
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.load(origin, chunk);
+
+    let mut sim = Simulator::new();
+    LifeLikeRule::parse(rulestring)
+      .unwrap()
+      .init(&mut sim, life);
+
+    for &expected in steps.iter() {
+      sim.step(&mut chunk_map);
+      debugger.assert_match(chunk_map.get(origin).unwrap().get(), expected);
+    }
+  }
+
+  const BLINKER: &str = ".....
+                         .....
+                         .LLL.
+                         .....
+                         .....";
+
+  const BLINKER_TURNED: &str = ".....
+                                ..L..
+                                ..L..
+                                ..L..
+                                .....";
+
+  const BLOCK: &str = "....
+                       .LL.
+                       .LL.
+                       ....";
+
+  #[test]
+  fn test_parse() {
+    let conway = LifeLikeRule::parse("B3/S23").unwrap();
+    assert_eq!(conway, LifeLikeRule::conway());
+    assert!(conway.is_born(3));
+    assert!(!conway.is_born(2));
+    assert!(conway.survives(2));
+    assert!(conway.survives(3));
+    assert!(!conway.survives(4));
+    assert!(!conway.is_born(9));
+    assert!(!conway.survives(100));
+
+    assert_eq!(LifeLikeRule::parse("s23/b3").unwrap(), conway);
+    assert_eq!(
+      LifeLikeRule::parse("B36/S23").unwrap().to_string(),
+      "B36/S23"
+    );
+    assert_eq!(LifeLikeRule::parse("B2/S").unwrap().to_string(), "B2/S");
+    assert_eq!(
+      LifeLikeRule::parse("B/S012345678").unwrap().to_string(),
+      "B/S012345678"
+    );
+
+    for &bad in ["", "B3", "23/3", "B3/S23/S4", "B3/B3", "B9/S23", "B3/Sx"].iter() {
+      assert_eq!(
+        LifeLikeRule::parse(bad),
+        Err(RulestringError {
+          rulestring: bad.to_string()
+        })
+      );
+    }
+  }
+
+  #[test]
+  fn test_conway() {
+    assert_steps("B3/S23", BLINKER, &[BLINKER_TURNED, BLINKER]);
+    assert_steps("B3/S23", BLOCK, &[BLOCK, BLOCK]);
+
+    // A glider moves one block diagonally every 4 steps
+    assert_steps(
+      "B3/S23",
+      ".......
+       ..L....
+       ...L...
+       .LLL...
+       .......
+       .......",
+      &[
+        ".......
+         .......
+         .L.L...
+         ..LL...
+         ..L....
+         .......",
+        ".......
+         .......
+         ...L...
+         .L.L...
+         ..LL...
+         .......",
+        ".......
+         .......
+         ..L....
+         ...LL..
+         ..LL...
+         .......",
+        ".......
+         .......
+         ...L...
+         ....L..
+         ..LLL..
+         .......",
+      ],
+    );
+  }
+
+  #[test]
+  fn test_conway_overcrowding() {
+    // The middle of the plus has 4 live neighbors, which is too many
+    assert_steps(
+      "B3/S23",
+      ".....
+       ..L..
+       .LLL.
+       ..L..
+       .....",
+      &[".....
+         .LLL.
+         .L.L.
+         .LLL.
+         ....."],
+    );
+  }
+
+  #[test]
+  fn test_highlife() {
+    // HighLife behaves like Conway's Game of Life, except that 6 live
+    // neighbors also give birth
+    assert_steps("B36/S23", BLINKER, &[BLINKER_TURNED, BLINKER]);
+    assert_steps("B36/S23", BLOCK, &[BLOCK]);
+
+    let two_blinkers = ".....
+                        .....
+                        .LLL.
+                        .....
+                        .LLL.
+                        .....
+                        .....";
+    assert_steps(
+      "B3/S23",
+      two_blinkers,
+      &[".....
+         ..L..
+         ..L..
+         .....
+         ..L..
+         ..L..
+         ....."],
+    );
+    assert_steps(
+      "B36/S23",
+      two_blinkers,
+      &[".....
+         ..L..
+         ..L..
+         ..L..
+         ..L..
+         ..L..
+         ....."],
+    );
+  }
+
+  #[test]
+  fn test_seeds() {
+    // Nothing survives in Seeds, and 2 live neighbors give birth
+    assert_steps(
+      "B2/S",
+      ".....
+       .....
+       .LL..
+       .....
+       .....",
+      &[
+        ".....
+         .LL..
+         .....
+         .LL..
+         .....",
+        ".LL..
+         .....
+         L..L.
+         .....
+         .LL..",
+      ],
+    );
+    assert_steps(
+      "B2/S",
+      BLOCK,
+      &[".LL.
+                                   L..L
+                                   L..L
+                                   .LL."],
+    );
+  }
+}
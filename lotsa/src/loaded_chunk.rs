@@ -1,82 +1,215 @@
-use std::{collections::HashMap};
+use std::collections::HashMap;
 
 use crate::{
   block::BlockType,
+  block_field::FieldId,
   chunk::Chunk,
+  chunk_coord::ChunkCoord,
   chunk_index::ChunkIndex,
   chunk_pos::ChunkPos,
-  query::{BlockInfo, Cacheability, CacheableField},
+  query::{BlockInfo, Cacheability, CacheabilitySet, CacheableField},
+  topology::Topology,
 };
 
+/// A cache buster mark that belongs in another chunk
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpilledMark {
+  pub cacheability: Cacheability,
+  pub coord: ChunkCoord,
+  pub pos: ChunkPos,
+}
+
 #[derive(Clone)]
 pub struct LoadedChunk {
+  coord: ChunkCoord,
   chunk: Chunk,
+  // Positions to consider on the next step for each cacheability. A
+  // cacheability that isn't in here hasn't had its first full pass over the
+  // chunk yet. Updaters with a set of cacheabilities consider the union of
+  // their indices.
   cache_busters: HashMap<Cacheability, ChunkIndex>,
+  // Where each block type is in the chunk, so that updaters only visit
+  // blocks of their target type
+  block_types: HashMap<BlockType, ChunkIndex>,
 }
 
 impl LoadedChunk {
-  pub fn new(chunk: Chunk) -> LoadedChunk {
+  pub fn new(coord: ChunkCoord, chunk: Chunk) -> LoadedChunk {
+    let mut block_types: HashMap<BlockType, ChunkIndex> = HashMap::new();
+    for (pos, block) in chunk.blocks_iter() {
+      block_types.entry(block.block_type).or_default().mark(pos);
+    }
     LoadedChunk {
+      coord,
       chunk,
       cache_busters: HashMap::new(),
+      block_types,
     }
   }
 
+  pub fn coord(&self) -> ChunkCoord { self.coord }
+
   pub fn get(&self) -> &Chunk { &self.chunk }
 
+  /// Called after every block has been considered for each of the given
+  /// cacheabilities, to start tracking changes from scratch
   pub fn reset_cache_busters<'a, T: Iterator<Item = &'a Cacheability>>(
     &mut self,
     cacheabilities: T,
   ) {
     self.cache_busters = HashMap::new();
     for cacheability in cacheabilities {
+      // Every block is considered every time anyway
+      if *cacheability == Cacheability::DontCache {
+        continue;
+      }
       self
         .cache_busters
         .insert(cacheability.clone(), ChunkIndex::new());
     }
   }
 
-  pub fn set_block_type(&mut self, pos: ChunkPos, block_type: BlockType) {
+  pub fn mark_cache_buster(&mut self, cacheability: &Cacheability, pos: ChunkPos) {
+    if let Some(chunk_index) = self.cache_busters.get_mut(cacheability) {
+      chunk_index.mark(pos);
+    }
+  }
+
+  /// Makes the position considerable on the next step for anything with
+  /// this set of cacheabilities
+  pub fn mark_considerable(&mut self, cacheability: &CacheabilitySet, pos: ChunkPos) {
+    // Any one of the set's indices will do, since they are unioned
+    if let Some(cacheability) = cacheability.iter().next() {
+      self.mark_cache_buster(cacheability, pos);
+    }
+  }
+
+  /// Returns the marks that need to be applied to other chunks' cache busters
+  pub fn set_block_type(
+    &mut self,
+    pos: ChunkPos,
+    block_type: BlockType,
+    topology: &Topology,
+  ) -> Vec<SpilledMark> {
+    let old_block_type = self.chunk.get_block(pos).block_type;
+    if old_block_type != block_type {
+      if let Some(positions) = self.block_types.get_mut(&old_block_type) {
+        positions.unmark(pos);
+        if positions.is_empty() {
+          self.block_types.remove(&old_block_type);
+        }
+      }
+      self.block_types.entry(block_type).or_default().mark(pos);
+    }
     self.chunk.set_block_type(pos, block_type);
+    self.mark_change(pos, CacheableField::CacheableBlockType, topology)
+  }
+
+  /// Returns the marks that need to be applied to other chunks' cache busters
+  pub fn set_field_raw(
+    &mut self,
+    pos: ChunkPos,
+    field_id: FieldId,
+    value: u16,
+    topology: &Topology,
+  ) -> Vec<SpilledMark> {
+    self.chunk.set_field_raw(pos, field_id, value);
+    self.mark_change(pos, CacheableField::CacheableBlockField(field_id), topology)
+  }
+
+  fn mark_change(
+    &mut self,
+    pos: ChunkPos,
+    field: CacheableField,
+    topology: &Topology,
+  ) -> Vec<SpilledMark> {
+    let coord = self.coord;
+    let mut spilled_marks = Vec::new();
 
     for (cacheability, chunk_index) in self.cache_busters.iter_mut() {
-      match cacheability {
-        // TODO: Shouldn't even bother to keep the two below in cache_busters
-        Cacheability::DontCache => (),
-        Cacheability::Forever => (),
-        Cacheability::UntilChangeInSelf { fields } => {
-          if fields.contains(&CacheableField::CacheableBlockType) {
-            chunk_index.mark(pos);
-          }
+      // A block that has just become some updater's target type needs its
+      // first run, even if nothing it reads has changed
+      if field == CacheableField::CacheableBlockType {
+        chunk_index.mark(pos);
+      }
+      let spilled = match cacheability {
+        Cacheability::DontCache | Cacheability::Forever => continue,
+        _ if !cacheability.fields().contains(&field) => continue,
+        Cacheability::UntilChangeInSelf { .. } => {
+          chunk_index.mark(pos);
+          continue;
+        },
+        Cacheability::UntilChangeInChebyshevNeighborhood { distance, .. } => {
+          chunk_index.mark_chebyshev_neighborhood(coord, pos, *distance, topology)
         },
-        Cacheability::UntilChangeInChebyshevNeighborhood { fields, distance } => {
-          if fields.contains(&CacheableField::CacheableBlockType) {
-            chunk_index.mark_chebyshev_neighborhood(pos, *distance);
-          }
+        Cacheability::UntilChangeInVonNeumannNeighborhood { distance, .. } => {
+          chunk_index.mark_von_neumann_neighborhood(coord, pos, *distance, topology)
         },
+        Cacheability::UntilChangeInOffsets { offsets, .. } => {
+          chunk_index.mark_offsets(coord, pos, offsets, topology)
+        },
+      };
+      for (coord, pos) in spilled {
+        spilled_marks.push(SpilledMark {
+          cacheability: cacheability.clone(),
+          coord,
+          pos,
+        });
       }
     }
+
+    spilled_marks
   }
 
   pub fn considerable_blocks_iter<'a>(
     &'a self,
-    cacheability: &Cacheability,
+    cacheability: &CacheabilitySet,
   ) -> Box<dyn Iterator<Item = (ChunkPos, BlockInfo)> + 'a> {
-    match cacheability {
-      Cacheability::DontCache =>
-        Box::new(self.chunk.blocks_iter()),
-      Cacheability::Forever =>
-        Box::new(self.chunk.blocks_iter()), // FIXME: Only first run
-      _ => {
-        match self.cache_busters.get(cacheability) {
-          None => Box::new(self.chunk.blocks_iter()),
-          Some(chunk_index) => {
-            Box::new(
-              chunk_index.iter().map(move |chunk_pos| (chunk_pos, self.chunk.get_block(chunk_pos)) )
-            )
-          }
-        }
-      }
+    match self.considerable_index(cacheability) {
+      None => Box::new(self.chunk.blocks_iter()),
+      Some(considerable) => Box::new(
+        considerable
+          .iter()
+          .collect::<Vec<_>>()
+          .into_iter()
+          .map(move |chunk_pos| (chunk_pos, self.chunk.get_block(chunk_pos))),
+      ),
+    }
+  }
+
+  /// Like `considerable_blocks_iter`, but only the blocks of the given type
+  pub fn considerable_targets_iter<'a>(
+    &'a self,
+    cacheability: &CacheabilitySet,
+    target: BlockType,
+  ) -> Box<dyn Iterator<Item = (ChunkPos, BlockInfo)> + 'a> {
+    let targets = match self.block_types.get(&target) {
+      None => return Box::new(std::iter::empty()),
+      Some(targets) => targets,
+    };
+    let positions: Box<dyn Iterator<Item = ChunkPos> + 'a> =
+      match self.considerable_index(cacheability) {
+        None => Box::new(targets.iter()),
+        Some(mut considerable) => {
+          considerable.intersect_with(targets);
+          Box::new(considerable.iter().collect::<Vec<_>>().into_iter())
+        },
+      };
+    Box::new(positions.map(move |chunk_pos| (chunk_pos, self.chunk.get_block(chunk_pos))))
+  }
+
+  // The union of the set's cache busters, or `None` if every block needs to
+  // be considered
+  fn considerable_index(&self, cacheability: &CacheabilitySet) -> Option<ChunkIndex> {
+    if cacheability.is_dont_cache() {
+      return None;
+    }
+    let mut union = ChunkIndex::new();
+    for cacheability in cacheability.iter() {
+      // Everything is considered until all of the set's cacheabilities have
+      // had their first full pass
+      union.union_with(self.cache_busters.get(cacheability)?);
     }
+    Some(union)
   }
 }
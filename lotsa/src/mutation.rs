@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  block::BlockType,
+  block_field::{BlockField, FieldId, FieldValue},
+  relative_pos::RelativePos,
+  world_pos::WorldPos,
+};
+
+/// A change that an updater makes to the block it is running on, or to
+/// another block relative to that one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mutation {
+  SetBlockType(BlockType),
+  SetField(FieldId, u16),
+  SetBlockTypeAt(RelativePos, BlockType),
+  SetFieldAt(RelativePos, FieldId, u16),
+  /// Exchanges the block type and fields of this block with another one
+  SwapWith(RelativePos),
+}
+
+impl Mutation {
+  pub fn set_field<T: FieldValue>(field: BlockField<T>, value: T) -> Mutation {
+    Mutation::SetField(field.id(), value.to_raw())
+  }
+
+  pub fn set_field_at<T: FieldValue>(at: RelativePos, field: BlockField<T>, value: T) -> Mutation {
+    Mutation::SetFieldAt(at, field.id(), value.to_raw())
+  }
+}
+
+/// A single concrete change to a block, after a mutation has been resolved
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockChange {
+  BlockType(BlockType),
+  Field(FieldId, u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockWrite {
+  pub pos: WorldPos,
+  pub change: BlockChange,
+}
+
+/// Anything an updater function can return
+pub trait IntoMutations {
+  fn into_mutations(self) -> Vec<Mutation>;
+}
+
+impl IntoMutations for () {
+  fn into_mutations(self) -> Vec<Mutation> { Vec::new() }
+}
+
+impl IntoMutations for Option<BlockType> {
+  fn into_mutations(self) -> Vec<Mutation> {
+    match self {
+      None => Vec::new(),
+      Some(block_type) => vec![Mutation::SetBlockType(block_type)],
+    }
+  }
+}
+
+impl IntoMutations for Mutation {
+  fn into_mutations(self) -> Vec<Mutation> { vec![self] }
+}
+
+impl IntoMutations for Option<Mutation> {
+  fn into_mutations(self) -> Vec<Mutation> { self.into_iter().collect() }
+}
+
+impl IntoMutations for Vec<Mutation> {
+  fn into_mutations(self) -> Vec<Mutation> { self }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const COBBLE: BlockType = BlockType(37);
+  const AGE: BlockField<u8> = BlockField::new(1);
+
+  #[test]
+  fn test_typed_constructors() {
+    let east = RelativePos::new(1, 0, 0);
+    assert_eq!(
+      Mutation::set_field(AGE, 200),
+      Mutation::SetField(AGE.id(), 200)
+    );
+    assert_eq!(
+      Mutation::set_field_at(east, AGE, 4),
+      Mutation::SetFieldAt(east, AGE.id(), 4)
+    );
+  }
+
+  #[test]
+  fn test_into_mutations() {
+    assert_eq!(().into_mutations(), vec![]);
+    assert_eq!((None as Option<BlockType>).into_mutations(), vec![]);
+    assert_eq!((None as Option<Mutation>).into_mutations(), vec![]);
+    assert_eq!(
+      Some(COBBLE).into_mutations(),
+      vec![Mutation::SetBlockType(COBBLE)]
+    );
+    assert_eq!(
+      vec![Mutation::SetBlockType(COBBLE), Mutation::set_field(AGE, 3)].into_mutations(),
+      vec![
+        Mutation::SetBlockType(COBBLE),
+        Mutation::SetField(AGE.id(), 3)
+      ]
+    );
+  }
+}
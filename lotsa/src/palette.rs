@@ -0,0 +1,282 @@
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::{block::BlockType, chunk::CHUNK_WIDTH_E3};
+
+/// Block types for a whole chunk, stored as indices into a palette of the
+/// block types actually in use. Indices are bit-packed into words, using as
+/// few bits as the palette allows (rounded up to a power of two so that
+/// indices never straddle words), and a chunk that is all one block type
+/// stores no indices at all.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PalettedBlockTypes {
+  palette: Vec<BlockType>,
+  bits: u8,
+  words: Vec<u64>,
+}
+
+// The same fields, before they're checked
+#[derive(Deserialize)]
+#[serde(rename = "PalettedBlockTypes")]
+struct UncheckedPalettedBlockTypes {
+  palette: Vec<BlockType>,
+  bits: u8,
+  words: Vec<u64>,
+}
+
+// Deserializing checks that every index is in the palette, so that `get`
+// can't panic on corrupt data
+impl<'de> Deserialize<'de> for PalettedBlockTypes {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let unchecked = UncheckedPalettedBlockTypes::deserialize(deserializer)?;
+    PalettedBlockTypes::checked(unchecked).map_err(de::Error::custom)
+  }
+}
+
+impl PalettedBlockTypes {
+  pub fn new(block_type: BlockType) -> PalettedBlockTypes {
+    PalettedBlockTypes {
+      palette: vec![block_type],
+      bits: 0,
+      words: Vec::new(),
+    }
+  }
+
+  fn checked(unchecked: UncheckedPalettedBlockTypes) -> Result<PalettedBlockTypes, &'static str> {
+    let UncheckedPalettedBlockTypes {
+      palette,
+      bits,
+      words,
+    } = unchecked;
+    match bits {
+      0 | 1 | 2 | 4 | 8 | 16 => (),
+      _ => return Err("palette index size isn't a power of two up to 16"),
+    }
+    if palette.is_empty() || palette.len() > 1 << bits {
+      return Err("palette doesn't fit its index size");
+    }
+    if words.len() != CHUNK_WIDTH_E3 * bits as usize / 64 {
+      return Err("wrong number of palette index words");
+    }
+    let block_types = PalettedBlockTypes {
+      palette,
+      bits,
+      words,
+    };
+    if (0..CHUNK_WIDTH_E3).any(|n| block_types.palette_index(n) >= block_types.palette.len()) {
+      return Err("palette index out of range");
+    }
+    Ok(block_types)
+  }
+
+  pub fn get(&self, n: usize) -> BlockType { self.palette[self.palette_index(n)] }
+
+  pub fn set(&mut self, n: usize, block_type: BlockType) {
+    let palette_index = match self.palette.iter().position(|&bt| bt == block_type) {
+      Some(i) => i,
+      None => {
+        self.palette.push(block_type);
+        if self.palette.len() > 1 << self.bits {
+          self.repack(bits_for(self.palette.len()));
+        }
+        self.palette.len() - 1
+      },
+    };
+    if self.bits > 0 {
+      self.set_palette_index(n, palette_index);
+    }
+  }
+
+  pub fn fill(&mut self, block_type: BlockType) { *self = PalettedBlockTypes::new(block_type); }
+
+  /// Drops palette entries that are no longer used, shrinking the indices if
+  /// possible
+  pub fn compact(&mut self) {
+    if self.bits == 0 {
+      return;
+    }
+    let mut used = vec![false; self.palette.len()];
+    for n in 0..CHUNK_WIDTH_E3 {
+      used[self.palette_index(n)] = true;
+    }
+
+    let mut remap = vec![0; self.palette.len()];
+    let mut palette = Vec::new();
+    for (i, &bt) in self.palette.iter().enumerate() {
+      if used[i] {
+        remap[i] = palette.len();
+        palette.push(bt);
+      }
+    }
+
+    let indices: Vec<usize> = (0..CHUNK_WIDTH_E3)
+      .map(|n| remap[self.palette_index(n)])
+      .collect();
+    self.palette = palette;
+    self.pack(bits_for(self.palette.len()), indices);
+  }
+
+  pub fn palette(&self) -> &[BlockType] { &self.palette }
+
+  /// How many bits each index takes up, or zero if every block is the same
+  pub fn bits(&self) -> u8 { self.bits }
+
+  /// Returns which word holds the given index, and where in the word it is
+  fn locate(&self, n: usize) -> (usize, usize) {
+    let bits_log2 = self.bits.trailing_zeros() as usize;
+    let per_word_log2 = 6 - bits_log2;
+    let word = n >> per_word_log2;
+    let shift = (n & ((1 << per_word_log2) - 1)) << bits_log2;
+    (word, shift)
+  }
+
+  fn palette_index(&self, n: usize) -> usize {
+    if self.bits == 0 {
+      return 0;
+    }
+    let (word, shift) = self.locate(n);
+    let mask = (1u64 << self.bits) - 1;
+    ((self.words[word] >> shift) & mask) as usize
+  }
+
+  fn set_palette_index(&mut self, n: usize, palette_index: usize) {
+    let (word, shift) = self.locate(n);
+    let mask = (1u64 << self.bits) - 1;
+    let word = &mut self.words[word];
+    *word = (*word & !(mask << shift)) | ((palette_index as u64) << shift);
+  }
+
+  fn repack(&mut self, bits: u8) {
+    let indices: Vec<usize> = (0..CHUNK_WIDTH_E3).map(|n| self.palette_index(n)).collect();
+    self.pack(bits, indices);
+  }
+
+  fn pack(&mut self, bits: u8, indices: Vec<usize>) {
+    self.bits = bits;
+    if bits == 0 {
+      self.words = Vec::new();
+      return;
+    }
+    self.words = vec![0; CHUNK_WIDTH_E3 * bits as usize / 64];
+    for (n, palette_index) in indices.into_iter().enumerate() {
+      self.set_palette_index(n, palette_index);
+    }
+  }
+}
+
+fn bits_for(palette_len: usize) -> u8 {
+  match palette_len {
+    0..=1 => 0,
+    2 => 1,
+    3..=4 => 2,
+    5..=16 => 4,
+    17..=256 => 8,
+    _ => 16,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::block::{EMPTY, UNKNOWN};
+
+  const COBBLE: BlockType = BlockType(37);
+
+  #[test]
+  fn test_single_value() {
+    let mut p = PalettedBlockTypes::new(EMPTY);
+    assert_eq!(p.get(0), EMPTY);
+    assert_eq!(p.get(CHUNK_WIDTH_E3 - 1), EMPTY);
+
+    p.set(17, EMPTY);
+    assert_eq!(p.bits(), 0);
+    assert!(p.words.is_empty());
+  }
+
+  #[test]
+  fn test_set() {
+    let mut p = PalettedBlockTypes::new(EMPTY);
+    p.set(5, COBBLE);
+    assert_eq!(p.bits(), 1);
+    assert_eq!(p.get(4), EMPTY);
+    assert_eq!(p.get(5), COBBLE);
+    assert_eq!(p.get(6), EMPTY);
+
+    p.set(CHUNK_WIDTH_E3 - 1, UNKNOWN);
+    assert_eq!(p.bits(), 2);
+    assert_eq!(p.get(5), COBBLE);
+    assert_eq!(p.get(CHUNK_WIDTH_E3 - 1), UNKNOWN);
+    assert_eq!(p.get(CHUNK_WIDTH_E3 - 2), EMPTY);
+  }
+
+  #[test]
+  fn test_many_block_types() {
+    let mut p = PalettedBlockTypes::new(EMPTY);
+    for n in 0..1000 {
+      p.set(n * 7, BlockType(100 + n as u16));
+    }
+    assert_eq!(p.bits(), 16);
+    for n in 0..1000 {
+      assert_eq!(p.get(n * 7), BlockType(100 + n as u16));
+      assert_eq!(p.get(n * 7 + 1), EMPTY);
+    }
+  }
+
+  #[test]
+  fn test_fill() {
+    let mut p = PalettedBlockTypes::new(EMPTY);
+    p.set(5, COBBLE);
+    p.fill(UNKNOWN);
+    assert_eq!(p.bits(), 0);
+    assert_eq!(p.palette(), &[UNKNOWN]);
+    assert_eq!(p.get(5), UNKNOWN);
+  }
+
+  #[test]
+  fn test_compact() {
+    let mut p = PalettedBlockTypes::new(EMPTY);
+    p.set(5, COBBLE);
+    p.set(6, UNKNOWN);
+    p.set(5, EMPTY);
+    assert_eq!(p.bits(), 2);
+
+    p.compact();
+    assert_eq!(p.palette(), &[EMPTY, UNKNOWN]);
+    assert_eq!(p.bits(), 1);
+    assert_eq!(p.get(5), EMPTY);
+    assert_eq!(p.get(6), UNKNOWN);
+
+    p.set(6, EMPTY);
+    p.compact();
+    assert_eq!(p.palette(), &[EMPTY]);
+    assert_eq!(p.bits(), 0);
+    assert_eq!(p.get(6), EMPTY);
+  }
+
+  #[test]
+  fn test_deserialize() {
+    let mut p = PalettedBlockTypes::new(EMPTY);
+    p.set(5, COBBLE);
+    p.set(6, UNKNOWN);
+    let round_trip = |p: &PalettedBlockTypes| {
+      bincode::deserialize::<PalettedBlockTypes>(&bincode::serialize(p).unwrap())
+    };
+    assert_eq!(round_trip(&p).unwrap(), p);
+
+    // The palette has 3 entries, but 2 bit indices go up to 3
+    let mut out_of_range = p.clone();
+    out_of_range.words[0] = !0;
+    assert!(round_trip(&out_of_range).is_err());
+
+    let mut short = p.clone();
+    short.words.pop();
+    assert!(round_trip(&short).is_err());
+
+    let mut odd_bits = p.clone();
+    odd_bits.bits = 3;
+    assert!(round_trip(&odd_bits).is_err());
+
+    let mut empty = PalettedBlockTypes::new(EMPTY);
+    empty.palette.clear();
+    assert!(round_trip(&empty).is_err());
+  }
+}
@@ -0,0 +1,254 @@
+use std::{
+  fmt, fs,
+  io::{self, BufReader, BufWriter, Write},
+  path::{Path, PathBuf},
+};
+
+use crate::{
+  block_registry::BlockRegistry,
+  chunk::Chunk,
+  chunk_coord::ChunkCoord,
+  chunk_format::{self, ChunkFormatError},
+  chunk_map::ChunkMap,
+  topology::Topology,
+};
+
+const CHUNK_EXTENSION: &str = "chunk";
+
+#[derive(Debug)]
+pub enum PersistError {
+  Io(io::Error),
+  Format(ChunkFormatError),
+}
+
+impl fmt::Display for PersistError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PersistError::Io(err) => write!(f, "io error: {}", err),
+      PersistError::Format(err) => write!(f, "bad chunk file: {}", err),
+    }
+  }
+}
+
+impl std::error::Error for PersistError {}
+
+impl From<io::Error> for PersistError {
+  fn from(err: io::Error) -> PersistError { PersistError::Io(err) }
+}
+
+/// Saves a world as a directory with one file per chunk, written with
+/// `chunk_format`
+pub struct WorldStore {
+  dir: PathBuf,
+}
+
+impl WorldStore {
+  pub fn new<P: Into<PathBuf>>(dir: P) -> WorldStore { WorldStore { dir: dir.into() } }
+
+  pub fn dir(&self) -> &Path { &self.dir }
+
+  pub fn save(&self, chunk_map: &ChunkMap, registry: &BlockRegistry) -> Result<(), PersistError> {
+    for (coord, loaded_chunk) in chunk_map.iter() {
+      self.save_chunk(coord, loaded_chunk.get(), registry)?;
+    }
+    Ok(())
+  }
+
+  /// Returns `None` if nothing has been saved yet
+  pub fn load(
+    &self,
+    topology: Topology,
+    registry: &BlockRegistry,
+  ) -> Result<Option<ChunkMap>, PersistError> {
+    let entries = match fs::read_dir(&self.dir) {
+      Ok(entries) => entries,
+      Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+      Err(err) => return Err(err.into()),
+    };
+
+    let mut chunk_map = ChunkMap::with_topology(topology);
+    for entry in entries {
+      let path = entry?.path();
+      if let Some(coord) = coord_from_path(&path) {
+        chunk_map.load(coord, read_chunk(&path, registry)?);
+      }
+    }
+
+    if chunk_map.is_empty() {
+      Ok(None)
+    } else {
+      Ok(Some(chunk_map))
+    }
+  }
+
+  pub fn save_chunk(
+    &self,
+    coord: ChunkCoord,
+    chunk: &Chunk,
+    registry: &BlockRegistry,
+  ) -> Result<(), PersistError> {
+    fs::create_dir_all(&self.dir)?;
+    // Written to a temporary file first, so that a crash mid-save can't leave
+    // a truncated chunk behind
+    let path = self.chunk_path(coord);
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(fs::File::create(&tmp_path)?);
+    chunk_format::write(&mut writer, chunk, registry).map_err(PersistError::Format)?;
+    writer.flush()?;
+    fs::rename(&tmp_path, &path)?;
+    Ok(())
+  }
+
+  /// Returns `None` if the chunk hasn't been saved
+  pub fn load_chunk(
+    &self,
+    coord: ChunkCoord,
+    registry: &BlockRegistry,
+  ) -> Result<Option<Chunk>, PersistError> {
+    let path = self.chunk_path(coord);
+    if !path.exists() {
+      return Ok(None);
+    }
+    read_chunk(&path, registry).map(Some)
+  }
+
+  fn chunk_path(&self, coord: ChunkCoord) -> PathBuf {
+    self.dir.join(format!(
+      "{}.{}.{}.{}",
+      coord.x, coord.y, coord.z, CHUNK_EXTENSION
+    ))
+  }
+}
+
+fn coord_from_path(path: &Path) -> Option<ChunkCoord> {
+  if path.extension()? != CHUNK_EXTENSION {
+    return None;
+  }
+  let parts: Vec<i32> = path
+    .file_stem()?
+    .to_str()?
+    .split('.')
+    .map(|part| part.parse().ok())
+    .collect::<Option<_>>()?;
+  match parts[..] {
+    [x, y, z] => Some(ChunkCoord::new(x, y, z)),
+    _ => None,
+  }
+}
+
+fn read_chunk(path: &Path, registry: &BlockRegistry) -> Result<Chunk, PersistError> {
+  let reader = BufReader::new(fs::File::open(path)?);
+  chunk_format::read(reader, registry).map_err(PersistError::Format)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    block::EMPTY,
+    chunk::ChunkStorage,
+    chunk_format::FORMAT_VERSION,
+    chunk_pos::ChunkPos,
+    life::{self, life_registry},
+  };
+
+  fn temp_store(name: &str) -> WorldStore {
+    let dir = std::env::temp_dir().join(format!("lotsa-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    WorldStore::new(dir)
+  }
+
+  fn life_chunk(pos: ChunkPos, registry: &BlockRegistry) -> Chunk {
+    let mut chunk = Chunk::with_storage(ChunkStorage::Paletted);
+    chunk.fill_with_block_type(EMPTY);
+    chunk.set_block_type(pos, life::life_block_type(registry));
+    chunk
+  }
+
+  #[test]
+  fn test_save_and_load() {
+    let store = temp_store("save-and-load");
+    let west = ChunkCoord::new(-1, 0, 0);
+    let east = ChunkCoord::new(0, 0, 3);
+    let registry = life_registry();
+    let life = life::life_block_type(&registry);
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.load(west, life_chunk(ChunkPos::new(1, 2, 3), &registry));
+    chunk_map.load(east, life_chunk(ChunkPos::new(4, 5, 6), &registry));
+
+    store.save(&chunk_map, &registry).unwrap();
+    let loaded = store.load(Topology::Infinite, &registry).unwrap().unwrap();
+
+    assert_eq!(loaded.len(), 2);
+    let west_chunk = loaded.get(west).unwrap().get();
+    assert_eq!(
+      west_chunk.get_block(ChunkPos::new(1, 2, 3)).block_type,
+      life
+    );
+    assert_eq!(
+      west_chunk.get_block(ChunkPos::new(4, 5, 6)).block_type,
+      EMPTY
+    );
+    let east_chunk = loaded.get(east).unwrap().get();
+    assert_eq!(
+      east_chunk.get_block(ChunkPos::new(4, 5, 6)).block_type,
+      life
+    );
+    assert_eq!(east_chunk.storage(), ChunkStorage::Paletted);
+
+    fs::remove_dir_all(store.dir()).unwrap();
+  }
+
+  #[test]
+  fn test_nothing_saved() {
+    let store = temp_store("nothing-saved");
+    let registry = life_registry();
+    assert!(store.load(Topology::Infinite, &registry).unwrap().is_none());
+    assert!(store
+      .load_chunk(ChunkCoord::origin(), &registry)
+      .unwrap()
+      .is_none());
+
+    fs::create_dir_all(store.dir()).unwrap();
+    fs::write(store.dir().join("notes.txt"), "not a chunk").unwrap();
+    assert!(store.load(Topology::Infinite, &registry).unwrap().is_none());
+
+    fs::remove_dir_all(store.dir()).unwrap();
+  }
+
+  #[test]
+  fn test_wrong_version() {
+    let store = temp_store("wrong-version");
+    let registry = life_registry();
+    let pos = ChunkPos::new(0, 0, 0);
+    store
+      .save_chunk(ChunkCoord::origin(), &life_chunk(pos, &registry), &registry)
+      .unwrap();
+
+    // The version comes right after the magic
+    let path = store.chunk_path(ChunkCoord::origin());
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[4] = bytes[4].wrapping_add(1);
+    fs::write(&path, bytes).unwrap();
+
+    match store.load_chunk(ChunkCoord::origin(), &registry) {
+      Err(PersistError::Format(ChunkFormatError::UnknownVersion(version))) => {
+        assert_eq!(version, FORMAT_VERSION + 1)
+      },
+      _ => panic!("expected a version error"),
+    }
+
+    fs::remove_dir_all(store.dir()).unwrap();
+  }
+
+  #[test]
+  fn test_coord_from_path() {
+    assert_eq!(
+      coord_from_path(Path::new("world/-1.20.-300.chunk")),
+      Some(ChunkCoord::new(-1, 20, -300))
+    );
+    assert_eq!(coord_from_path(Path::new("world/1.2.chunk")), None);
+    assert_eq!(coord_from_path(Path::new("world/1.2.3.tmp")), None);
+    assert_eq!(coord_from_path(Path::new("world/a.b.c.chunk")), None);
+  }
+}
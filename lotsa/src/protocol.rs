@@ -0,0 +1,636 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  fmt,
+  io::{Read, Write},
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+  block::BlockType,
+  block_field::FieldId,
+  block_registry::BlockRegistry,
+  chunk::Chunk,
+  chunk_coord::ChunkCoord,
+  chunk_format::{self, ChunkFormatError},
+  chunk_pos::ChunkPos,
+  loaded_chunk::LoadedChunk,
+  mutation::{BlockChange, BlockWrite},
+  world_pos::WorldPos,
+};
+
+/// Bumped whenever the messages below change in a way that old clients or
+/// servers can't read
+pub const PROTOCOL_VERSION: u16 = 5;
+
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+  version: u16,
+  body: T,
+}
+
+#[derive(Debug)]
+pub enum ProtocolError {
+  Malformed(bincode::Error),
+  WrongVersion(u16),
+}
+
+impl fmt::Display for ProtocolError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ProtocolError::Malformed(err) => write!(f, "malformed message: {}", err),
+      ProtocolError::WrongVersion(version) => write!(
+        f,
+        "message has protocol version {}, expected {}",
+        version, PROTOCOL_VERSION
+      ),
+    }
+  }
+}
+
+impl std::error::Error for ProtocolError {}
+
+fn encode<T: Serialize>(body: &T) -> Vec<u8> {
+  let envelope = Envelope {
+    version: PROTOCOL_VERSION,
+    body,
+  };
+  bincode::serialize(&envelope).expect("serialize message")
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ProtocolError> {
+  // The version comes first, so it can be checked before the body is parsed
+  let version: u16 = bincode::deserialize(bytes).map_err(ProtocolError::Malformed)?;
+  if version != PROTOCOL_VERSION {
+    return Err(ProtocolError::WrongVersion(version));
+  }
+  let envelope: Envelope<T> = bincode::deserialize(bytes).map_err(ProtocolError::Malformed)?;
+  Ok(envelope.body)
+}
+
+/// The chunks from `min` to `max` inclusive
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkRegion {
+  pub min: ChunkCoord,
+  pub max: ChunkCoord,
+}
+
+impl ChunkRegion {
+  pub fn single(coord: ChunkCoord) -> ChunkRegion {
+    ChunkRegion {
+      min: coord,
+      max: coord,
+    }
+  }
+
+  pub fn contains(&self, coord: ChunkCoord) -> bool {
+    coord.x >= self.min.x
+      && coord.x <= self.max.x
+      && coord.y >= self.min.y
+      && coord.y <= self.max.y
+      && coord.z >= self.min.z
+      && coord.z <= self.max.z
+  }
+}
+
+/// What clients send to the server
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClientCommand {
+  SetBlock {
+    pos: WorldPos,
+    block_type: BlockType,
+  },
+  Control(SimControl),
+  /// Replaces the chunks the client gets updates for
+  Subscribe(ChunkRegion),
+  Ping {
+    nonce: u32,
+  },
+  /// Asks for a fresh snapshot of a chunk, after the client missed a delta
+  Resync(ChunkCoord),
+}
+
+impl ClientCommand {
+  pub fn encode(&self) -> Vec<u8> { encode(self) }
+
+  pub fn decode(bytes: &[u8]) -> Result<ClientCommand, ProtocolError> { decode(bytes) }
+}
+
+/// Changes to how the server runs the simulation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SimControl {
+  Pause,
+  Resume,
+  /// Pauses, then runs this many more steps, one per tick
+  Step {
+    ticks: u32,
+  },
+  SetTickInterval {
+    millis: u32,
+  },
+}
+
+pub const DEFAULT_TICK_INTERVAL_MS: u32 = 100;
+
+/// Whether and how fast the server is running the simulation. Sent to every
+/// client whenever it changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimState {
+  pub paused: bool,
+  /// Steps still to run before staying paused
+  pub steps_remaining: u32,
+  pub tick_interval_ms: u32,
+}
+
+impl SimState {
+  pub fn new() -> SimState {
+    SimState {
+      paused: false,
+      steps_remaining: 0,
+      tick_interval_ms: DEFAULT_TICK_INTERVAL_MS,
+    }
+  }
+
+  pub fn apply(&mut self, control: SimControl) {
+    match control {
+      SimControl::Pause => self.paused = true,
+      SimControl::Resume => {
+        self.paused = false;
+        self.steps_remaining = 0;
+      },
+      SimControl::Step { ticks } => {
+        self.paused = true;
+        self.steps_remaining = self.steps_remaining.saturating_add(ticks);
+      },
+      // A zero interval would tick as fast as the actor can spin
+      SimControl::SetTickInterval { millis } => self.tick_interval_ms = millis.max(1),
+    }
+  }
+
+  /// Called once per tick. Returns whether to run a step, using up one of the
+  /// remaining steps if paused.
+  pub fn take_step(&mut self) -> bool {
+    if !self.paused {
+      return true;
+    }
+    if self.steps_remaining == 0 {
+      return false;
+    }
+    self.steps_remaining -= 1;
+    true
+  }
+}
+
+impl Default for SimState {
+  fn default() -> SimState { SimState::new() }
+}
+
+/// What the server sends to clients
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ServerEvent {
+  /// Sent first to every client, since chunks and commands refer to the
+  /// game's block types by id
+  Registry(BlockRegistry),
+  Chunk(ChunkUpdate),
+  Pong {
+    nonce: u32,
+  },
+  SimState(SimState),
+}
+
+impl ServerEvent {
+  pub fn encode(&self) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&encode(self)).expect("compress message");
+    encoder.finish().expect("finish compressing message")
+  }
+
+  pub fn decode(bytes: &[u8]) -> Result<ServerEvent, ProtocolError> {
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(bytes)
+      .read_to_end(&mut decompressed)
+      .map_err(|err| ProtocolError::Malformed(err.into()))?;
+    decode(&decompressed)
+  }
+}
+
+/// The state of a chunk, either in full or as changes since the last update.
+/// Each chunk's updates have their own sequence numbers, and a delta with
+/// sequence number n can only be applied on top of update n - 1 for the same
+/// chunk.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ChunkUpdate {
+  Snapshot {
+    seq: u64,
+    coord: ChunkCoord,
+    /// The chunk in `chunk_format`
+    data: Vec<u8>,
+  },
+  Delta {
+    seq: u64,
+    coord: ChunkCoord,
+    /// Block types and field values, so clients see everything updaters write
+    changes: Vec<(ChunkPos, BlockChange)>,
+  },
+}
+
+impl ChunkUpdate {
+  pub fn snapshot(seq: u64, loaded_chunk: &LoadedChunk, registry: &BlockRegistry) -> ChunkUpdate {
+    ChunkUpdate::Snapshot {
+      seq,
+      coord: loaded_chunk.coord(),
+      data: chunk_format::encode(loaded_chunk.get(), registry),
+    }
+  }
+
+  /// The current value of every block type and field in the chunk that was
+  /// written to
+  pub fn delta(seq: u64, loaded_chunk: &LoadedChunk, writes: &[BlockWrite]) -> ChunkUpdate {
+    let coord = loaded_chunk.coord();
+    let chunk = loaded_chunk.get();
+    // Keyed by which field was written, if any, so each one is only sent once
+    let changes: BTreeMap<(ChunkPos, Option<FieldId>), BlockChange> = writes
+      .iter()
+      .filter(|write| write.pos.chunk_coord() == coord)
+      .map(|write| {
+        let pos = write.pos.chunk_pos();
+        match write.change {
+          BlockChange::BlockType(_) => (
+            (pos, None),
+            BlockChange::BlockType(chunk.get_block(pos).block_type()),
+          ),
+          BlockChange::Field(field_id, _) => (
+            (pos, Some(field_id)),
+            BlockChange::Field(field_id, chunk.get_field_raw(pos, field_id)),
+          ),
+        }
+      })
+      .collect();
+    ChunkUpdate::Delta {
+      seq,
+      coord,
+      changes: changes
+        .into_iter()
+        .map(|((pos, _), change)| (pos, change))
+        .collect(),
+    }
+  }
+
+  pub fn seq(&self) -> u64 {
+    match self {
+      ChunkUpdate::Snapshot { seq, .. } | ChunkUpdate::Delta { seq, .. } => *seq,
+    }
+  }
+
+  pub fn coord(&self) -> ChunkCoord {
+    match self {
+      ChunkUpdate::Snapshot { coord, .. } | ChunkUpdate::Delta { coord, .. } => *coord,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeqCheck {
+  Apply,
+  /// Stale or duplicate, or arrived while waiting for a snapshot
+  Ignore,
+  /// A delta was missed, so the client should ask for a resync of the chunk
+  /// and then wait for a snapshot
+  Resync,
+}
+
+/// Tracks sequence numbers for each chunk on the client, to notice missed
+/// deltas
+#[derive(Clone, Debug, Default)]
+pub struct SeqTracker {
+  last_seqs: HashMap<ChunkCoord, u64>,
+}
+
+impl SeqTracker {
+  pub fn new() -> SeqTracker {
+    SeqTracker {
+      last_seqs: HashMap::new(),
+    }
+  }
+
+  pub fn check(&mut self, update: &ChunkUpdate) -> SeqCheck {
+    let coord = update.coord();
+    match (update, self.last_seqs.get(&coord).cloned()) {
+      (ChunkUpdate::Snapshot { seq, .. }, _) => {
+        self.last_seqs.insert(coord, *seq);
+        SeqCheck::Apply
+      },
+      (ChunkUpdate::Delta { .. }, None) => SeqCheck::Ignore,
+      (ChunkUpdate::Delta { seq, .. }, Some(last_seq)) => {
+        if *seq <= last_seq {
+          SeqCheck::Ignore
+        } else if *seq == last_seq + 1 {
+          self.last_seqs.insert(coord, *seq);
+          SeqCheck::Apply
+        } else {
+          self.last_seqs.remove(&coord);
+          SeqCheck::Resync
+        }
+      },
+    }
+  }
+}
+
+/// Applies an update to the client's copy of a chunk
+pub fn apply(
+  update: ChunkUpdate,
+  chunk: &mut Chunk,
+  registry: &BlockRegistry,
+) -> Result<(), ChunkFormatError> {
+  match update {
+    ChunkUpdate::Snapshot { data, .. } => *chunk = chunk_format::decode(&data, registry)?,
+    ChunkUpdate::Delta { changes, .. } => {
+      for (pos, change) in changes {
+        match change {
+          BlockChange::BlockType(block_type) => chunk.set_block_type(pos, block_type),
+          BlockChange::Field(field_id, value) => chunk.set_field_raw(pos, field_id, value),
+        }
+      }
+    },
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    block::EMPTY, block_field::MAX_BLOCK_FIELDS, block_registry::BlockMeta, chunk::ChunkStorage,
+  };
+
+  const COBBLE: BlockType = BlockType(3);
+
+  fn cobble_registry() -> BlockRegistry {
+    let mut registry = BlockRegistry::new();
+    assert_eq!(
+      registry.register(BlockMeta::new("cobble", "#888", 'C')),
+      COBBLE
+    );
+    registry
+  }
+
+  fn cobble_chunk() -> LoadedChunk {
+    let mut chunk = Chunk::with_storage(ChunkStorage::Paletted);
+    chunk.fill_with_block_type(EMPTY);
+    chunk.set_block_type(ChunkPos::new(1, 2, 0), COBBLE);
+    chunk.set_field_raw(ChunkPos::new(1, 2, 0), FieldId::new(1).unwrap(), 9);
+    LoadedChunk::new(ChunkCoord::new(0, 1, 0), chunk)
+  }
+
+  fn write(x: i32, y: i32, block_type: BlockType) -> BlockWrite {
+    BlockWrite {
+      pos: WorldPos::new(x, y, 0),
+      change: BlockChange::BlockType(block_type),
+    }
+  }
+
+  fn write_field(x: i32, y: i32, field_id: u8, value: u16) -> BlockWrite {
+    BlockWrite {
+      pos: WorldPos::new(x, y, 0),
+      change: BlockChange::Field(FieldId::new(field_id).unwrap(), value),
+    }
+  }
+
+  fn delta(seq: u64) -> ChunkUpdate {
+    ChunkUpdate::Delta {
+      seq,
+      coord: ChunkCoord::new(0, 1, 0),
+      changes: Vec::new(),
+    }
+  }
+
+  fn decode_chunk_update(bytes: &[u8]) -> ChunkUpdate {
+    match ServerEvent::decode(bytes).unwrap() {
+      ServerEvent::Chunk(update) => update,
+      _ => panic!("expected a chunk update"),
+    }
+  }
+
+  #[test]
+  fn test_delta() {
+    let loaded_chunk = cobble_chunk();
+    let writes = vec![
+      write(1, 34, EMPTY),
+      write(1, 34, COBBLE),
+      write(0, 32, EMPTY),
+      write_field(1, 34, 1, 5),
+      write_field(1, 34, 1, 9),
+      write(1, 2, COBBLE),
+    ];
+
+    match ChunkUpdate::delta(7, &loaded_chunk, &writes) {
+      ChunkUpdate::Delta {
+        seq,
+        coord,
+        changes,
+      } => {
+        assert_eq!(seq, 7);
+        assert_eq!(coord, ChunkCoord::new(0, 1, 0));
+        assert_eq!(
+          changes,
+          vec![
+            (ChunkPos::new(0, 0, 0), BlockChange::BlockType(EMPTY)),
+            (ChunkPos::new(1, 2, 0), BlockChange::BlockType(COBBLE)),
+            (
+              ChunkPos::new(1, 2, 0),
+              BlockChange::Field(FieldId::new(1).unwrap(), 9)
+            ),
+          ]
+        );
+      },
+      _ => panic!("expected a delta"),
+    }
+  }
+
+  #[test]
+  fn test_encode_and_apply() {
+    let loaded_chunk = cobble_chunk();
+    let registry = cobble_registry();
+    let mut client_chunk = Chunk::new();
+
+    let snapshot = ServerEvent::Chunk(ChunkUpdate::snapshot(3, &loaded_chunk, &registry));
+    let snapshot = decode_chunk_update(&snapshot.encode());
+    assert_eq!(snapshot.seq(), 3);
+    assert_eq!(snapshot.coord(), ChunkCoord::new(0, 1, 0));
+    apply(snapshot, &mut client_chunk, &registry).unwrap();
+    assert_eq!(
+      client_chunk.get_block(ChunkPos::new(1, 2, 0)).block_type(),
+      COBBLE
+    );
+    assert_eq!(
+      client_chunk.get_block(ChunkPos::new(1, 3, 0)).block_type(),
+      EMPTY
+    );
+
+    let delta = ServerEvent::Chunk(ChunkUpdate::Delta {
+      seq: 4,
+      coord: loaded_chunk.coord(),
+      changes: vec![
+        (ChunkPos::new(1, 3, 0), BlockChange::BlockType(COBBLE)),
+        (
+          ChunkPos::new(1, 3, 0),
+          BlockChange::Field(FieldId::new(2).unwrap(), 7),
+        ),
+      ],
+    });
+    apply(
+      decode_chunk_update(&delta.encode()),
+      &mut client_chunk,
+      &registry,
+    )
+    .unwrap();
+    assert_eq!(
+      client_chunk.get_block(ChunkPos::new(1, 3, 0)).block_type(),
+      COBBLE
+    );
+    assert_eq!(
+      client_chunk.get_field_raw(ChunkPos::new(1, 3, 0), FieldId::new(2).unwrap()),
+      7
+    );
+
+    // Field ids past the last one don't decode
+    let last_field = FieldId::new(MAX_BLOCK_FIELDS as u8 - 1).unwrap();
+    let mut bad_field = encode(&ServerEvent::Chunk(ChunkUpdate::Delta {
+      seq: 5,
+      coord: loaded_chunk.coord(),
+      changes: vec![(
+        ChunkPos::new(1, 3, 0),
+        BlockChange::Field(last_field, 0xbeef),
+      )],
+    }));
+    let id_at = bad_field
+      .windows(3)
+      .position(|bytes| bytes == [MAX_BLOCK_FIELDS as u8 - 1, 0xef, 0xbe])
+      .unwrap();
+    bad_field[id_at] = MAX_BLOCK_FIELDS as u8;
+    assert!(decode::<ServerEvent>(&bad_field).is_err());
+  }
+
+  #[test]
+  fn test_registry() {
+    let registry = cobble_registry();
+    match ServerEvent::decode(&ServerEvent::Registry(registry.clone()).encode()).unwrap() {
+      ServerEvent::Registry(decoded) => {
+        assert_eq!(decoded, registry);
+        assert_eq!(decoded.lookup("cobble"), Some(COBBLE));
+      },
+      _ => panic!("expected a registry"),
+    }
+  }
+
+  #[test]
+  fn test_seq_tracker() {
+    let loaded_chunk = cobble_chunk();
+    let registry = cobble_registry();
+    let mut tracker = SeqTracker::new();
+
+    assert_eq!(tracker.check(&delta(4)), SeqCheck::Ignore);
+    assert_eq!(
+      tracker.check(&ChunkUpdate::snapshot(4, &loaded_chunk, &registry)),
+      SeqCheck::Apply
+    );
+    assert_eq!(tracker.check(&delta(5)), SeqCheck::Apply);
+    assert_eq!(tracker.check(&delta(5)), SeqCheck::Ignore);
+    assert_eq!(tracker.check(&delta(6)), SeqCheck::Apply);
+
+    // Missing delta 7
+    assert_eq!(tracker.check(&delta(8)), SeqCheck::Resync);
+    assert_eq!(tracker.check(&delta(9)), SeqCheck::Ignore);
+    assert_eq!(
+      tracker.check(&ChunkUpdate::snapshot(9, &loaded_chunk, &registry)),
+      SeqCheck::Apply
+    );
+    assert_eq!(tracker.check(&delta(10)), SeqCheck::Apply);
+
+    // Other chunks are tracked separately
+    let other_delta = ChunkUpdate::Delta {
+      seq: 11,
+      coord: ChunkCoord::origin(),
+      changes: Vec::new(),
+    };
+    assert_eq!(tracker.check(&other_delta), SeqCheck::Ignore);
+    assert_eq!(tracker.check(&delta(11)), SeqCheck::Apply);
+  }
+
+  #[test]
+  fn test_client_commands() {
+    let commands = vec![
+      ClientCommand::SetBlock {
+        pos: WorldPos::new(-3, 4, 5),
+        block_type: COBBLE,
+      },
+      ClientCommand::Control(SimControl::Pause),
+      ClientCommand::Control(SimControl::Resume),
+      ClientCommand::Control(SimControl::Step { ticks: 3 }),
+      ClientCommand::Control(SimControl::SetTickInterval { millis: 250 }),
+      ClientCommand::Subscribe(ChunkRegion {
+        min: ChunkCoord::new(-1, -1, 0),
+        max: ChunkCoord::new(1, 1, 0),
+      }),
+      ClientCommand::Ping { nonce: 42 },
+      ClientCommand::Resync(ChunkCoord::origin()),
+    ];
+    for command in commands {
+      assert_eq!(ClientCommand::decode(&command.encode()).unwrap(), command);
+    }
+  }
+
+  #[test]
+  fn test_wrong_version() {
+    let mut bytes = ClientCommand::Control(SimControl::Pause).encode();
+    bytes[0] = bytes[0].wrapping_add(1);
+    match ClientCommand::decode(&bytes) {
+      Err(ProtocolError::WrongVersion(version)) => assert_eq!(version, PROTOCOL_VERSION + 1),
+      _ => panic!("expected a version error"),
+    }
+
+    assert!(ClientCommand::decode(&[]).is_err());
+  }
+
+  #[test]
+  fn test_sim_state() {
+    let mut state = SimState::new();
+    assert!(state.take_step());
+
+    state.apply(SimControl::Pause);
+    assert!(!state.take_step());
+
+    state.apply(SimControl::Step { ticks: 2 });
+    assert!(state.paused);
+    assert!(state.take_step());
+    assert!(state.take_step());
+    assert!(!state.take_step());
+
+    state.apply(SimControl::Step { ticks: 5 });
+    state.apply(SimControl::Resume);
+    assert_eq!(state.steps_remaining, 0);
+    assert!(state.take_step());
+
+    state.apply(SimControl::SetTickInterval { millis: 0 });
+    assert_eq!(state.tick_interval_ms, 1);
+
+    match ServerEvent::decode(&ServerEvent::SimState(state).encode()).unwrap() {
+      ServerEvent::SimState(decoded) => assert_eq!(decoded, state),
+      _ => panic!("expected sim state"),
+    }
+  }
+
+  #[test]
+  fn test_chunk_region() {
+    let region = ChunkRegion {
+      min: ChunkCoord::new(-1, 0, 0),
+      max: ChunkCoord::new(1, 2, 0),
+    };
+    assert!(region.contains(ChunkCoord::new(-1, 2, 0)));
+    assert!(region.contains(ChunkCoord::new(1, 0, 0)));
+    assert!(!region.contains(ChunkCoord::new(2, 0, 0)));
+    assert!(!region.contains(ChunkCoord::new(0, 0, 1)));
+    assert!(ChunkRegion::single(ChunkCoord::origin()).contains(ChunkCoord::origin()));
+  }
+}
@@ -1,43 +1,97 @@
 use std::{
-  cmp::max,
-  collections::hash_map::DefaultHasher,
+  cmp::{max, min},
+  collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet},
   hash::{Hash, Hasher},
+  iter::FromIterator,
   marker::PhantomData,
 };
 
-use crate::{block::BlockType, relative_pos::RelativePos, unique_descrip::UniqueDescrip};
+use crate::{
+  block::BlockType,
+  block_field::{BlockField, FieldId, FieldValue, MAX_BLOCK_FIELDS},
+  chunk::CHUNK_WIDTH,
+  relative_pos::RelativePos,
+  unique_descrip::UniqueDescrip,
+};
+
+mod aggregate;
+pub use aggregate::*;
+
+mod arithmetic;
+pub use arithmetic::*;
+
+mod at;
+pub use at::*;
+
+mod boxed;
+pub use boxed::*;
 
 mod chebyshev_2d_neighbors;
 pub use chebyshev_2d_neighbors::*;
 
+mod chebyshev_3d_neighbors;
+pub use chebyshev_3d_neighbors::*;
+
+mod comparison;
+pub use comparison::*;
+
 mod constant;
 pub use constant::*;
 
 mod equals;
 pub use equals::*;
 
+mod get_block_field;
+pub use get_block_field::*;
+
 mod get_block_type;
 pub use get_block_type::*;
 
+mod logic;
+pub use logic::*;
+
+mod offset_neighbors;
+pub use offset_neighbors::*;
+
+mod von_neumann_neighbors;
+pub use von_neumann_neighbors::*;
+
 pub trait Context {
   fn get_block(&self, pos: RelativePos) -> BlockInfo;
 }
 
 pub trait GenericQuery: UniqueDescrip {
-  fn cacheability(&self) -> Cacheability;
+  fn cacheability(&self) -> CacheabilitySet;
 }
 
 pub trait Query<'a, T: 'a>: GenericQuery + Clone {
   fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> T;
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockInfo {
   pub block_type: BlockType,
+  pub fields: [u16; MAX_BLOCK_FIELDS],
 }
 
 impl BlockInfo {
+  pub fn new(block_type: BlockType) -> BlockInfo {
+    BlockInfo {
+      block_type,
+      fields: [0; MAX_BLOCK_FIELDS],
+    }
+  }
+
   pub fn block_type(&self) -> BlockType { self.block_type }
+
+  /// Like `Chunk::get_field_raw`, ids past `MAX_BLOCK_FIELDS` read as zero
+  pub fn field_raw(&self, field_id: FieldId) -> u16 {
+    self.fields.get(field_id.index()).cloned().unwrap_or(0)
+  }
+
+  pub fn field<T: FieldValue>(&self, field: BlockField<T>) -> T {
+    T::from_raw(self.field_raw(field.id()))
+  }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -51,13 +105,126 @@ pub enum Cacheability {
     fields: Vec<CacheableField>,
     distance: u8,
   },
+  UntilChangeInVonNeumannNeighborhood {
+    fields: Vec<CacheableField>,
+    distance: u8,
+  },
+  /// The offsets are sorted, and the block at `pos` reads `pos + offset`
+  UntilChangeInOffsets {
+    fields: Vec<CacheableField>,
+    offsets: Vec<RelativePos>,
+  },
 }
 
 use Cacheability::*;
 
+/// A shape of positions around a block, always in 3D
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Neighborhood {
+  /// Every position that is within `distance` along each axis, i.e. a cube
+  Chebyshev(u8),
+  /// Every position within a Manhattan distance of `distance`
+  VonNeumann(u8),
+  /// Exactly these offsets, sorted
+  Offsets(Vec<RelativePos>),
+}
+
+impl Neighborhood {
+  pub fn offsets(offsets: &[RelativePos]) -> Neighborhood {
+    let mut offsets = offsets.to_vec();
+    offsets.sort_unstable();
+    offsets.dedup();
+    Neighborhood::Offsets(offsets)
+  }
+
+  pub fn contains(&self, offset: RelativePos) -> bool {
+    match self {
+      Neighborhood::Chebyshev(distance) => chebyshev_length(offset) <= *distance,
+      Neighborhood::VonNeumann(distance) => manhattan_length(offset) <= u16::from(*distance),
+      Neighborhood::Offsets(offsets) => offsets.binary_search(&offset).is_ok(),
+    }
+  }
+
+  /// How far the neighborhood reaches along any one axis
+  pub fn chebyshev_radius(&self) -> u8 {
+    match self {
+      Neighborhood::Chebyshev(distance) | Neighborhood::VonNeumann(distance) => *distance,
+      Neighborhood::Offsets(offsets) => offsets
+        .iter()
+        .cloned()
+        .map(chebyshev_length)
+        .max()
+        .unwrap_or(0),
+    }
+  }
+
+  /// Every position that can be reached by an offset in `self` followed by
+  /// one in `other`. Sums that don't make one of the shapes are rounded up to
+  /// one that covers them.
+  pub fn plus(&self, other: &Neighborhood) -> Neighborhood {
+    use Neighborhood::*;
+    match (self, other) {
+      (Chebyshev(0), _) => other.clone(),
+      (_, Chebyshev(0)) => self.clone(),
+      (Chebyshev(a), Chebyshev(b)) => chebyshev(u16::from(*a) + u16::from(*b)),
+      (VonNeumann(a), VonNeumann(b)) => von_neumann(u16::from(*a) + u16::from(*b)),
+      (Offsets(a), Offsets(b)) => {
+        let sums: Option<Vec<RelativePos>> = a
+          .iter()
+          .flat_map(|a| b.iter().map(move |b| a.checked_add(*b)))
+          .collect();
+        match sums {
+          Some(sums) => Neighborhood::offsets(&sums),
+          None => Chebyshev(MAX_NEIGHBORHOOD_DISTANCE),
+        }
+      },
+      (VonNeumann(distance), Offsets(offsets)) | (Offsets(offsets), VonNeumann(distance)) => {
+        von_neumann(
+          u16::from(*distance)
+            + offsets
+              .iter()
+              .cloned()
+              .map(manhattan_length)
+              .max()
+              .unwrap_or(0),
+        )
+      },
+      _ => chebyshev(u16::from(self.chebyshev_radius()) + u16::from(other.chebyshev_radius())),
+    }
+  }
+}
+
+/// The furthest any neighborhood reaches along one axis, which keeps every
+/// offset within the range `RelativePos::new` allows. Sums past this saturate.
+pub const MAX_NEIGHBORHOOD_DISTANCE: u8 = CHUNK_WIDTH - 1;
+
+fn chebyshev(distance: u16) -> Neighborhood {
+  Neighborhood::Chebyshev(min(distance, u16::from(MAX_NEIGHBORHOOD_DISTANCE)) as u8)
+}
+
+/// Falls back to the largest cube, which holds every offset that fits
+fn von_neumann(distance: u16) -> Neighborhood {
+  if distance <= u16::from(MAX_NEIGHBORHOOD_DISTANCE) {
+    Neighborhood::VonNeumann(distance as u8)
+  } else {
+    Neighborhood::Chebyshev(MAX_NEIGHBORHOOD_DISTANCE)
+  }
+}
+
+fn chebyshev_length(offset: RelativePos) -> u8 {
+  let abs = |c: i8| i16::from(c).abs();
+  max(max(abs(offset.x), abs(offset.y)), abs(offset.z)) as u8
+}
+
+fn manhattan_length(offset: RelativePos) -> u16 {
+  let abs = |c: i8| i16::from(c).abs();
+  (abs(offset.x) + abs(offset.y) + abs(offset.z)) as u16
+}
+
 #[derive(Copy, Clone, Debug, Hash, PartialOrd, Ord, PartialEq, Eq)]
 pub enum CacheableField {
   CacheableBlockType,
+  CacheableBlockField(FieldId),
 }
 
 use CacheableField::*;
@@ -65,43 +232,39 @@ use CacheableField::*;
 const NO_FIELDS: &[CacheableField] = &[];
 const ALL_FIELDS: &[CacheableField] = &[CacheableBlockType];
 
-impl CacheableField {
-  fn merge(a: &[CacheableField], b: &[CacheableField]) -> Vec<CacheableField> {
-    let mut new_fields = Vec::new();
-    new_fields.extend_from_slice(a);
-    new_fields.extend_from_slice(b);
-    new_fields.sort_unstable();
-    new_fields.dedup();
-    new_fields
-  }
-}
-
 impl Cacheability {
-  pub fn merge(a: &Cacheability, b: &Cacheability) -> Cacheability {
-    match (a, b) {
-      (DontCache, _) => DontCache,
-      (_, DontCache) => DontCache,
-      (Forever, _) => b.clone(),
-      (_, Forever) => a.clone(),
-      (UntilChangeInSelf { fields: fields_a }, UntilChangeInSelf { fields: fields_b }) => {
-        UntilChangeInSelf {
-          fields: CacheableField::merge(fields_a, fields_b),
-        }
-      },
-      // TODO: This might be non-optimal in some cases, could be better if we
-      // had a Cacheability variant that represented a set, e.g. Vec<Cacheability>,
-      // or if we generally held on to sets of Cacheability rather than singles
-      (_, _) => UntilChangeInChebyshevNeighborhood {
-        distance: max(a.distance(), b.distance()),
-        fields: CacheableField::merge(a.fields(), b.fields()),
+  /// Watches the fields in every block of the neighborhood
+  pub fn until_change_in(fields: Vec<CacheableField>, neighborhood: Neighborhood) -> Cacheability {
+    match neighborhood {
+      Neighborhood::Chebyshev(0) => UntilChangeInSelf { fields },
+      Neighborhood::Chebyshev(distance) => UntilChangeInChebyshevNeighborhood { fields, distance },
+      Neighborhood::VonNeumann(distance) => {
+        UntilChangeInVonNeumannNeighborhood { fields, distance }
       },
+      Neighborhood::Offsets(offsets) => UntilChangeInOffsets { fields, offsets },
     }
   }
 
+  /// How far away the fields are read from, along any one axis
   pub fn distance(&self) -> u8 {
+    self
+      .neighborhood()
+      .map_or(0, |neighborhood| neighborhood.chebyshev_radius())
+  }
+
+  /// Where the fields are read from, which is `None` for `DontCache` and
+  /// `Forever`
+  pub fn neighborhood(&self) -> Option<Neighborhood> {
     match self {
-      UntilChangeInChebyshevNeighborhood { distance, .. } => *distance,
-      _ => 0,
+      DontCache | Forever => None,
+      UntilChangeInSelf { .. } => Some(Neighborhood::Chebyshev(0)),
+      UntilChangeInChebyshevNeighborhood { distance, .. } => {
+        Some(Neighborhood::Chebyshev(*distance))
+      },
+      UntilChangeInVonNeumannNeighborhood { distance, .. } => {
+        Some(Neighborhood::VonNeumann(*distance))
+      },
+      UntilChangeInOffsets { offsets, .. } => Some(Neighborhood::Offsets(offsets.clone())),
     }
   }
 
@@ -111,6 +274,8 @@ impl Cacheability {
       Forever => NO_FIELDS,
       UntilChangeInSelf { fields } => &fields,
       UntilChangeInChebyshevNeighborhood { fields, .. } => &fields,
+      UntilChangeInVonNeumannNeighborhood { fields, .. } => &fields,
+      UntilChangeInOffsets { fields, .. } => &fields,
     }
   }
 
@@ -121,24 +286,322 @@ impl Cacheability {
   }
 }
 
+/// Everything a cached query result depends on, as a normalized set of
+/// cacheabilities. Unlike merging them into a single cacheability, nothing is
+/// lost: a query that reads its own field and its neighbors' block types is
+/// only re-evaluated when the field changes in that block, not anywhere in the
+/// neighborhood.
+///
+/// Normalized so that equal requirements make equal sets: each field is only
+/// watched at the largest distance it is read from, fields watched at the
+/// same distance share a cacheability, and `Forever` and `DontCache` only
+/// ever appear alone.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheabilitySet {
+  cacheabilities: Vec<Cacheability>,
+}
+
+impl CacheabilitySet {
+  /// The set for a query that reads nothing
+  pub fn new() -> CacheabilitySet {
+    CacheabilitySet {
+      cacheabilities: vec![Forever],
+    }
+  }
+
+  pub fn union(a: &CacheabilitySet, b: &CacheabilitySet) -> CacheabilitySet {
+    a.iter().chain(b.iter()).cloned().collect()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &Cacheability> { self.cacheabilities.iter() }
+
+  pub fn is_dont_cache(&self) -> bool { self.cacheabilities[0] == DontCache }
+
+  /// For a query that evaluates another query with this set at each position
+  /// of a neighborhood
+  pub fn widen(&self, neighborhood: &Neighborhood) -> CacheabilitySet {
+    self
+      .iter()
+      .map(|cacheability| match cacheability.neighborhood() {
+        None => cacheability.clone(),
+        Some(read) => {
+          Cacheability::until_change_in(cacheability.fields().to_vec(), neighborhood.plus(&read))
+        },
+      })
+      .collect()
+  }
+}
+
+impl Default for CacheabilitySet {
+  fn default() -> CacheabilitySet { CacheabilitySet::new() }
+}
+
+impl FromIterator<Cacheability> for CacheabilitySet {
+  fn from_iter<I: IntoIterator<Item = Cacheability>>(iter: I) -> CacheabilitySet {
+    let mut footprints: BTreeMap<CacheableField, Footprint> = BTreeMap::new();
+    for cacheability in iter {
+      match cacheability.neighborhood() {
+        None if cacheability == DontCache => {
+          return CacheabilitySet {
+            cacheabilities: vec![DontCache],
+          }
+        },
+        None => (),
+        Some(neighborhood) => {
+          for &field in cacheability.fields() {
+            footprints.entry(field).or_default().add(&neighborhood);
+          }
+        },
+      }
+    }
+
+    let mut by_neighborhood: BTreeMap<Neighborhood, Vec<CacheableField>> = BTreeMap::new();
+    for (field, footprint) in footprints {
+      for neighborhood in footprint.into_neighborhoods() {
+        by_neighborhood.entry(neighborhood).or_default().push(field);
+      }
+    }
+    let cacheabilities: Vec<Cacheability> = by_neighborhood
+      .into_iter()
+      .map(|(neighborhood, fields)| Cacheability::until_change_in(fields, neighborhood))
+      .collect();
+
+    if cacheabilities.is_empty() {
+      CacheabilitySet::new()
+    } else {
+      CacheabilitySet { cacheabilities }
+    }
+  }
+}
+
+/// Everywhere that one field is read from
+#[derive(Default)]
+struct Footprint {
+  chebyshev: Option<u8>,
+  von_neumann: Option<u8>,
+  offsets: BTreeSet<RelativePos>,
+}
+
+impl Footprint {
+  fn add(&mut self, neighborhood: &Neighborhood) {
+    match neighborhood {
+      Neighborhood::Chebyshev(distance) => {
+        self.chebyshev = max(self.chebyshev, Some(*distance));
+      },
+      Neighborhood::VonNeumann(distance) => {
+        self.von_neumann = max(self.von_neumann, Some(*distance));
+      },
+      Neighborhood::Offsets(offsets) => self.offsets.extend(offsets),
+    }
+  }
+
+  /// The fewest neighborhoods that cover the footprint, dropping any that
+  /// are inside another
+  fn into_neighborhoods(self) -> Vec<Neighborhood> {
+    let mut chebyshev = self.chebyshev.map(Neighborhood::Chebyshev);
+    let mut von_neumann = self.von_neumann.map(Neighborhood::VonNeumann);
+    if let (Some(Neighborhood::Chebyshev(c)), Some(Neighborhood::VonNeumann(v))) =
+      (&chebyshev, &von_neumann)
+    {
+      if v <= c {
+        von_neumann = None;
+      } else if 3 * (*c as u16) <= *v as u16 {
+        chebyshev = None;
+      }
+    }
+    let offsets: Vec<RelativePos> = self
+      .offsets
+      .into_iter()
+      .filter(|&offset| {
+        !chebyshev
+          .iter()
+          .chain(von_neumann.iter())
+          .any(|neighborhood| neighborhood.contains(offset))
+      })
+      .collect();
+    // Only reading the block itself is the same as `UntilChangeInSelf`
+    if offsets == [RelativePos::here()] {
+      return vec![Neighborhood::Chebyshev(0)];
+    }
+
+    let mut neighborhoods: Vec<Neighborhood> = chebyshev.into_iter().chain(von_neumann).collect();
+    if !offsets.is_empty() {
+      neighborhoods.push(Neighborhood::Offsets(offsets));
+    }
+    neighborhoods
+  }
+}
+
+impl From<Cacheability> for CacheabilitySet {
+  fn from(cacheability: Cacheability) -> CacheabilitySet {
+    Some(cacheability).into_iter().collect()
+  }
+}
+
+impl PartialEq<Cacheability> for CacheabilitySet {
+  fn eq(&self, other: &Cacheability) -> bool { *self == CacheabilitySet::from(other.clone()) }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
   use crate::block::UNKNOWN;
 
   pub const COBBLE: BlockType = BlockType(37);
+  pub const AGE: BlockField<u8> = BlockField::new(2);
 
   pub struct TestContext {}
 
   impl Context for TestContext {
     fn get_block(&self, pos: RelativePos) -> BlockInfo {
       if pos.x == 0 && pos.y == 0 && pos.z == 0 {
-        BlockInfo { block_type: COBBLE }
+        let mut block = BlockInfo::new(COBBLE);
+        block.fields[AGE.id().index()] = 5;
+        block
       } else {
-        BlockInfo {
-          block_type: UNKNOWN,
-        }
+        BlockInfo::new(UNKNOWN)
       }
     }
   }
+
+  #[test]
+  fn test_cacheability_set() {
+    let age = CacheableBlockField(AGE.id());
+    let reads_age = UntilChangeInSelf { fields: vec![age] };
+    let reads_neighbor_types = UntilChangeInChebyshevNeighborhood {
+      fields: vec![CacheableBlockType],
+      distance: 1,
+    };
+
+    assert_eq!(CacheabilitySet::new(), Forever);
+    assert_eq!(
+      CacheabilitySet::union(&Forever.into(), &reads_age.clone().into()),
+      reads_age
+    );
+    assert_eq!(
+      CacheabilitySet::union(&reads_age.clone().into(), &DontCache.into()),
+      DontCache
+    );
+
+    let mixed: CacheabilitySet = vec![reads_neighbor_types.clone(), reads_age.clone()]
+      .into_iter()
+      .collect();
+    assert_eq!(
+      mixed.iter().cloned().collect::<Vec<_>>(),
+      vec![reads_age.clone(), reads_neighbor_types.clone()]
+    );
+    assert_eq!(
+      mixed
+        .widen(&Neighborhood::Chebyshev(2))
+        .iter()
+        .cloned()
+        .collect::<Vec<_>>(),
+      vec![
+        UntilChangeInChebyshevNeighborhood {
+          fields: vec![age],
+          distance: 2,
+        },
+        UntilChangeInChebyshevNeighborhood {
+          fields: vec![CacheableBlockType],
+          distance: 3,
+        },
+      ]
+    );
+
+    // A field read from further away covers reading it from closer
+    let covered: CacheabilitySet = vec![
+      UntilChangeInSelf {
+        fields: vec![CacheableBlockType, age],
+      },
+      reads_neighbor_types.clone(),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(covered, mixed);
+  }
+
+  #[test]
+  fn test_cacheability_set_neighborhoods() {
+    let reads =
+      |neighborhood| Cacheability::until_change_in(vec![CacheableBlockType], neighborhood);
+    let set = |neighborhoods: Vec<Neighborhood>| -> Vec<Cacheability> {
+      let set: CacheabilitySet = neighborhoods.into_iter().map(reads).collect();
+      set.iter().cloned().collect()
+    };
+    let below = RelativePos::new(0, 0, -1);
+    let far_below = RelativePos::new(0, 0, -3);
+
+    // Smaller shapes inside bigger ones are dropped
+    assert_eq!(
+      set(vec![
+        Neighborhood::Chebyshev(1),
+        Neighborhood::VonNeumann(1)
+      ]),
+      vec![reads(Neighborhood::Chebyshev(1))]
+    );
+    assert_eq!(
+      set(vec![
+        Neighborhood::Chebyshev(1),
+        Neighborhood::VonNeumann(3)
+      ]),
+      vec![reads(Neighborhood::VonNeumann(3))]
+    );
+    assert_eq!(
+      set(vec![
+        Neighborhood::Chebyshev(1),
+        Neighborhood::VonNeumann(2)
+      ]),
+      vec![
+        reads(Neighborhood::Chebyshev(1)),
+        reads(Neighborhood::VonNeumann(2))
+      ]
+    );
+    assert_eq!(
+      set(vec![
+        Neighborhood::offsets(&[below, far_below]),
+        Neighborhood::VonNeumann(1)
+      ]),
+      vec![
+        reads(Neighborhood::VonNeumann(1)),
+        reads(Neighborhood::offsets(&[far_below]))
+      ]
+    );
+    assert_eq!(
+      set(vec![Neighborhood::offsets(&[RelativePos::here()])]),
+      vec![UntilChangeInSelf {
+        fields: vec![CacheableBlockType]
+      }]
+    );
+  }
+
+  #[test]
+  fn test_neighborhood_plus_saturates() {
+    use Neighborhood::*;
+    let far = RelativePos::new(20, 0, 0);
+
+    assert_eq!(Chebyshev(2).plus(&VonNeumann(1)), Chebyshev(3));
+    assert_eq!(VonNeumann(2).plus(&VonNeumann(1)), VonNeumann(3));
+    assert_eq!(VonNeumann(10).plus(&VonNeumann(21)), VonNeumann(31));
+    assert_eq!(
+      Chebyshev(20).plus(&Chebyshev(20)),
+      Chebyshev(MAX_NEIGHBORHOOD_DISTANCE)
+    );
+    assert_eq!(
+      VonNeumann(20).plus(&VonNeumann(20)),
+      Chebyshev(MAX_NEIGHBORHOOD_DISTANCE)
+    );
+    assert_eq!(
+      Neighborhood::offsets(&[RelativePos::new(15, 0, 0)])
+        .plus(&Neighborhood::offsets(&[RelativePos::new(16, 0, 0)])),
+      Neighborhood::offsets(&[RelativePos::new(31, 0, 0)])
+    );
+    assert_eq!(
+      Neighborhood::offsets(&[far]).plus(&Neighborhood::offsets(&[far])),
+      Chebyshev(MAX_NEIGHBORHOOD_DISTANCE)
+    );
+    assert_eq!(
+      VonNeumann(20).plus(&Neighborhood::offsets(&[far])),
+      Chebyshev(MAX_NEIGHBORHOOD_DISTANCE)
+    );
+  }
 }
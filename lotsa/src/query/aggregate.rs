@@ -0,0 +1,255 @@
+use crate::{query::*, relative_pos::*, unique_descrip::UniqueDescrip};
+
+// Queries that boil an iterator-valued query, such as a neighborhood, down to
+// a single value. They read exactly what the inner query reads.
+
+/// How many of the values are true
+#[derive(Clone)]
+pub struct Count<E> {
+  expr: E,
+}
+
+impl<'a, E: 'a> Count<E>
+where
+  E: Query<'a, Box<dyn Iterator<Item = bool> + 'a>>,
+{
+  pub fn new(expr: &E) -> Count<E> { Count { expr: expr.clone() } }
+}
+
+impl<E> UniqueDescrip for Count<E>
+where
+  E: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String { format!("Count( {} )", self.expr.unique_descrip()) }
+}
+
+impl<E> GenericQuery for Count<E>
+where
+  E: GenericQuery,
+{
+  fn cacheability(&self) -> CacheabilitySet { self.expr.cacheability() }
+}
+
+impl<'a, E: 'a> Query<'a, u32> for Count<E>
+where
+  E: Query<'a, Box<dyn Iterator<Item = bool> + 'a>>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> u32 {
+    self.expr.eval(n, pos).filter(|&value| value).count() as u32
+  }
+}
+
+/// The total of the values, saturating like `Add`
+pub struct Sum<T, E> {
+  expr: E,
+  _phantom: PhantomData<T>,
+}
+
+impl<'a, T: 'a, E: 'a> Sum<T, E>
+where
+  T: Saturating,
+  E: Query<'a, Box<dyn Iterator<Item = T> + 'a>>,
+{
+  pub fn new(expr: &E) -> Sum<T, E> {
+    Sum {
+      expr: expr.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<T, E> UniqueDescrip for Sum<T, E>
+where
+  E: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String { format!("Sum( {} )", self.expr.unique_descrip()) }
+}
+
+impl<T, E> GenericQuery for Sum<T, E>
+where
+  E: GenericQuery,
+{
+  fn cacheability(&self) -> CacheabilitySet { self.expr.cacheability() }
+}
+
+impl<'a, T: 'a, E: 'a> Query<'a, T> for Sum<T, E>
+where
+  T: Saturating,
+  E: Query<'a, Box<dyn Iterator<Item = T> + 'a>>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> T {
+    self
+      .expr
+      .eval(n, pos)
+      .fold(T::default(), Saturating::saturating_add)
+  }
+}
+
+impl<T, E> Clone for Sum<T, E>
+where
+  E: Clone,
+{
+  fn clone(self: &Self) -> Self {
+    Sum {
+      expr: self.expr.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+/// Whether any of the values are true, which is false if there are none
+#[derive(Clone)]
+pub struct Any<E> {
+  expr: E,
+}
+
+impl<'a, E: 'a> Any<E>
+where
+  E: Query<'a, Box<dyn Iterator<Item = bool> + 'a>>,
+{
+  pub fn new(expr: &E) -> Any<E> { Any { expr: expr.clone() } }
+}
+
+impl<E> UniqueDescrip for Any<E>
+where
+  E: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String { format!("Any( {} )", self.expr.unique_descrip()) }
+}
+
+impl<E> GenericQuery for Any<E>
+where
+  E: GenericQuery,
+{
+  fn cacheability(&self) -> CacheabilitySet { self.expr.cacheability() }
+}
+
+impl<'a, E: 'a> Query<'a, bool> for Any<E>
+where
+  E: Query<'a, Box<dyn Iterator<Item = bool> + 'a>>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> bool {
+    self.expr.eval(n, pos).any(|value| value)
+  }
+}
+
+/// Whether all of the values are true, which is true if there are none
+#[derive(Clone)]
+pub struct All<E> {
+  expr: E,
+}
+
+impl<'a, E: 'a> All<E>
+where
+  E: Query<'a, Box<dyn Iterator<Item = bool> + 'a>>,
+{
+  pub fn new(expr: &E) -> All<E> { All { expr: expr.clone() } }
+}
+
+impl<E> UniqueDescrip for All<E>
+where
+  E: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String { format!("All( {} )", self.expr.unique_descrip()) }
+}
+
+impl<E> GenericQuery for All<E>
+where
+  E: GenericQuery,
+{
+  fn cacheability(&self) -> CacheabilitySet { self.expr.cacheability() }
+}
+
+impl<'a, E: 'a> Query<'a, bool> for All<E>
+where
+  E: Query<'a, Box<dyn Iterator<Item = bool> + 'a>>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> bool {
+    self.expr.eval(n, pos).all(|value| value)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::query::tests::{TestContext, AGE, COBBLE};
+
+  #[test]
+  fn test_count() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+    let far_away = RelativePos::new(5, 5, 0);
+
+    let is_cobble = Equals::new(&GetBlockType::new(), &Constant::new(COBBLE));
+    let cobble_neighbors = Count::new(&Chebyshev2DNeighbors::new(1, &is_cobble));
+    assert_eq!(cobble_neighbors.eval(&context, origin), 1);
+    assert_eq!(cobble_neighbors.eval(&context, far_away), 0);
+    assert_eq!(
+      Count::new(&Chebyshev2DNeighbors::new(1, &Not::new(&is_cobble))).eval(&context, origin),
+      8
+    );
+    assert_eq!(
+      cobble_neighbors.cacheability(),
+      UntilChangeInChebyshevNeighborhood {
+        distance: 1,
+        fields: vec![CacheableBlockType]
+      }
+    );
+  }
+
+  #[test]
+  fn test_sum() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+    let west = RelativePos::new(-1, 0, 0);
+
+    let neighbor_ages: Sum<u8, _> =
+      Sum::new(&VonNeumannNeighbors::new_2d(1, &GetBlockField::new(AGE)));
+    assert_eq!(neighbor_ages.eval(&context, origin), 5);
+    assert_eq!(neighbor_ages.eval(&context, west), 5);
+    assert_eq!(
+      Sum::new(&VonNeumannNeighbors::new_2d(1, &Constant::new(2u8))).eval(&context, origin),
+      10
+    );
+    assert_eq!(
+      Sum::new(&VonNeumannNeighbors::new_2d(1, &Constant::new(100u8))).eval(&context, origin),
+      255
+    );
+    assert_eq!(
+      neighbor_ages.cacheability(),
+      UntilChangeInVonNeumannNeighborhood {
+        distance: 1,
+        fields: vec![CacheableBlockField(AGE.id())]
+      }
+    );
+  }
+
+  #[test]
+  fn test_any_and_all() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+    let far_away = RelativePos::new(5, 5, 0);
+
+    let is_cobble = Equals::new(&GetBlockType::new(), &Constant::new(COBBLE));
+    let near_cobble = Any::new(&Chebyshev2DNeighbors::new(1, &is_cobble));
+    assert!(near_cobble.eval(&context, origin));
+    assert!(!near_cobble.eval(&context, far_away));
+    assert!(!Any::new(&OffsetNeighbors::new(&[], &is_cobble)).eval(&context, origin));
+
+    let surrounded_by_cobble = All::new(&Chebyshev2DNeighbors::new(1, &is_cobble));
+    assert!(!surrounded_by_cobble.eval(&context, origin));
+    assert!(
+      All::new(&OffsetNeighbors::new(&[RelativePos::here()], &is_cobble)).eval(&context, origin)
+    );
+    assert!(All::new(&OffsetNeighbors::new(&[], &is_cobble)).eval(&context, far_away));
+
+    assert_eq!(
+      near_cobble.cacheability(),
+      surrounded_by_cobble.cacheability()
+    );
+    assert_ne!(
+      near_cobble.unique_descrip(),
+      surrounded_by_cobble.unique_descrip()
+    );
+  }
+}
@@ -0,0 +1,246 @@
+use crate::{query::*, relative_pos::*, unique_descrip::UniqueDescrip};
+
+/// Numbers that queries can do arithmetic on. Arithmetic saturates at the
+/// type's bounds rather than wrapping or panicking, so a rule never flips a
+/// huge count to a small one, and `Sub` of unsigned numbers stops at zero.
+pub trait Saturating: Copy + Default {
+  fn saturating_add(self, other: Self) -> Self;
+  fn saturating_sub(self, other: Self) -> Self;
+}
+
+impl Saturating for u8 {
+  fn saturating_add(self, other: Self) -> Self { u8::saturating_add(self, other) }
+
+  fn saturating_sub(self, other: Self) -> Self { u8::saturating_sub(self, other) }
+}
+
+impl Saturating for u16 {
+  fn saturating_add(self, other: Self) -> Self { u16::saturating_add(self, other) }
+
+  fn saturating_sub(self, other: Self) -> Self { u16::saturating_sub(self, other) }
+}
+
+impl Saturating for u32 {
+  fn saturating_add(self, other: Self) -> Self { u32::saturating_add(self, other) }
+
+  fn saturating_sub(self, other: Self) -> Self { u32::saturating_sub(self, other) }
+}
+
+impl Saturating for i8 {
+  fn saturating_add(self, other: Self) -> Self { i8::saturating_add(self, other) }
+
+  fn saturating_sub(self, other: Self) -> Self { i8::saturating_sub(self, other) }
+}
+
+impl Saturating for i16 {
+  fn saturating_add(self, other: Self) -> Self { i16::saturating_add(self, other) }
+
+  fn saturating_sub(self, other: Self) -> Self { i16::saturating_sub(self, other) }
+}
+
+impl Saturating for i32 {
+  fn saturating_add(self, other: Self) -> Self { i32::saturating_add(self, other) }
+
+  fn saturating_sub(self, other: Self) -> Self { i32::saturating_sub(self, other) }
+}
+
+/// Saturating addition, see `Saturating`
+pub struct Add<T, L, R> {
+  left: L,
+  right: R,
+  _phantom: PhantomData<T>,
+}
+
+impl<'a, T: 'a, L, R> Add<T, L, R>
+where
+  T: Saturating,
+  L: Query<'a, T>,
+  R: Query<'a, T>,
+{
+  pub fn new(left: &L, right: &R) -> Add<T, L, R> {
+    Add {
+      left: left.clone(),
+      right: right.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<T, L, R> UniqueDescrip for Add<T, L, R>
+where
+  L: UniqueDescrip,
+  R: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String {
+    format!(
+      "Add( {}, {} )",
+      self.left.unique_descrip(),
+      self.right.unique_descrip()
+    )
+  }
+}
+
+impl<'a, T: 'a, L, R> GenericQuery for Add<T, L, R>
+where
+  L: Query<'a, T>,
+  R: Query<'a, T>,
+{
+  fn cacheability(&self) -> CacheabilitySet {
+    CacheabilitySet::union(&self.left.cacheability(), &self.right.cacheability())
+  }
+}
+
+impl<'a, T: 'a, L, R> Query<'a, T> for Add<T, L, R>
+where
+  T: Saturating,
+  L: Query<'a, T>,
+  R: Query<'a, T>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> T {
+    self
+      .left
+      .eval(n, pos)
+      .saturating_add(self.right.eval(n, pos))
+  }
+}
+
+impl<'a, T: 'a, L, R> Clone for Add<T, L, R>
+where
+  L: Query<'a, T>,
+  R: Query<'a, T>,
+{
+  fn clone(self: &Self) -> Self {
+    Add {
+      left: self.left.clone(),
+      right: self.right.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+/// Saturating subtraction, see `Saturating`
+pub struct Sub<T, L, R> {
+  left: L,
+  right: R,
+  _phantom: PhantomData<T>,
+}
+
+impl<'a, T: 'a, L, R> Sub<T, L, R>
+where
+  T: Saturating,
+  L: Query<'a, T>,
+  R: Query<'a, T>,
+{
+  pub fn new(left: &L, right: &R) -> Sub<T, L, R> {
+    Sub {
+      left: left.clone(),
+      right: right.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<T, L, R> UniqueDescrip for Sub<T, L, R>
+where
+  L: UniqueDescrip,
+  R: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String {
+    format!(
+      "Sub( {}, {} )",
+      self.left.unique_descrip(),
+      self.right.unique_descrip()
+    )
+  }
+}
+
+impl<'a, T: 'a, L, R> GenericQuery for Sub<T, L, R>
+where
+  L: Query<'a, T>,
+  R: Query<'a, T>,
+{
+  fn cacheability(&self) -> CacheabilitySet {
+    CacheabilitySet::union(&self.left.cacheability(), &self.right.cacheability())
+  }
+}
+
+impl<'a, T: 'a, L, R> Query<'a, T> for Sub<T, L, R>
+where
+  T: Saturating,
+  L: Query<'a, T>,
+  R: Query<'a, T>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> T {
+    self
+      .left
+      .eval(n, pos)
+      .saturating_sub(self.right.eval(n, pos))
+  }
+}
+
+impl<'a, T: 'a, L, R> Clone for Sub<T, L, R>
+where
+  L: Query<'a, T>,
+  R: Query<'a, T>,
+{
+  fn clone(self: &Self) -> Self {
+    Sub {
+      left: self.left.clone(),
+      right: self.right.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::query::tests::{TestContext, AGE};
+
+  #[test]
+  fn test_add_and_sub() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+    let west = RelativePos::new(-1, 0, 0);
+
+    let two: Constant<u8> = Constant::new(2);
+    let three: Constant<u8> = Constant::new(3);
+    assert_eq!(Add::new(&two, &three).eval(&context, origin), 5);
+    assert_eq!(Sub::new(&three, &two).eval(&context, origin), 1);
+    assert_eq!(Add::new(&two, &three).cacheability(), Forever);
+
+    let age = GetBlockField::new(AGE);
+    let next_age = Add::new(&age, &Constant::new(1));
+    assert_eq!(next_age.eval(&context, origin), 6);
+    assert_eq!(next_age.eval(&context, west), 1);
+    assert_eq!(Sub::new(&age, &two).eval(&context, origin), 3);
+    assert_eq!(
+      next_age.cacheability(),
+      UntilChangeInSelf {
+        fields: vec![CacheableBlockField(AGE.id())]
+      }
+    );
+    assert_ne!(
+      Add::new(&age, &two).unique_descrip(),
+      Sub::new(&age, &two).unique_descrip()
+    );
+  }
+
+  #[test]
+  fn test_saturates() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+
+    let big: Constant<u8> = Constant::new(200);
+    let small: Constant<u8> = Constant::new(100);
+    assert_eq!(Add::new(&big, &small).eval(&context, origin), 255);
+    assert_eq!(Sub::new(&small, &big).eval(&context, origin), 0);
+
+    let low: Constant<i8> = Constant::new(-100);
+    assert_eq!(Add::new(&low, &low).eval(&context, origin), -128);
+    assert_eq!(
+      Sub::new(&Constant::new(100), &low).eval(&context, origin),
+      127
+    );
+  }
+}
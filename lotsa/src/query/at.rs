@@ -0,0 +1,132 @@
+use std::marker::PhantomData;
+
+use crate::{query::*, relative_pos::*, unique_descrip::UniqueDescrip};
+
+/// Evaluates at a single offset, e.g. the block type directly below
+pub struct At<T, E> {
+  offset: RelativePos,
+  expr: E,
+  _phantom: PhantomData<T>,
+}
+
+impl<'a, T: 'a, E> At<T, E>
+where
+  E: Query<'a, T>,
+{
+  pub fn new(offset: RelativePos, expr: &E) -> At<T, E> {
+    At {
+      offset,
+      expr: expr.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<T, E> UniqueDescrip for At<T, E>
+where
+  E: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String {
+    format!(
+      "At( {},{},{}, {} )",
+      self.offset.x,
+      self.offset.y,
+      self.offset.z,
+      self.expr.unique_descrip()
+    )
+  }
+}
+
+impl<'a, T: 'a, E> GenericQuery for At<T, E>
+where
+  E: Query<'a, T>,
+{
+  // Only the block at `pos - offset` reads a change at `pos`
+  fn cacheability(&self) -> CacheabilitySet {
+    self
+      .expr
+      .cacheability()
+      .widen(&Neighborhood::offsets(&[self.offset]))
+  }
+}
+
+impl<'a, T: 'a, E> Query<'a, T> for At<T, E>
+where
+  E: Query<'a, T>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> T {
+    self.expr.eval(n, pos.offset(self.offset))
+  }
+}
+
+impl<'a, T: 'a, E> Clone for At<T, E>
+where
+  E: Query<'a, T>,
+{
+  fn clone(self: &Self) -> Self {
+    At {
+      offset: self.offset,
+      expr: self.expr.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    block::UNKNOWN,
+    query::tests::{TestContext, AGE, COBBLE},
+  };
+
+  #[test]
+  fn test_block_type_below() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+    let above = RelativePos::new(0, 0, 1);
+    let below = RelativePos::new(0, 0, -1);
+
+    let type_below = At::new(below, &GetBlockType::new());
+    assert_eq!(type_below.eval(&context, above), COBBLE);
+    assert_eq!(type_below.eval(&context, origin), UNKNOWN);
+    assert_eq!(
+      type_below.cacheability(),
+      UntilChangeInOffsets {
+        fields: vec![CacheableBlockType],
+        offsets: vec![below],
+      }
+    );
+    assert_ne!(
+      type_below.unique_descrip(),
+      At::new(above, &GetBlockType::new()).unique_descrip()
+    );
+  }
+
+  #[test]
+  fn test_nested_offsets() {
+    let context = TestContext {};
+    let east = RelativePos::new(1, 0, 0);
+    let two_west = RelativePos::new(-2, 0, 0);
+
+    // One east of two west is one west
+    let age_west = At::new(east, &At::new(two_west, &GetBlockField::new(AGE)));
+    assert_eq!(age_west.eval(&context, RelativePos::new(1, 0, 0)), 5);
+    assert_eq!(
+      age_west.cacheability(),
+      UntilChangeInOffsets {
+        fields: vec![CacheableBlockField(AGE.id())],
+        offsets: vec![RelativePos::new(-1, 0, 0)],
+      }
+    );
+
+    // Here is the block itself
+    assert_eq!(
+      At::new(RelativePos::here(), &GetBlockField::new(AGE)).cacheability(),
+      UntilChangeInSelf {
+        fields: vec![CacheableBlockField(AGE.id())]
+      }
+    );
+    assert_eq!(At::new(east, &Constant::new(3u8)).cacheability(), Forever);
+  }
+}
@@ -0,0 +1,148 @@
+use crate::{query::*, relative_pos::*, unique_descrip::UniqueDescrip};
+
+// Queries are normally nested generics, so their types are fixed at compile
+// time. These wrappers hide the type of the query inside, so that queries can
+// also be put together at runtime, e.g. from rules in a data file.
+
+trait ErasedQuery<T>: UniqueDescrip {
+  fn erased_cacheability(&self) -> CacheabilitySet;
+  fn erased_eval<'a>(&'a self, n: &'a dyn Context, pos: RelativePos) -> T;
+  fn erased_clone(&self) -> Box<dyn ErasedQuery<T>>;
+}
+
+impl<T: 'static, Q> ErasedQuery<T> for Q
+where
+  Q: for<'a> Query<'a, T> + 'static,
+{
+  fn erased_cacheability(&self) -> CacheabilitySet { self.cacheability() }
+
+  fn erased_eval<'a>(&'a self, n: &'a dyn Context, pos: RelativePos) -> T { self.eval(n, pos) }
+
+  fn erased_clone(&self) -> Box<dyn ErasedQuery<T>> { Box::new(self.clone()) }
+}
+
+/// Any query that evaluates to a `T`
+pub struct BoxedQuery<T> {
+  query: Box<dyn ErasedQuery<T>>,
+}
+
+impl<T: 'static> BoxedQuery<T> {
+  pub fn new<Q>(query: &Q) -> BoxedQuery<T>
+  where
+    Q: for<'a> Query<'a, T> + 'static,
+  {
+    BoxedQuery {
+      query: Box::new(query.clone()),
+    }
+  }
+}
+
+impl<T> UniqueDescrip for BoxedQuery<T> {
+  fn unique_descrip(&self) -> String { self.query.unique_descrip() }
+}
+
+impl<T> GenericQuery for BoxedQuery<T> {
+  fn cacheability(&self) -> CacheabilitySet { self.query.erased_cacheability() }
+}
+
+impl<'a, T: 'static> Query<'a, T> for BoxedQuery<T> {
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> T { self.query.erased_eval(n, pos) }
+}
+
+impl<T> Clone for BoxedQuery<T> {
+  fn clone(self: &Self) -> Self {
+    BoxedQuery {
+      query: self.query.erased_clone(),
+    }
+  }
+}
+
+trait ErasedIterQuery<T>: UniqueDescrip {
+  fn erased_cacheability(&self) -> CacheabilitySet;
+  fn erased_eval<'a>(
+    &'a self,
+    n: &'a dyn Context,
+    pos: RelativePos,
+  ) -> Box<dyn Iterator<Item = T> + 'a>;
+  fn erased_clone(&self) -> Box<dyn ErasedIterQuery<T>>;
+}
+
+impl<T: 'static, Q> ErasedIterQuery<T> for Q
+where
+  Q: for<'a> Query<'a, Box<dyn Iterator<Item = T> + 'a>> + 'static,
+{
+  fn erased_cacheability(&self) -> CacheabilitySet { self.cacheability() }
+
+  fn erased_eval<'a>(
+    &'a self,
+    n: &'a dyn Context,
+    pos: RelativePos,
+  ) -> Box<dyn Iterator<Item = T> + 'a> {
+    self.eval(n, pos)
+  }
+
+  fn erased_clone(&self) -> Box<dyn ErasedIterQuery<T>> { Box::new(self.clone()) }
+}
+
+/// Any query that evaluates to an iterator of `T`s, such as a neighborhood
+pub struct BoxedIterQuery<T> {
+  query: Box<dyn ErasedIterQuery<T>>,
+}
+
+impl<T: 'static> BoxedIterQuery<T> {
+  pub fn new<Q>(query: &Q) -> BoxedIterQuery<T>
+  where
+    Q: for<'a> Query<'a, Box<dyn Iterator<Item = T> + 'a>> + 'static,
+  {
+    BoxedIterQuery {
+      query: Box::new(query.clone()),
+    }
+  }
+}
+
+impl<T> UniqueDescrip for BoxedIterQuery<T> {
+  fn unique_descrip(&self) -> String { self.query.unique_descrip() }
+}
+
+impl<T> GenericQuery for BoxedIterQuery<T> {
+  fn cacheability(&self) -> CacheabilitySet { self.query.erased_cacheability() }
+}
+
+impl<'a, T: 'static> Query<'a, Box<dyn Iterator<Item = T> + 'a>> for BoxedIterQuery<T> {
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> Box<dyn Iterator<Item = T> + 'a> {
+    self.query.erased_eval(n, pos)
+  }
+}
+
+impl<T> Clone for BoxedIterQuery<T> {
+  fn clone(self: &Self) -> Self {
+    BoxedIterQuery {
+      query: self.query.erased_clone(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::query::tests::{TestContext, COBBLE};
+
+  #[test]
+  fn test_boxed_queries() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+
+    let is_cobble = Equals::new(&GetBlockType::new(), &Constant::new(COBBLE));
+    let boxed = BoxedQuery::new(&is_cobble);
+    assert!(boxed.eval(&context, origin));
+    assert_eq!(boxed.cacheability(), is_cobble.cacheability());
+    assert_eq!(boxed.unique_descrip(), is_cobble.unique_descrip());
+
+    // Boxed queries nest inside other queries like any other
+    let neighbors = Chebyshev2DNeighbors::new(1, &boxed);
+    let boxed_neighbors = BoxedIterQuery::new(&neighbors);
+    let count = BoxedQuery::new(&Count::new(&boxed_neighbors.clone()));
+    assert_eq!(count.eval(&context, RelativePos::new(1, 1, 0)), 1);
+    assert_eq!(count.clone().cacheability(), neighbors.cacheability());
+  }
+}
@@ -14,8 +14,8 @@ where
 {
   // TODO: Const
   pub fn new(distance: u8, map_expr: &E) -> Chebyshev2DNeighbors<T, E> {
-    if distance > 127 {
-      panic!("Distance must be <= 127")
+    if distance > MAX_NEIGHBORHOOD_DISTANCE {
+      panic!("Distance must be <= {}", MAX_NEIGHBORHOOD_DISTANCE)
     }
     Chebyshev2DNeighbors {
       distance,
@@ -42,15 +42,12 @@ impl<'a, T: 'a, E> GenericQuery for Chebyshev2DNeighbors<T, E>
 where
   E: Query<'a, T>,
 {
-  fn cacheability(&self) -> Cacheability {
-    match self.map_expr.cacheability() {
-      DontCache => DontCache,
-      Forever => Forever,
-      map_expr_cacheability => UntilChangeInChebyshevNeighborhood {
-        distance: self.distance + map_expr_cacheability.distance(),
-        fields: map_expr_cacheability.fields().to_vec(),
-      },
-    }
+  // Cache busting works in 3D, so this also watches the blocks above and below
+  fn cacheability(&self) -> CacheabilitySet {
+    self
+      .map_expr
+      .cacheability()
+      .widen(&Neighborhood::Chebyshev(self.distance))
   }
 }
 
@@ -60,13 +57,11 @@ where
 {
   fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> Box<dyn Iterator<Item = T> + 'a> {
     let d = self.distance as i8;
-    // TODO: Z
     Box::new((-d..=d).flat_map(move |y_offset| {
       (-d..=d).map(move |x_offset| {
-        self.map_expr.eval(
-          n,
-          RelativePos::new(x_offset + pos.x, y_offset + pos.y, pos.z),
-        )
+        self
+          .map_expr
+          .eval(n, pos.offset(RelativePos::new(x_offset, y_offset, 0)))
       })
     }))
   }
@@ -214,4 +209,20 @@ mod tests {
       }
     )
   }
+
+  #[test]
+  fn test_furthest_neighbors() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+
+    let furthest = Chebyshev2DNeighbors::new(MAX_NEIGHBORHOOD_DISTANCE, &GetBlockType::new());
+    let width = 2 * usize::from(MAX_NEIGHBORHOOD_DISTANCE) + 1;
+    assert_eq!(furthest.eval(&context, origin).count(), width * width);
+  }
+
+  #[test]
+  #[should_panic(expected = "Distance must be <= 31")]
+  fn test_too_far() {
+    Chebyshev2DNeighbors::new(MAX_NEIGHBORHOOD_DISTANCE + 1, &GetBlockType::new());
+  }
 }
@@ -0,0 +1,130 @@
+use std::marker::PhantomData;
+
+use crate::{query::*, relative_pos::*, unique_descrip::UniqueDescrip};
+
+pub struct Chebyshev3DNeighbors<T, E> {
+  distance: u8,
+  map_expr: E,
+  _phantom: PhantomData<T>,
+}
+
+impl<'a, T: 'a, E> Chebyshev3DNeighbors<T, E>
+where
+  E: Query<'a, T>,
+{
+  pub fn new(distance: u8, map_expr: &E) -> Chebyshev3DNeighbors<T, E> {
+    if distance > MAX_NEIGHBORHOOD_DISTANCE {
+      panic!("Distance must be <= {}", MAX_NEIGHBORHOOD_DISTANCE)
+    }
+    Chebyshev3DNeighbors {
+      distance,
+      map_expr: map_expr.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<T, E> UniqueDescrip for Chebyshev3DNeighbors<T, E>
+where
+  E: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String {
+    format!(
+      "Chebyshev3DNeighbors( dist:{}, {} )",
+      self.distance,
+      self.map_expr.unique_descrip()
+    )
+  }
+}
+
+impl<'a, T: 'a, E> GenericQuery for Chebyshev3DNeighbors<T, E>
+where
+  E: Query<'a, T>,
+{
+  fn cacheability(&self) -> CacheabilitySet {
+    self
+      .map_expr
+      .cacheability()
+      .widen(&Neighborhood::Chebyshev(self.distance))
+  }
+}
+
+impl<'a, T: 'a, E: 'a> Query<'a, Box<dyn Iterator<Item = T> + 'a>> for Chebyshev3DNeighbors<T, E>
+where
+  E: Query<'a, T>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> Box<dyn Iterator<Item = T> + 'a> {
+    let d = self.distance as i8;
+    Box::new((-d..=d).flat_map(move |z_offset| {
+      (-d..=d).flat_map(move |y_offset| {
+        (-d..=d).map(move |x_offset| {
+          self.map_expr.eval(
+            n,
+            pos.offset(RelativePos::new(x_offset, y_offset, z_offset)),
+          )
+        })
+      })
+    }))
+  }
+}
+
+impl<'a, T: 'a, E> Clone for Chebyshev3DNeighbors<T, E>
+where
+  E: Query<'a, T>,
+{
+  fn clone(self: &Self) -> Self {
+    Chebyshev3DNeighbors {
+      distance: self.distance,
+      map_expr: self.map_expr.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    block::UNKNOWN,
+    query::tests::{TestContext, COBBLE},
+  };
+
+  #[test]
+  fn test_neighbors_block_types() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+    let above = RelativePos::new(0, 0, 1);
+    let get_neighbor_types = Chebyshev3DNeighbors::new(1, &GetBlockType::new());
+
+    let mut expected = vec![UNKNOWN; 27];
+    expected[13] = COBBLE;
+    assert_eq!(
+      expected,
+      get_neighbor_types
+        .eval(&context, origin)
+        .collect::<Vec<BlockType>>()
+    );
+
+    // The cobble is now in the middle of the bottom layer
+    let mut expected = vec![UNKNOWN; 27];
+    expected[4] = COBBLE;
+    assert_eq!(
+      expected,
+      get_neighbor_types
+        .eval(&context, above)
+        .collect::<Vec<BlockType>>()
+    );
+
+    assert_eq!(
+      get_neighbor_types.cacheability(),
+      UntilChangeInChebyshevNeighborhood {
+        distance: 1,
+        fields: vec![CacheableBlockType]
+      }
+    );
+    assert_ne!(
+      get_neighbor_types.unique_descrip(),
+      Chebyshev2DNeighbors::new(1, &GetBlockType::new()).unique_descrip()
+    );
+  }
+}
@@ -0,0 +1,195 @@
+use crate::{query::*, relative_pos::*, unique_descrip::UniqueDescrip};
+
+pub struct LessThan<T, L, R> {
+  left: L,
+  right: R,
+  _phantom: PhantomData<T>,
+}
+
+impl<'a, T: 'a, L, R> LessThan<T, L, R>
+where
+  T: PartialOrd,
+  L: Query<'a, T>,
+  R: Query<'a, T>,
+{
+  pub fn new(left: &L, right: &R) -> LessThan<T, L, R> {
+    LessThan {
+      left: left.clone(),
+      right: right.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<T, L, R> UniqueDescrip for LessThan<T, L, R>
+where
+  L: UniqueDescrip,
+  R: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String {
+    format!(
+      "LessThan( {}, {} )",
+      self.left.unique_descrip(),
+      self.right.unique_descrip()
+    )
+  }
+}
+
+impl<'a, T: 'a, L, R> GenericQuery for LessThan<T, L, R>
+where
+  L: Query<'a, T>,
+  R: Query<'a, T>,
+{
+  fn cacheability(&self) -> CacheabilitySet {
+    CacheabilitySet::union(&self.left.cacheability(), &self.right.cacheability())
+  }
+}
+
+impl<'a, T: 'a, L, R> Query<'a, bool> for LessThan<T, L, R>
+where
+  T: PartialOrd,
+  L: Query<'a, T>,
+  R: Query<'a, T>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> bool {
+    self.left.eval(n, pos) < self.right.eval(n, pos)
+  }
+}
+
+impl<'a, T: 'a, L, R> Clone for LessThan<T, L, R>
+where
+  L: Query<'a, T>,
+  R: Query<'a, T>,
+{
+  fn clone(self: &Self) -> Self {
+    LessThan {
+      left: self.left.clone(),
+      right: self.right.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+/// Whether the value is one of a fixed set of values
+pub struct InSet<T, E> {
+  expr: E,
+  values: Vec<T>,
+}
+
+impl<'a, T: 'a, E> InSet<T, E>
+where
+  T: Copy + PartialEq + UniqueDescrip,
+  E: Query<'a, T>,
+{
+  pub fn new(expr: &E, values: &[T]) -> InSet<T, E> {
+    InSet {
+      expr: expr.clone(),
+      values: values.to_vec(),
+    }
+  }
+}
+
+impl<T, E> UniqueDescrip for InSet<T, E>
+where
+  T: UniqueDescrip,
+  E: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String {
+    let values: Vec<String> = self
+      .values
+      .iter()
+      .map(UniqueDescrip::unique_descrip)
+      .collect();
+    format!(
+      "InSet( {}, [{}] )",
+      self.expr.unique_descrip(),
+      values.join(" ")
+    )
+  }
+}
+
+impl<'a, T: 'a, E> GenericQuery for InSet<T, E>
+where
+  T: UniqueDescrip,
+  E: Query<'a, T>,
+{
+  fn cacheability(&self) -> CacheabilitySet { self.expr.cacheability() }
+}
+
+impl<'a, T: 'a, E> Query<'a, bool> for InSet<T, E>
+where
+  T: Copy + PartialEq + UniqueDescrip,
+  E: Query<'a, T>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> bool {
+    self.values.contains(&self.expr.eval(n, pos))
+  }
+}
+
+impl<'a, T: 'a, E> Clone for InSet<T, E>
+where
+  T: Copy,
+  E: Query<'a, T>,
+{
+  fn clone(self: &Self) -> Self {
+    InSet {
+      expr: self.expr.clone(),
+      values: self.values.clone(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    block::UNKNOWN,
+    query::tests::{TestContext, AGE, COBBLE},
+  };
+
+  #[test]
+  fn test_less_than() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+    let west = RelativePos::new(-1, 0, 0);
+
+    let age = GetBlockField::new(AGE);
+    let young = LessThan::new(&age, &Constant::new(3));
+    assert!(!young.eval(&context, origin));
+    assert!(young.eval(&context, west));
+    assert!(LessThan::new(&Constant::new(3), &age).eval(&context, origin));
+    assert!(!LessThan::new(&age, &age).eval(&context, origin));
+    assert_eq!(
+      young.cacheability(),
+      UntilChangeInSelf {
+        fields: vec![CacheableBlockField(AGE.id())]
+      }
+    );
+  }
+
+  #[test]
+  fn test_in_set() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+    let west = RelativePos::new(-1, 0, 0);
+
+    let age = GetBlockField::new(AGE);
+    assert!(InSet::new(&age, &[4, 5, 6]).eval(&context, origin));
+    assert!(!InSet::new(&age, &[4, 6]).eval(&context, origin));
+    assert!(!InSet::new(&age, &[]).eval(&context, origin));
+
+    let cobble_or_unknown = InSet::new(&GetBlockType::new(), &[COBBLE, UNKNOWN]);
+    assert!(cobble_or_unknown.eval(&context, origin));
+    assert!(cobble_or_unknown.eval(&context, west));
+    assert_eq!(
+      cobble_or_unknown.cacheability(),
+      UntilChangeInSelf {
+        fields: vec![CacheableBlockType]
+      }
+    );
+    assert_ne!(
+      InSet::new(&age, &[4, 5]).unique_descrip(),
+      InSet::new(&age, &[4, 6]).unique_descrip()
+    );
+  }
+}
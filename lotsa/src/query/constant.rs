@@ -24,7 +24,7 @@ impl<T> GenericQuery for Constant<T>
 where
   T: Copy + UniqueDescrip,
 {
-  fn cacheability(&self) -> Cacheability { Forever }
+  fn cacheability(&self) -> CacheabilitySet { Forever.into() }
 }
 
 impl<'a, T: 'a> Query<'a, T> for Constant<T>
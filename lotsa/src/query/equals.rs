@@ -41,8 +41,8 @@ where
   L: Query<'a, T>,
   R: Query<'a, T>,
 {
-  fn cacheability(&self) -> Cacheability {
-    Cacheability::merge(&self.left.cacheability(), &self.right.cacheability())
+  fn cacheability(&self) -> CacheabilitySet {
+    CacheabilitySet::union(&self.left.cacheability(), &self.right.cacheability())
   }
 }
 
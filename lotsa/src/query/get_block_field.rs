@@ -0,0 +1,102 @@
+use crate::{query::*, relative_pos::*, unique_descrip::UniqueDescrip};
+
+pub struct GetBlockField<T> {
+  field: BlockField<T>,
+}
+
+impl<T> GetBlockField<T>
+where
+  T: FieldValue,
+{
+  // TODO: Const
+  pub fn new(field: BlockField<T>) -> GetBlockField<T> { GetBlockField { field } }
+}
+
+impl<T> UniqueDescrip for GetBlockField<T> {
+  fn unique_descrip(&self) -> String {
+    format!("GetBlockField( {} )", self.field.id().unique_descrip())
+  }
+}
+
+impl<T> GenericQuery for GetBlockField<T> {
+  fn cacheability(&self) -> CacheabilitySet {
+    UntilChangeInSelf {
+      fields: vec![CacheableBlockField(self.field.id())],
+    }
+    .into()
+  }
+}
+
+impl<'a, T: 'a> Query<'a, T> for GetBlockField<T>
+where
+  T: FieldValue,
+{
+  fn eval(&self, n: &dyn Context, pos: RelativePos) -> T { n.get_block(pos).field(self.field) }
+}
+
+impl<T> Clone for GetBlockField<T> {
+  fn clone(&self) -> Self { GetBlockField { field: self.field } }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::query::tests::{TestContext, AGE, COBBLE};
+
+  #[test]
+  fn test_get_block_field() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+    let west = RelativePos::new(-1, 0, 0);
+
+    let get_age = GetBlockField::new(AGE);
+
+    assert_eq!(get_age.eval(&context, origin), 5);
+    assert_eq!(get_age.eval(&context, west), 0);
+
+    assert_eq!(
+      get_age.cacheability(),
+      UntilChangeInSelf {
+        fields: vec![CacheableBlockField(AGE.id())]
+      }
+    );
+  }
+
+  #[test]
+  fn test_get_block_field_equality() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+
+    let five: Constant<u8> = Constant::new(5);
+    let age_is_five = Equals::new(&GetBlockField::new(AGE), &five);
+    assert!(age_is_five.eval(&context, origin));
+    assert_eq!(
+      age_is_five.cacheability(),
+      UntilChangeInSelf {
+        fields: vec![CacheableBlockField(AGE.id())]
+      }
+    );
+
+    let cobble_neighbors = Chebyshev2DNeighbors::new(
+      1,
+      &Equals::new(&GetBlockType::new(), &Constant::new(COBBLE)),
+    );
+    // Reading the block's own age doesn't widen to the neighborhood
+    let both = CacheabilitySet::union(
+      &cobble_neighbors.cacheability(),
+      &age_is_five.cacheability(),
+    );
+    assert_eq!(
+      both.iter().cloned().collect::<Vec<_>>(),
+      vec![
+        UntilChangeInSelf {
+          fields: vec![CacheableBlockField(AGE.id())]
+        },
+        UntilChangeInChebyshevNeighborhood {
+          distance: 1,
+          fields: vec![CacheableBlockType]
+        },
+      ]
+    );
+  }
+}
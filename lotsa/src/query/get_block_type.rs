@@ -16,10 +16,11 @@ impl Default for GetBlockType {
 }
 
 impl GenericQuery for GetBlockType {
-  fn cacheability(&self) -> Cacheability {
+  fn cacheability(&self) -> CacheabilitySet {
     UntilChangeInSelf {
       fields: vec![CacheableBlockType],
     }
+    .into()
   }
 }
 
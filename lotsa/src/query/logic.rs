@@ -0,0 +1,310 @@
+use crate::{query::*, relative_pos::*, unique_descrip::UniqueDescrip};
+
+/// Only evaluates `right` if `left` is true, but is re-evaluated when either
+/// of them might change
+#[derive(Clone)]
+pub struct And<L, R> {
+  left: L,
+  right: R,
+}
+
+impl<'a, L, R> And<L, R>
+where
+  L: Query<'a, bool>,
+  R: Query<'a, bool>,
+{
+  pub fn new(left: &L, right: &R) -> And<L, R> {
+    And {
+      left: left.clone(),
+      right: right.clone(),
+    }
+  }
+}
+
+impl<L, R> UniqueDescrip for And<L, R>
+where
+  L: UniqueDescrip,
+  R: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String {
+    format!(
+      "And( {}, {} )",
+      self.left.unique_descrip(),
+      self.right.unique_descrip()
+    )
+  }
+}
+
+impl<L, R> GenericQuery for And<L, R>
+where
+  L: GenericQuery,
+  R: GenericQuery,
+{
+  fn cacheability(&self) -> CacheabilitySet {
+    CacheabilitySet::union(&self.left.cacheability(), &self.right.cacheability())
+  }
+}
+
+impl<'a, L, R> Query<'a, bool> for And<L, R>
+where
+  L: Query<'a, bool>,
+  R: Query<'a, bool>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> bool {
+    self.left.eval(n, pos) && self.right.eval(n, pos)
+  }
+}
+
+/// Only evaluates `right` if `left` is false, but is re-evaluated when either
+/// of them might change
+#[derive(Clone)]
+pub struct Or<L, R> {
+  left: L,
+  right: R,
+}
+
+impl<'a, L, R> Or<L, R>
+where
+  L: Query<'a, bool>,
+  R: Query<'a, bool>,
+{
+  pub fn new(left: &L, right: &R) -> Or<L, R> {
+    Or {
+      left: left.clone(),
+      right: right.clone(),
+    }
+  }
+}
+
+impl<L, R> UniqueDescrip for Or<L, R>
+where
+  L: UniqueDescrip,
+  R: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String {
+    format!(
+      "Or( {}, {} )",
+      self.left.unique_descrip(),
+      self.right.unique_descrip()
+    )
+  }
+}
+
+impl<L, R> GenericQuery for Or<L, R>
+where
+  L: GenericQuery,
+  R: GenericQuery,
+{
+  fn cacheability(&self) -> CacheabilitySet {
+    CacheabilitySet::union(&self.left.cacheability(), &self.right.cacheability())
+  }
+}
+
+impl<'a, L, R> Query<'a, bool> for Or<L, R>
+where
+  L: Query<'a, bool>,
+  R: Query<'a, bool>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> bool {
+    self.left.eval(n, pos) || self.right.eval(n, pos)
+  }
+}
+
+#[derive(Clone)]
+pub struct Not<E> {
+  expr: E,
+}
+
+impl<'a, E> Not<E>
+where
+  E: Query<'a, bool>,
+{
+  pub fn new(expr: &E) -> Not<E> { Not { expr: expr.clone() } }
+}
+
+impl<E> UniqueDescrip for Not<E>
+where
+  E: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String { format!("Not( {} )", self.expr.unique_descrip()) }
+}
+
+impl<E> GenericQuery for Not<E>
+where
+  E: GenericQuery,
+{
+  fn cacheability(&self) -> CacheabilitySet { self.expr.cacheability() }
+}
+
+impl<'a, E> Query<'a, bool> for Not<E>
+where
+  E: Query<'a, bool>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> bool { !self.expr.eval(n, pos) }
+}
+
+/// Evaluates `then` or `otherwise` depending on `condition`. Which one is
+/// only known when evaluating, so it depends on all three.
+pub struct If<T, C, A, B> {
+  condition: C,
+  then: A,
+  otherwise: B,
+  _phantom: PhantomData<T>,
+}
+
+impl<'a, T: 'a, C, A, B> If<T, C, A, B>
+where
+  C: Query<'a, bool>,
+  A: Query<'a, T>,
+  B: Query<'a, T>,
+{
+  pub fn new(condition: &C, then: &A, otherwise: &B) -> If<T, C, A, B> {
+    If {
+      condition: condition.clone(),
+      then: then.clone(),
+      otherwise: otherwise.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<T, C, A, B> UniqueDescrip for If<T, C, A, B>
+where
+  C: UniqueDescrip,
+  A: UniqueDescrip,
+  B: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String {
+    format!(
+      "If( {}, {}, {} )",
+      self.condition.unique_descrip(),
+      self.then.unique_descrip(),
+      self.otherwise.unique_descrip()
+    )
+  }
+}
+
+impl<'a, T: 'a, C, A, B> GenericQuery for If<T, C, A, B>
+where
+  C: Query<'a, bool>,
+  A: Query<'a, T>,
+  B: Query<'a, T>,
+{
+  fn cacheability(&self) -> CacheabilitySet {
+    CacheabilitySet::union(
+      &self.condition.cacheability(),
+      &CacheabilitySet::union(&self.then.cacheability(), &self.otherwise.cacheability()),
+    )
+  }
+}
+
+impl<'a, T: 'a, C, A, B> Query<'a, T> for If<T, C, A, B>
+where
+  C: Query<'a, bool>,
+  A: Query<'a, T>,
+  B: Query<'a, T>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> T {
+    if self.condition.eval(n, pos) {
+      self.then.eval(n, pos)
+    } else {
+      self.otherwise.eval(n, pos)
+    }
+  }
+}
+
+impl<'a, T: 'a, C, A, B> Clone for If<T, C, A, B>
+where
+  C: Query<'a, bool>,
+  A: Query<'a, T>,
+  B: Query<'a, T>,
+{
+  fn clone(self: &Self) -> Self {
+    If {
+      condition: self.condition.clone(),
+      then: self.then.clone(),
+      otherwise: self.otherwise.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    block::UNKNOWN,
+    query::tests::{TestContext, AGE, COBBLE},
+  };
+
+  #[test]
+  fn test_and_or_not() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+    let west = RelativePos::new(-1, 0, 0);
+
+    let yes = Constant::new(true);
+    let no = Constant::new(false);
+    assert!(And::new(&yes, &yes).eval(&context, origin));
+    assert!(!And::new(&yes, &no).eval(&context, origin));
+    assert!(Or::new(&no, &yes).eval(&context, origin));
+    assert!(!Or::new(&no, &no).eval(&context, origin));
+    assert!(Not::new(&no).eval(&context, origin));
+    assert_eq!(And::new(&yes, &no).cacheability(), Forever);
+
+    let is_cobble = Equals::new(&GetBlockType::new(), &Constant::new(COBBLE));
+    let is_old = Equals::new(&GetBlockField::new(AGE), &Constant::new(5));
+    let old_cobble = And::new(&is_cobble, &is_old);
+    assert!(old_cobble.eval(&context, origin));
+    assert!(!old_cobble.eval(&context, west));
+    assert!(Not::new(&old_cobble).eval(&context, west));
+    assert_eq!(
+      old_cobble.cacheability(),
+      UntilChangeInSelf {
+        fields: vec![CacheableBlockType, CacheableBlockField(AGE.id())]
+      }
+    );
+    assert_eq!(
+      Not::new(&old_cobble).cacheability(),
+      old_cobble.cacheability()
+    );
+    assert_ne!(
+      And::new(&is_cobble, &is_old).unique_descrip(),
+      Or::new(&is_cobble, &is_old).unique_descrip()
+    );
+  }
+
+  #[test]
+  fn test_if() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+    let west = RelativePos::new(-1, 0, 0);
+
+    let is_cobble = Equals::new(&GetBlockType::new(), &Constant::new(COBBLE));
+    let age_or_zero = If::new(&is_cobble, &GetBlockField::new(AGE), &Constant::new(0));
+    assert_eq!(age_or_zero.eval(&context, origin), 5);
+    assert_eq!(age_or_zero.eval(&context, west), 0);
+    assert_eq!(
+      age_or_zero.cacheability(),
+      UntilChangeInSelf {
+        fields: vec![CacheableBlockType, CacheableBlockField(AGE.id())]
+      }
+    );
+
+    let neighbors_cobble = Any::new(&Chebyshev2DNeighbors::new(1, &is_cobble));
+    let type_if_near_cobble = If::new(
+      &neighbors_cobble,
+      &GetBlockType::new(),
+      &Constant::new(UNKNOWN),
+    );
+    assert_eq!(type_if_near_cobble.eval(&context, origin), COBBLE);
+    // Reading its own type is covered by reading its neighbors' types
+    assert_eq!(
+      type_if_near_cobble.cacheability(),
+      UntilChangeInChebyshevNeighborhood {
+        distance: 1,
+        fields: vec![CacheableBlockType]
+      }
+    );
+  }
+}
@@ -0,0 +1,163 @@
+use std::marker::PhantomData;
+
+use crate::{query::*, relative_pos::*, unique_descrip::UniqueDescrip};
+
+/// Evaluates at each of a list of offsets, in the order they were given
+pub struct OffsetNeighbors<T, E> {
+  offsets: Vec<RelativePos>,
+  map_expr: E,
+  _phantom: PhantomData<T>,
+}
+
+impl<'a, T: 'a, E> OffsetNeighbors<T, E>
+where
+  E: Query<'a, T>,
+{
+  pub fn new(offsets: &[RelativePos], map_expr: &E) -> OffsetNeighbors<T, E> {
+    OffsetNeighbors {
+      offsets: offsets.to_vec(),
+      map_expr: map_expr.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<T, E> UniqueDescrip for OffsetNeighbors<T, E>
+where
+  E: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String {
+    let offsets: Vec<String> = self
+      .offsets
+      .iter()
+      .map(|offset| format!("{},{},{}", offset.x, offset.y, offset.z))
+      .collect();
+    format!(
+      "OffsetNeighbors( offsets:[{}], {} )",
+      offsets.join(" "),
+      self.map_expr.unique_descrip()
+    )
+  }
+}
+
+impl<'a, T: 'a, E> GenericQuery for OffsetNeighbors<T, E>
+where
+  E: Query<'a, T>,
+{
+  fn cacheability(&self) -> CacheabilitySet {
+    self
+      .map_expr
+      .cacheability()
+      .widen(&Neighborhood::offsets(&self.offsets))
+  }
+}
+
+impl<'a, T: 'a, E: 'a> Query<'a, Box<dyn Iterator<Item = T> + 'a>> for OffsetNeighbors<T, E>
+where
+  E: Query<'a, T>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> Box<dyn Iterator<Item = T> + 'a> {
+    Box::new(
+      self
+        .offsets
+        .iter()
+        .map(move |offset| self.map_expr.eval(n, pos.offset(*offset))),
+    )
+  }
+}
+
+impl<'a, T: 'a, E> Clone for OffsetNeighbors<T, E>
+where
+  E: Query<'a, T>,
+{
+  fn clone(self: &Self) -> Self {
+    OffsetNeighbors {
+      offsets: self.offsets.clone(),
+      map_expr: self.map_expr.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    block::UNKNOWN,
+    query::tests::{TestContext, AGE, COBBLE},
+  };
+
+  // A knight's moves in the x/y plane
+  fn knight_offsets() -> Vec<RelativePos> {
+    vec![
+      RelativePos::new(1, 2, 0),
+      RelativePos::new(2, 1, 0),
+      RelativePos::new(2, -1, 0),
+      RelativePos::new(1, -2, 0),
+      RelativePos::new(-1, -2, 0),
+      RelativePos::new(-2, -1, 0),
+      RelativePos::new(-2, 1, 0),
+      RelativePos::new(-1, 2, 0),
+    ]
+  }
+
+  #[test]
+  fn test_offset_block_types() {
+    let context = TestContext {};
+    let knight_types = OffsetNeighbors::new(&knight_offsets(), &GetBlockType::new());
+
+    assert!(knight_types
+      .eval(&context, RelativePos::new(0, 0, 0))
+      .all(|block_type| block_type == UNKNOWN));
+    assert_eq!(
+      vec![UNKNOWN, UNKNOWN, UNKNOWN, COBBLE, UNKNOWN, UNKNOWN, UNKNOWN, UNKNOWN],
+      knight_types
+        .eval(&context, RelativePos::new(-1, 2, 0))
+        .collect::<Vec<BlockType>>()
+    );
+
+    let mut sorted_offsets = knight_offsets();
+    sorted_offsets.sort();
+    assert_eq!(
+      knight_types.cacheability(),
+      UntilChangeInOffsets {
+        fields: vec![CacheableBlockType],
+        offsets: sorted_offsets,
+      }
+    );
+  }
+
+  #[test]
+  fn test_offsets_inside_other_neighborhoods() {
+    let below = [RelativePos::new(0, 0, -1)];
+    let below_age = OffsetNeighbors::new(&below, &GetBlockField::new(AGE));
+    assert_eq!(
+      below_age.cacheability(),
+      UntilChangeInOffsets {
+        fields: vec![CacheableBlockField(AGE.id())],
+        offsets: below.to_vec(),
+      }
+    );
+    assert_ne!(
+      below_age.unique_descrip(),
+      OffsetNeighbors::new(&[RelativePos::new(0, 0, 1)], &GetBlockField::new(AGE)).unique_descrip()
+    );
+
+    // Reading the age below is covered by reading it from every neighbor
+    let neighbor_ages = Chebyshev3DNeighbors::new(1, &GetBlockField::new(AGE));
+    assert_eq!(
+      CacheabilitySet::union(&below_age.cacheability(), &neighbor_ages.cacheability()),
+      neighbor_ages.cacheability()
+    );
+
+    // Offsets of offsets are every sum of the two
+    let below_below_age = OffsetNeighbors::new(&below, &below_age);
+    assert_eq!(
+      below_below_age.cacheability(),
+      UntilChangeInOffsets {
+        fields: vec![CacheableBlockField(AGE.id())],
+        offsets: vec![RelativePos::new(0, 0, -2)],
+      }
+    );
+  }
+}
@@ -0,0 +1,214 @@
+use std::marker::PhantomData;
+
+use crate::{query::*, relative_pos::*, unique_descrip::UniqueDescrip};
+
+/// Every position within a Manhattan distance, either in the x/y plane or in
+/// all three dimensions
+pub struct VonNeumannNeighbors<T, E> {
+  distance: u8,
+  three_d: bool,
+  map_expr: E,
+  _phantom: PhantomData<T>,
+}
+
+impl<'a, T: 'a, E> VonNeumannNeighbors<T, E>
+where
+  E: Query<'a, T>,
+{
+  pub fn new_2d(distance: u8, map_expr: &E) -> VonNeumannNeighbors<T, E> {
+    VonNeumannNeighbors::new(distance, false, map_expr)
+  }
+
+  pub fn new_3d(distance: u8, map_expr: &E) -> VonNeumannNeighbors<T, E> {
+    VonNeumannNeighbors::new(distance, true, map_expr)
+  }
+
+  fn new(distance: u8, three_d: bool, map_expr: &E) -> VonNeumannNeighbors<T, E> {
+    if distance > MAX_NEIGHBORHOOD_DISTANCE {
+      panic!("Distance must be <= {}", MAX_NEIGHBORHOOD_DISTANCE)
+    }
+    VonNeumannNeighbors {
+      distance,
+      three_d,
+      map_expr: map_expr.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<T, E> UniqueDescrip for VonNeumannNeighbors<T, E>
+where
+  E: UniqueDescrip,
+{
+  fn unique_descrip(&self) -> String {
+    format!(
+      "VonNeumann{}Neighbors( dist:{}, {} )",
+      if self.three_d { "3D" } else { "2D" },
+      self.distance,
+      self.map_expr.unique_descrip()
+    )
+  }
+}
+
+impl<'a, T: 'a, E> GenericQuery for VonNeumannNeighbors<T, E>
+where
+  E: Query<'a, T>,
+{
+  // Cache busting works in 3D, so the 2D version also watches the blocks
+  // above and below
+  fn cacheability(&self) -> CacheabilitySet {
+    self
+      .map_expr
+      .cacheability()
+      .widen(&Neighborhood::VonNeumann(self.distance))
+  }
+}
+
+impl<'a, T: 'a, E: 'a> Query<'a, Box<dyn Iterator<Item = T> + 'a>> for VonNeumannNeighbors<T, E>
+where
+  E: Query<'a, T>,
+{
+  fn eval(&'a self, n: &'a dyn Context, pos: RelativePos) -> Box<dyn Iterator<Item = T> + 'a> {
+    let d = self.distance as i8;
+    let z_d = if self.three_d { d } else { 0 };
+    Box::new((-z_d..=z_d).flat_map(move |z_offset| {
+      (-d..=d).flat_map(move |y_offset| {
+        (-d..=d)
+          .filter(move |x_offset| {
+            i16::from(x_offset.abs()) + i16::from(y_offset.abs()) + i16::from(z_offset.abs())
+              <= i16::from(d)
+          })
+          .map(move |x_offset| {
+            self.map_expr.eval(
+              n,
+              pos.offset(RelativePos::new(x_offset, y_offset, z_offset)),
+            )
+          })
+      })
+    }))
+  }
+}
+
+impl<'a, T: 'a, E> Clone for VonNeumannNeighbors<T, E>
+where
+  E: Query<'a, T>,
+{
+  fn clone(self: &Self) -> Self {
+    VonNeumannNeighbors {
+      distance: self.distance,
+      three_d: self.three_d,
+      map_expr: self.map_expr.clone(),
+      _phantom: PhantomData,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    block::UNKNOWN,
+    query::tests::{TestContext, COBBLE},
+  };
+
+  #[test]
+  fn test_neighbors_block_types_2d() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+    let west = RelativePos::new(-1, 0, 0);
+    let get_neighbor_types = VonNeumannNeighbors::new_2d(1, &GetBlockType::new());
+
+    assert_eq!(
+      vec![UNKNOWN, UNKNOWN, COBBLE, UNKNOWN, UNKNOWN],
+      get_neighbor_types
+        .eval(&context, origin)
+        .collect::<Vec<BlockType>>()
+    );
+    assert_eq!(
+      vec![UNKNOWN, UNKNOWN, UNKNOWN, COBBLE, UNKNOWN],
+      get_neighbor_types
+        .eval(&context, west)
+        .collect::<Vec<BlockType>>()
+    );
+    assert_eq!(
+      VonNeumannNeighbors::new_2d(2, &GetBlockType::new())
+        .eval(&context, origin)
+        .count(),
+      13
+    );
+
+    assert_eq!(
+      get_neighbor_types.cacheability(),
+      UntilChangeInVonNeumannNeighborhood {
+        distance: 1,
+        fields: vec![CacheableBlockType]
+      }
+    );
+  }
+
+  #[test]
+  fn test_neighbors_block_types_3d() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+    let get_neighbor_types = VonNeumannNeighbors::new_3d(1, &GetBlockType::new());
+
+    assert_eq!(
+      vec![UNKNOWN, UNKNOWN, UNKNOWN, COBBLE, UNKNOWN, UNKNOWN, UNKNOWN],
+      get_neighbor_types
+        .eval(&context, origin)
+        .collect::<Vec<BlockType>>()
+    );
+    assert_eq!(
+      VonNeumannNeighbors::new_3d(2, &GetBlockType::new())
+        .eval(&context, origin)
+        .count(),
+      25
+    );
+
+    assert_ne!(
+      get_neighbor_types.unique_descrip(),
+      VonNeumannNeighbors::new_2d(1, &GetBlockType::new()).unique_descrip()
+    );
+  }
+
+  #[test]
+  fn test_nested() {
+    let get_block_type = GetBlockType::new();
+    let von_neumann = VonNeumannNeighbors::new_2d(1, &get_block_type);
+
+    assert_eq!(
+      VonNeumannNeighbors::new_2d(1, &von_neumann).cacheability(),
+      UntilChangeInVonNeumannNeighborhood {
+        distance: 2,
+        fields: vec![CacheableBlockType]
+      }
+    );
+    // Mixing shapes rounds up to a cube
+    assert_eq!(
+      Chebyshev2DNeighbors::new(1, &von_neumann).cacheability(),
+      UntilChangeInChebyshevNeighborhood {
+        distance: 2,
+        fields: vec![CacheableBlockType]
+      }
+    );
+  }
+
+  #[test]
+  fn test_furthest_neighbors() {
+    let context = TestContext {};
+    let origin = RelativePos::new(0, 0, 0);
+
+    let furthest = VonNeumannNeighbors::new_2d(MAX_NEIGHBORHOOD_DISTANCE, &GetBlockType::new());
+    let d = usize::from(MAX_NEIGHBORHOOD_DISTANCE);
+    assert_eq!(
+      furthest.eval(&context, origin).count(),
+      2 * d * d + 2 * d + 1
+    );
+  }
+
+  #[test]
+  #[should_panic(expected = "Distance must be <= 31")]
+  fn test_too_far() {
+    VonNeumannNeighbors::new_3d(MAX_NEIGHBORHOOD_DISTANCE + 1, &GetBlockType::new());
+  }
+}
@@ -1,6 +1,6 @@
 use crate::chunk::CHUNK_WIDTH;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct RelativePos {
   pub x: i8,
   pub y: i8,
@@ -16,4 +16,63 @@ impl RelativePos {
   }
 
   pub fn here() -> RelativePos { RelativePos::new(0, 0, 0) }
+
+  /// None if the sum is a chunk's width or more away along any axis, which is
+  /// further than `new` allows
+  pub fn checked_add(self, other: RelativePos) -> Option<RelativePos> {
+    let add = |a: i8, b: i8| {
+      let sum = i16::from(a) + i16::from(b);
+      if sum.abs() < i16::from(CHUNK_WIDTH) {
+        Some(sum as i8)
+      } else {
+        None
+      }
+    };
+    Some(RelativePos::new(
+      add(self.x, other.x)?,
+      add(self.y, other.y)?,
+      add(self.z, other.z)?,
+    ))
+  }
+
+  /// Panics if the sum is out of range, which only neighborhoods nested a
+  /// chunk's width deep can cause
+  pub fn offset(self, offset: RelativePos) -> RelativePos {
+    self
+      .checked_add(offset)
+      .expect("relative position out of range")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_checked_add() {
+    let pos = RelativePos::new(1, -2, 3);
+    assert_eq!(pos.checked_add(pos), Some(RelativePos::new(2, -4, 6)));
+
+    let edge = RelativePos::new(30, 0, -30);
+    assert_eq!(
+      edge.checked_add(RelativePos::new(1, 0, -1)),
+      Some(RelativePos::new(31, 0, -31))
+    );
+    assert_eq!(
+      RelativePos::new(31, 0, 0).checked_add(RelativePos::new(1, 0, 0)),
+      None
+    );
+    assert_eq!(
+      RelativePos::new(0, -31, 0).checked_add(RelativePos::new(0, -1, 0)),
+      None
+    );
+    assert_eq!(
+      RelativePos::new(0, 0, 31).checked_add(RelativePos::new(0, 0, 31)),
+      None
+    );
+  }
+
+  #[test]
+  #[should_panic(expected = "relative position out of range")]
+  fn test_offset_out_of_range() { RelativePos::new(31, 0, 0).offset(RelativePos::new(1, 0, 0)); }
 }
@@ -0,0 +1,1070 @@
+use std::fmt;
+
+use crate::{
+  block::BlockType,
+  block_registry::BlockRegistry,
+  query::*,
+  relative_pos::RelativePos,
+  sim::{Simulator, UpdaterHandle},
+  unique_descrip::UniqueDescrip,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuleError {
+  /// Counting from 1
+  pub line: usize,
+  pub message: String,
+}
+
+impl fmt::Display for RuleError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}: {}", self.line, self.message)
+  }
+}
+
+impl std::error::Error for RuleError {}
+
+/// Updaters written as text, so that they can live in data files. There is
+/// one rule per line:
+///
+/// ```text
+/// # Conway's Game of Life
+/// LIFE: count(neighbors8 == LIFE) not in 2..3 -> EMPTY
+/// EMPTY: count(neighbors8 == LIFE) == 3 -> LIFE
+/// ```
+///
+/// A rule turns a block of the type before the colon into the type after the
+/// arrow when its condition is true. When more than one rule matches a block,
+/// the first one wins.
+///
+/// Conditions are made of:
+///
+/// - numbers, `true`, `false` and block type names, which are looked up in the
+///   registry ignoring case
+/// - `type`, the type of the block being updated
+/// - `neighbors4`, `neighbors8` (in the XY plane), `neighbors6` and
+///   `neighbors26` (in 3D), which don't include the block itself. On their own
+///   they are the neighbors' types, and `neighbors8(expr)` evaluates `expr` at
+///   each neighbor instead. Comparing a neighborhood with a value compares each
+///   neighbor, so `neighbors8 == LIFE` is `neighbors8(type == LIFE)`.
+/// - `count`, `any` and `all` of a neighborhood of booleans, and `sum` of a
+///   neighborhood of numbers
+/// - `+`, `==`, `!=`, `<`, `<=`, `>`, `>=`, `and`, `or`, `not` and brackets
+/// - `x in 2..3`, which includes both ends, `x in [LIFE, EMPTY]`, and the same
+///   with `not in`
+///
+/// Brackets and `not`s can be nested at most `MAX_NESTING` deep. Everything
+/// after a `#` is a comment.
+#[derive(Clone)]
+pub struct Rules {
+  rules: Vec<Rule>,
+}
+
+#[derive(Clone)]
+struct Rule {
+  target: BlockType,
+  condition: BoxedQuery<bool>,
+  result: BlockType,
+}
+
+impl Rules {
+  pub fn parse(source: &str, registry: &BlockRegistry) -> Result<Rules, RuleError> {
+    let mut rules = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+      let text = line.split('#').next().unwrap();
+      if text.trim().is_empty() {
+        continue;
+      }
+      let rule = parse_rule(text, registry).map_err(|message| RuleError {
+        line: i + 1,
+        message,
+      })?;
+      rules.push(rule);
+    }
+    Ok(Rules { rules })
+  }
+
+  pub fn len(&self) -> usize { self.rules.len() }
+
+  pub fn is_empty(&self) -> bool { self.rules.is_empty() }
+
+  /// Adds one updater for each block type that the rules target
+  pub fn add_to(&self, sim: &mut Simulator) {
+    let mut targets: Vec<BlockType> = Vec::new();
+    for rule in self.rules.iter() {
+      if !targets.contains(&rule.target) {
+        targets.push(rule.target);
+      }
+    }
+
+    for &target in targets.iter() {
+      let rules: Vec<Rule> = self
+        .rules
+        .iter()
+        .filter(|rule| rule.target == target)
+        .cloned()
+        .collect();
+      sim.add_updater(target, move |updater| {
+        let conditions: Vec<_> = rules
+          .iter()
+          .map(|rule| (updater.prepare_query(&rule.condition), rule.result))
+          .collect();
+        updater.implement(move |handle: &UpdaterHandle| {
+          conditions
+            .iter()
+            .find(|(condition, _)| handle.query(condition))
+            .map(|&(_, result)| result)
+        });
+      });
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+  Name(String),
+  Number(u32),
+  Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Token::Name(name) => write!(f, "`{}`", name),
+      Token::Number(number) => write!(f, "`{}`", number),
+      Token::Symbol(symbol) => write!(f, "`{}`", symbol),
+    }
+  }
+}
+
+// Longer symbols come first, so that e.g. `<=` isn't read as `<` then `=`
+const SYMBOLS: [&str; 15] = [
+  "->", "..", "==", "!=", "<=", ">=", "<", ">", "+", ":", ",", "(", ")", "[", "]",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+  let mut tokens = Vec::new();
+  let mut rest = text.trim_start();
+  while let Some(c) = rest.chars().next() {
+    let len = if c.is_ascii_alphabetic() || c == '_' {
+      let len = rest
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(rest.len());
+      tokens.push(Token::Name(rest[..len].to_string()));
+      len
+    } else if c.is_ascii_digit() {
+      let len = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+      let number = rest[..len]
+        .parse()
+        .map_err(|_| format!("`{}` is too big", &rest[..len]))?;
+      tokens.push(Token::Number(number));
+      len
+    } else if let Some(&symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+      tokens.push(Token::Symbol(symbol));
+      symbol.len()
+    } else {
+      return Err(format!("unexpected `{}`", c));
+    };
+    rest = rest[len..].trim_start();
+  }
+  Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Aggregate {
+  Count,
+  Sum,
+  Any,
+  All,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+  Number(u32),
+  Bool(bool),
+  Block(BlockType),
+  Type,
+  Neighbors(Vec<RelativePos>, Box<Expr>),
+  Aggregate(Aggregate, Box<Expr>),
+  Add(Box<Expr>, Box<Expr>),
+  Compare(Comparison, Box<Expr>, Box<Expr>),
+  InRange(Box<Expr>, u32, u32),
+  InList(Box<Expr>, Vec<Expr>),
+  Not(Box<Expr>),
+  And(Box<Expr>, Box<Expr>),
+  Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+  fn is_literal(&self) -> bool {
+    match self {
+      Expr::Number(_) | Expr::Bool(_) | Expr::Block(_) => true,
+      _ => false,
+    }
+  }
+}
+
+fn neighbors(name: &str) -> Option<Vec<RelativePos>> {
+  let (radius_z, include): (i8, fn(i8, i8, i8) -> bool) = match name {
+    "neighbors4" => (0, |x, y, _| x.abs() + y.abs() == 1),
+    "neighbors8" => (0, |x, y, _| x != 0 || y != 0),
+    "neighbors6" => (1, |x, y, z| x.abs() + y.abs() + z.abs() == 1),
+    "neighbors26" => (1, |x, y, z| x != 0 || y != 0 || z != 0),
+    _ => return None,
+  };
+  let mut offsets = Vec::new();
+  for z in -radius_z..=radius_z {
+    for y in -1..=1 {
+      for x in -1..=1 {
+        if include(x, y, z) {
+          offsets.push(RelativePos::new(x, y, z));
+        }
+      }
+    }
+  }
+  Some(offsets)
+}
+
+/// How deep brackets, including those of aggregates and neighborhoods, and
+/// `not`s can be nested. This keeps the parser's stack small and nested
+/// neighborhoods within `MAX_NEIGHBORHOOD_DISTANCE`.
+pub const MAX_NESTING: usize = 16;
+
+struct Parser<'r> {
+  tokens: Vec<Token>,
+  next: usize,
+  depth: usize,
+  registry: &'r BlockRegistry,
+}
+
+fn parse_rule(text: &str, registry: &BlockRegistry) -> Result<Rule, String> {
+  let mut parser = Parser {
+    tokens: tokenize(text)?,
+    next: 0,
+    depth: 0,
+    registry,
+  };
+  let target = parser.block_name()?;
+  parser.expect(":")?;
+  let condition = parser.expr()?;
+  parser.expect("->")?;
+  let result = parser.block_name()?;
+  if let Some(token) = parser.peek() {
+    return Err(format!("expected the end of the rule, found {}", token));
+  }
+
+  match compile(&condition)? {
+    Compiled::Bool(condition) => Ok(Rule {
+      target,
+      condition,
+      result,
+    }),
+    other => Err(format!(
+      "the condition must be true or false, but it is {}",
+      other.describe()
+    )),
+  }
+}
+
+impl<'r> Parser<'r> {
+  fn peek(&self) -> Option<&Token> { self.tokens.get(self.next) }
+
+  fn peek_symbol(&self, symbol: &str) -> bool {
+    match self.peek() {
+      Some(Token::Symbol(found)) => *found == symbol,
+      _ => false,
+    }
+  }
+
+  fn peek_name(&self, name: &str) -> bool {
+    match self.peek() {
+      Some(Token::Name(found)) => found == name,
+      _ => false,
+    }
+  }
+
+  fn unexpected(&self, expected: &str) -> String {
+    match self.peek() {
+      Some(token) => format!("expected {}, found {}", expected, token),
+      None => format!("expected {}, found the end of the rule", expected),
+    }
+  }
+
+  fn expect(&mut self, symbol: &str) -> Result<(), String> {
+    if self.peek_symbol(symbol) {
+      self.next += 1;
+      Ok(())
+    } else {
+      Err(self.unexpected(&format!("`{}`", symbol)))
+    }
+  }
+
+  fn number(&mut self) -> Result<u32, String> {
+    match self.peek() {
+      Some(&Token::Number(number)) => {
+        self.next += 1;
+        Ok(number)
+      },
+      _ => Err(self.unexpected("a number")),
+    }
+  }
+
+  fn block_name(&mut self) -> Result<BlockType, String> {
+    match self.peek() {
+      Some(Token::Name(name)) => {
+        let block_type = self.lookup(name)?;
+        self.next += 1;
+        Ok(block_type)
+      },
+      _ => Err(self.unexpected("a block type")),
+    }
+  }
+
+  fn lookup(&self, name: &str) -> Result<BlockType, String> {
+    let lowercase = name.to_lowercase();
+    self
+      .registry
+      .lookup(name)
+      .or_else(|| {
+        self
+          .registry
+          .iter()
+          .find(|(_, meta)| meta.name.to_lowercase() == lowercase)
+          .map(|(block_type, _)| block_type)
+      })
+      .ok_or_else(|| format!("unknown block type `{}`", name))
+  }
+
+  fn expr(&mut self) -> Result<Expr, String> {
+    let mut expr = self.and()?;
+    while self.peek_name("or") {
+      self.next += 1;
+      expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+    }
+    Ok(expr)
+  }
+
+  fn and(&mut self) -> Result<Expr, String> {
+    let mut expr = self.not()?;
+    while self.peek_name("and") {
+      self.next += 1;
+      expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+    }
+    Ok(expr)
+  }
+
+  fn not(&mut self) -> Result<Expr, String> {
+    if self.peek_name("not") {
+      self.next += 1;
+      return Ok(Expr::Not(Box::new(self.nested(Parser::not)?)));
+    }
+    self.comparison()
+  }
+
+  fn comparison(&mut self) -> Result<Expr, String> {
+    let left = self.sum()?;
+
+    let comparisons = [
+      ("==", Comparison::Eq),
+      ("!=", Comparison::Ne),
+      ("<=", Comparison::Le),
+      (">=", Comparison::Ge),
+      ("<", Comparison::Lt),
+      (">", Comparison::Gt),
+    ];
+    for &(symbol, comparison) in comparisons.iter() {
+      if self.peek_symbol(symbol) {
+        self.next += 1;
+        let right = self.sum()?;
+        return Ok(Expr::Compare(comparison, Box::new(left), Box::new(right)));
+      }
+    }
+
+    let negated = self.peek_name("not");
+    if negated {
+      self.next += 1;
+      if !self.peek_name("in") {
+        return Err(self.unexpected("`in`"));
+      }
+    }
+    if !self.peek_name("in") {
+      return Ok(left);
+    }
+    self.next += 1;
+
+    let expr = if self.peek_symbol("[") {
+      self.next += 1;
+      let mut values = Vec::new();
+      while !self.peek_symbol("]") {
+        if !values.is_empty() {
+          self.expect(",")?;
+        }
+        values.push(self.sum()?);
+      }
+      self.next += 1;
+      Expr::InList(Box::new(left), values)
+    } else {
+      let low = self.number()?;
+      self.expect("..")?;
+      let high = self.number()?;
+      Expr::InRange(Box::new(left), low, high)
+    };
+    if negated {
+      Ok(Expr::Not(Box::new(expr)))
+    } else {
+      Ok(expr)
+    }
+  }
+
+  fn sum(&mut self) -> Result<Expr, String> {
+    let mut expr = self.atom()?;
+    while self.peek_symbol("+") {
+      self.next += 1;
+      expr = Expr::Add(Box::new(expr), Box::new(self.atom()?));
+    }
+    Ok(expr)
+  }
+
+  fn bracketed(&mut self) -> Result<Expr, String> {
+    self.expect("(")?;
+    let expr = self.nested(Parser::expr)?;
+    self.expect(")")?;
+    Ok(expr)
+  }
+
+  /// Parses one level deeper, failing past `MAX_NESTING`
+  fn nested(&mut self, parse: fn(&mut Self) -> Result<Expr, String>) -> Result<Expr, String> {
+    if self.depth == MAX_NESTING {
+      return Err(format!("can't nest more than {} deep", MAX_NESTING));
+    }
+    self.depth += 1;
+    let expr = parse(self);
+    self.depth -= 1;
+    expr
+  }
+
+  fn atom(&mut self) -> Result<Expr, String> {
+    if self.peek_symbol("(") {
+      return self.bracketed();
+    }
+    let name = match self.peek() {
+      Some(&Token::Number(number)) => {
+        self.next += 1;
+        return Ok(Expr::Number(number));
+      },
+      Some(Token::Name(name)) => name.clone(),
+      _ => return Err(self.unexpected("a value")),
+    };
+
+    let aggregate = match name.as_str() {
+      "count" => Some(Aggregate::Count),
+      "sum" => Some(Aggregate::Sum),
+      "any" => Some(Aggregate::Any),
+      "all" => Some(Aggregate::All),
+      _ => None,
+    };
+    if let Some(aggregate) = aggregate {
+      self.next += 1;
+      return Ok(Expr::Aggregate(aggregate, Box::new(self.bracketed()?)));
+    }
+
+    if let Some(offsets) = neighbors(&name) {
+      self.next += 1;
+      let map_expr = if self.peek_symbol("(") {
+        self.bracketed()?
+      } else {
+        Expr::Type
+      };
+      return Ok(Expr::Neighbors(offsets, Box::new(map_expr)));
+    }
+
+    let expr = match name.as_str() {
+      "true" => Expr::Bool(true),
+      "false" => Expr::Bool(false),
+      "type" => Expr::Type,
+      "and" | "or" | "not" | "in" => return Err(self.unexpected("a value")),
+      _ => Expr::Block(self.lookup(&name)?),
+    };
+    self.next += 1;
+    Ok(expr)
+  }
+}
+
+enum Compiled {
+  Bool(BoxedQuery<bool>),
+  Number(BoxedQuery<u32>),
+  Block(BoxedQuery<BlockType>),
+  Bools(BoxedIterQuery<bool>),
+  Numbers(BoxedIterQuery<u32>),
+  // Nothing aggregates block types, so only the type is kept
+  Blocks,
+}
+
+impl Compiled {
+  fn describe(&self) -> &'static str {
+    match self {
+      Compiled::Bool(_) => "true or false",
+      Compiled::Number(_) => "a number",
+      Compiled::Block(_) => "a block type",
+      Compiled::Bools(_) => "a neighborhood of trues and falses",
+      Compiled::Numbers(_) => "a neighborhood of numbers",
+      Compiled::Blocks => "a neighborhood of block types",
+    }
+  }
+}
+
+fn equals<T>(left: &BoxedQuery<T>, right: &BoxedQuery<T>) -> BoxedQuery<bool>
+where
+  T: PartialEq + 'static,
+{
+  BoxedQuery::new(&Equals::new(left, right))
+}
+
+fn in_list<T>(expr: &BoxedQuery<T>, values: &[T]) -> BoxedQuery<bool>
+where
+  T: Copy + PartialEq + UniqueDescrip + 'static,
+{
+  BoxedQuery::new(&InSet::new(expr, values))
+}
+
+fn less_than(left: &BoxedQuery<u32>, right: &BoxedQuery<u32>) -> BoxedQuery<bool> {
+  BoxedQuery::new(&LessThan::new(left, right))
+}
+
+fn not(expr: &BoxedQuery<bool>) -> BoxedQuery<bool> { BoxedQuery::new(&Not::new(expr)) }
+
+// Neighborhoods are compared one neighbor at a time, which only makes sense
+// when the other side is the same wherever it's evaluated
+fn push_into_neighborhood(
+  left: &Expr,
+  right: &Expr,
+  build: impl Fn(Expr, Expr) -> Expr,
+) -> Result<Option<Expr>, String> {
+  match (left, right) {
+    (Expr::Neighbors(offsets, map_expr), other) if other.is_literal() => Ok(Some(Expr::Neighbors(
+      offsets.clone(),
+      Box::new(build((**map_expr).clone(), other.clone())),
+    ))),
+    (other, Expr::Neighbors(offsets, map_expr)) if other.is_literal() => Ok(Some(Expr::Neighbors(
+      offsets.clone(),
+      Box::new(build(other.clone(), (**map_expr).clone())),
+    ))),
+    (Expr::Neighbors(..), _) | (_, Expr::Neighbors(..)) => {
+      Err("a neighborhood can only be compared with a number, block type, true or false".into())
+    },
+    _ => Ok(None),
+  }
+}
+
+fn mismatch(what: &str, left: &Compiled, right: &Compiled) -> String {
+  format!(
+    "can't {} {} and {}",
+    what,
+    left.describe(),
+    right.describe()
+  )
+}
+
+fn compile(expr: &Expr) -> Result<Compiled, String> {
+  Ok(match expr {
+    Expr::Number(number) => Compiled::Number(BoxedQuery::new(&Constant::new(*number))),
+    Expr::Bool(value) => Compiled::Bool(BoxedQuery::new(&Constant::new(*value))),
+    Expr::Block(block_type) => Compiled::Block(BoxedQuery::new(&Constant::new(*block_type))),
+    Expr::Type => Compiled::Block(BoxedQuery::new(&GetBlockType::new())),
+
+    Expr::Neighbors(offsets, map_expr) => match compile(map_expr)? {
+      Compiled::Bool(q) => Compiled::Bools(BoxedIterQuery::new(&OffsetNeighbors::new(offsets, &q))),
+      Compiled::Number(q) => {
+        Compiled::Numbers(BoxedIterQuery::new(&OffsetNeighbors::new(offsets, &q)))
+      },
+      Compiled::Block(_) => Compiled::Blocks,
+      other => {
+        return Err(format!(
+          "can't take the neighbors of {}, try aggregating it first",
+          other.describe()
+        ))
+      },
+    },
+
+    Expr::Aggregate(aggregate, expr) => match (aggregate, compile(expr)?) {
+      (Aggregate::Count, Compiled::Bools(q)) => Compiled::Number(BoxedQuery::new(&Count::new(&q))),
+      (Aggregate::Any, Compiled::Bools(q)) => Compiled::Bool(BoxedQuery::new(&Any::new(&q))),
+      (Aggregate::All, Compiled::Bools(q)) => Compiled::Bool(BoxedQuery::new(&All::new(&q))),
+      (Aggregate::Sum, Compiled::Numbers(q)) => Compiled::Number(BoxedQuery::new(&Sum::new(&q))),
+      (Aggregate::Sum, other) => {
+        return Err(format!(
+          "sum needs a neighborhood of numbers, not {}",
+          other.describe()
+        ))
+      },
+      (_, other) => {
+        return Err(format!(
+          "{} needs a neighborhood of trues and falses, not {}",
+          format!("{:?}", aggregate).to_lowercase(),
+          other.describe()
+        ))
+      },
+    },
+
+    Expr::Add(left, right) => match (compile(left)?, compile(right)?) {
+      (Compiled::Number(l), Compiled::Number(r)) => {
+        Compiled::Number(BoxedQuery::new(&Add::new(&l, &r)))
+      },
+      (l, r) => return Err(mismatch("add", &l, &r)),
+    },
+
+    Expr::Compare(comparison, left, right) => {
+      let comparison = *comparison;
+      let pushed = push_into_neighborhood(left, right, |l, r| {
+        Expr::Compare(comparison, Box::new(l), Box::new(r))
+      })?;
+      if let Some(pushed) = pushed {
+        return compile(&pushed);
+      }
+
+      let (l, r) = (compile(left)?, compile(right)?);
+      let equal = match (&l, &r) {
+        (Compiled::Bool(l), Compiled::Bool(r)) => Some(equals(l, r)),
+        (Compiled::Number(l), Compiled::Number(r)) => Some(equals(l, r)),
+        (Compiled::Block(l), Compiled::Block(r)) => Some(equals(l, r)),
+        _ => None,
+      };
+      Compiled::Bool(match (comparison, equal, &l, &r) {
+        (Comparison::Eq, Some(equal), _, _) => equal,
+        (Comparison::Ne, Some(equal), _, _) => not(&equal),
+        (_, _, Compiled::Number(l), Compiled::Number(r)) => match comparison {
+          Comparison::Lt => less_than(l, r),
+          Comparison::Gt => less_than(r, l),
+          Comparison::Le => not(&less_than(r, l)),
+          Comparison::Ge => not(&less_than(l, r)),
+          Comparison::Eq | Comparison::Ne => unreachable!(),
+        },
+        _ => return Err(mismatch("compare", &l, &r)),
+      })
+    },
+
+    Expr::InRange(expr, low, high) => {
+      let (low, high) = (*low, *high);
+      let pushed = push_into_neighborhood(expr, &Expr::Number(low), |expr, _| {
+        Expr::InRange(Box::new(expr), low, high)
+      })?;
+      if let Some(pushed) = pushed {
+        return compile(&pushed);
+      }
+
+      match compile(expr)? {
+        Compiled::Number(q) => {
+          let too_low = less_than(&q, &BoxedQuery::new(&Constant::new(low)));
+          let too_high = less_than(&BoxedQuery::new(&Constant::new(high)), &q);
+          Compiled::Bool(not(&BoxedQuery::new(&Or::new(&too_low, &too_high))))
+        },
+        other => return Err(format!("{} isn't in a range of numbers", other.describe())),
+      }
+    },
+
+    Expr::InList(expr, values) => {
+      if let Some(value) = values.iter().find(|value| !value.is_literal()) {
+        return Err(format!(
+          "lists can only hold numbers, block types, true and false, not {}",
+          compile(value)
+            .map(|value| value.describe())
+            .unwrap_or("that")
+        ));
+      }
+      let pushed = push_into_neighborhood(expr, &Expr::Bool(true), |expr, _| {
+        Expr::InList(Box::new(expr), values.clone())
+      })?;
+      if let Some(pushed) = pushed {
+        return compile(&pushed);
+      }
+
+      let compiled = compile(expr)?;
+      let mut numbers = Vec::new();
+      let mut bools = Vec::new();
+      let mut blocks = Vec::new();
+      for value in values.iter() {
+        match value {
+          Expr::Number(number) => numbers.push(*number),
+          Expr::Bool(value) => bools.push(*value),
+          Expr::Block(block_type) => blocks.push(*block_type),
+          _ => unreachable!(),
+        }
+      }
+      let in_list = match &compiled {
+        Compiled::Number(q) if bools.is_empty() && blocks.is_empty() => in_list(q, &numbers),
+        Compiled::Bool(q) if numbers.is_empty() && blocks.is_empty() => in_list(q, &bools),
+        Compiled::Block(q) if numbers.is_empty() && bools.is_empty() => in_list(q, &blocks),
+        _ => {
+          return Err(format!(
+            "the list holds values that can't be compared with {}",
+            compiled.describe()
+          ))
+        },
+      };
+      Compiled::Bool(in_list)
+    },
+
+    Expr::Not(expr) => match compile(expr)? {
+      Compiled::Bool(q) => Compiled::Bool(not(&q)),
+      other => return Err(format!("can't negate {}", other.describe())),
+    },
+
+    Expr::And(left, right) => match (compile(left)?, compile(right)?) {
+      (Compiled::Bool(l), Compiled::Bool(r)) => Compiled::Bool(BoxedQuery::new(&And::new(&l, &r))),
+      (l, r) => return Err(mismatch("and", &l, &r)),
+    },
+
+    Expr::Or(left, right) => match (compile(left)?, compile(right)?) {
+      (Compiled::Bool(l), Compiled::Bool(r)) => Compiled::Bool(BoxedQuery::new(&Or::new(&l, &r))),
+      (l, r) => return Err(mismatch("or", &l, &r)),
+    },
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    block::EMPTY,
+    block_registry::BlockMeta,
+    chunk::Chunk,
+    chunk_coord::ChunkCoord,
+    chunk_map::ChunkMap,
+    debug::Debugger,
+    life::{self, life_registry},
+  };
+
+  const LIFE_RULES: &str = "
+    # Conway's Game of Life
+    LIFE: count(neighbors8 == LIFE) not in 2..3 -> EMPTY
+    EMPTY: count(neighbors8 == LIFE) == 3 -> LIFE
+  ";
+
+  fn parse_error(source: &str) -> RuleError {
+    match Rules::parse(source, &life_registry()) {
+      Ok(_) => panic!("{} should not parse", source),
+      Err(err) => err,
+    }
+  }
+
+  fn condition(source: &str) -> BoxedQuery<bool> {
+    let rules = Rules::parse(source, &life_registry()).unwrap();
+    rules.rules[0].condition.clone()
+  }
+
+  #[test]
+  fn test_life_rules() {
+    let registry = life_registry();
+    let rules = Rules::parse(LIFE_RULES, &registry).unwrap();
+    assert_eq!(rules.len(), 2);
+    let mut sim = Simulator::new();
+    rules.add_to(&mut sim);
+
+    let debugger = Debugger::from_registry(&registry);
+    let mut chunk = Chunk::new();
+    chunk.fill_with_block_type(EMPTY);
+    debugger.load(
+      &mut chunk,
+      ".....
+       .....
+       .LLL.
+       .....
+       .....",
+    );
+    let origin = ChunkCoord::origin();
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.load(origin, chunk);
+
+    sim.step(&mut chunk_map);
+    debugger.assert_match(
+      chunk_map.get(origin).unwrap().get(),
+      ".....
+       ..L..
+       ..L..
+       ..L..
+       .....",
+    );
+
+    sim.step(&mut chunk_map);
+    debugger.assert_match(
+      chunk_map.get(origin).unwrap().get(),
+      ".....
+       .....
+       .LLL.
+       .....
+       .....",
+    );
+  }
+
+  #[test]
+  fn test_first_matching_rule_wins() {
+    let registry = life_registry();
+    let rules = Rules::parse(
+      "EMPTY: true -> LIFE
+       EMPTY: true -> UNKNOWN
+       LIFE: false -> EMPTY",
+      &registry,
+    )
+    .unwrap();
+    let mut sim = Simulator::new();
+    rules.add_to(&mut sim);
+
+    let mut chunk = Chunk::new();
+    chunk.fill_with_block_type(EMPTY);
+    let origin = ChunkCoord::origin();
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.load(origin, chunk);
+
+    sim.step(&mut chunk_map);
+    sim.step(&mut chunk_map);
+    let chunk = chunk_map.get(origin).unwrap().get();
+    let life = life::life_block_type(&registry);
+    assert!(chunk
+      .blocks_iter()
+      .all(|(_, block)| block.block_type() == life));
+  }
+
+  #[test]
+  fn test_neighborhood_comparisons() {
+    // Comparing a neighborhood compares each neighbor
+    assert_eq!(
+      condition("EMPTY: count(neighbors8 == LIFE) == 3 -> LIFE").unique_descrip(),
+      condition("EMPTY: count(neighbors8(type == LIFE)) == 3 -> LIFE").unique_descrip()
+    );
+    assert_eq!(
+      condition("EMPTY: any(LIFE != neighbors4) -> LIFE").unique_descrip(),
+      condition("EMPTY: any(neighbors4(LIFE != type)) -> LIFE").unique_descrip()
+    );
+    assert_eq!(
+      condition("EMPTY: all(neighbors6 in [LIFE, EMPTY]) -> LIFE").unique_descrip(),
+      condition("EMPTY: all(neighbors6(type in [LIFE, EMPTY])) -> LIFE").unique_descrip()
+    );
+
+    // Neighborhoods leave out the block itself
+    assert_eq!(
+      condition("EMPTY: any(neighbors8 == LIFE) -> LIFE").cacheability(),
+      Cacheability::until_change_in(
+        vec![CacheableField::CacheableBlockType],
+        Neighborhood::offsets(&neighbors("neighbors8").unwrap())
+      )
+    );
+    assert_eq!(
+      condition("EMPTY: type == LIFE -> LIFE").cacheability(),
+      Cacheability::UntilChangeInSelf {
+        fields: vec![CacheableField::CacheableBlockType]
+      }
+    );
+  }
+
+  #[test]
+  fn test_nested_neighborhoods() {
+    const NESTED: &str = "EMPTY: sum(neighbors4(count(neighbors8 == LIFE))) + 1 >= 3 -> LIFE";
+    let registry = life_registry();
+    let rules = Rules::parse(NESTED, &registry).unwrap();
+    let mut sim = Simulator::new();
+    rules.add_to(&mut sim);
+
+    // Blocks touching the life block have two neighbors that see it, and the
+    // ones further out have at most one
+    let debugger = Debugger::from_registry(&registry);
+    let mut chunk = Chunk::new();
+    chunk.fill_with_block_type(EMPTY);
+    debugger.load(
+      &mut chunk,
+      ".....
+       .....
+       ..L..
+       .....
+       .....",
+    );
+    let origin = ChunkCoord::origin();
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.load(origin, chunk);
+
+    sim.step(&mut chunk_map);
+    debugger.assert_match(
+      chunk_map.get(origin).unwrap().get(),
+      ".....
+       .LLL.
+       .LLL.
+       .LLL.
+       .....",
+    );
+
+    // The condition reads every block two steps away
+    let neighborhood = Neighborhood::offsets(&neighbors("neighbors8").unwrap())
+      .plus(&Neighborhood::offsets(&neighbors("neighbors4").unwrap()));
+    assert_eq!(
+      condition(NESTED).cacheability(),
+      Cacheability::until_change_in(
+        vec![CacheableField::CacheableBlockType],
+        neighborhood.clone()
+      )
+    );
+    assert!(neighborhood.contains(RelativePos::new(2, 1, 0)));
+    assert!(neighborhood.contains(RelativePos::here()));
+    assert!(!neighborhood.contains(RelativePos::new(2, 2, 0)));
+  }
+
+  #[test]
+  fn test_arithmetic_saturates() {
+    let registry = life_registry();
+    let rules = Rules::parse(
+      "EMPTY: sum(neighbors8(4000000000)) > 1 -> LIFE
+       LIFE: 4000000000 + 4000000000 == 4294967295 -> EMPTY",
+      &registry,
+    )
+    .unwrap();
+    let mut sim = Simulator::new();
+    rules.add_to(&mut sim);
+
+    let mut chunk = Chunk::new();
+    chunk.fill_with_block_type(EMPTY);
+    let origin = ChunkCoord::origin();
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.load(origin, chunk);
+
+    sim.step(&mut chunk_map);
+    let life = life::life_block_type(&registry);
+    assert!(chunk_map
+      .get(origin)
+      .unwrap()
+      .get()
+      .blocks_iter()
+      .all(|(_, block)| block.block_type() == life));
+
+    sim.step(&mut chunk_map);
+    assert!(chunk_map
+      .get(origin)
+      .unwrap()
+      .get()
+      .blocks_iter()
+      .all(|(_, block)| block.block_type() == EMPTY));
+  }
+
+  #[test]
+  fn test_syntax_errors() {
+    let err = parse_error(
+      "# Nothing wrong here
+       LIFE: true -> EMPTY
+
+       LIFE: count(neighbors8 == LIFE -> EMPTY",
+    );
+    assert_eq!(err.line, 4);
+    assert_eq!(err.message, "expected `)`, found `->`");
+    assert_eq!(err.to_string(), "line 4: expected `)`, found `->`");
+
+    assert_eq!(
+      parse_error("LIFE: true -> EMPTY EMPTY").message,
+      "expected the end of the rule, found `EMPTY`"
+    );
+    assert_eq!(
+      parse_error("LIFE true -> EMPTY").message,
+      "expected `:`, found `true`"
+    );
+    assert_eq!(
+      parse_error("LIFE: 3 in 2 -> EMPTY").message,
+      "expected `..`, found `->`"
+    );
+    assert_eq!(
+      parse_error("LIFE: 3 = 3 -> EMPTY").message,
+      "unexpected `=`"
+    );
+    assert_eq!(
+      parse_error("LIFE: not -> EMPTY").message,
+      "expected a value, found `->`"
+    );
+  }
+
+  #[test]
+  fn test_nesting_limit() {
+    let nest = |depth: usize, open: &str, close: &str| {
+      format!(
+        "EMPTY: {}true{} -> LIFE",
+        open.repeat(depth),
+        close.repeat(depth)
+      )
+    };
+    let limit = format!("can't nest more than {} deep", MAX_NESTING);
+
+    // Each `any(neighbors8(` is two levels, and reaches one block further
+    let registry = life_registry();
+    let deepest = nest(MAX_NESTING / 2, "any(neighbors8(", "))");
+    let mut sim = Simulator::new();
+    Rules::parse(&deepest, &registry).unwrap().add_to(&mut sim);
+    let too_deep = nest(MAX_NESTING / 2 + 1, "any(neighbors8(", "))");
+    assert_eq!(parse_error(&too_deep).message, limit);
+    assert_eq!(
+      parse_error(&nest(32, "any(neighbors8(", "))")).message,
+      limit
+    );
+
+    assert_eq!(parse_error(&nest(100_000, "(", ")")).message, limit);
+    assert_eq!(parse_error(&nest(100_000, "not ", "")).message, limit);
+    assert_eq!(parse_error(&nest(100_000, "(", "")).message, limit);
+  }
+
+  #[test]
+  fn test_unknown_block_types() {
+    assert!(Rules::parse("life: TRUE == true -> empty", &life_registry()).is_err());
+    assert_eq!(
+      parse_error("LIFE: type == SAND -> EMPTY").message,
+      "unknown block type `SAND`"
+    );
+    assert_eq!(
+      parse_error("SAND: true -> EMPTY").message,
+      "unknown block type `SAND`"
+    );
+
+    // Names match whatever their case
+    let mut registry = life_registry();
+    let sand = registry.register(BlockMeta::new("Sand", "#ff0", 'S'));
+    let rules = Rules::parse("sAND: type == SaNd -> SAND", &registry).unwrap();
+    assert_eq!(rules.rules[0].target, sand);
+    assert_eq!(rules.rules[0].result, sand);
+  }
+
+  #[test]
+  fn test_type_errors() {
+    assert_eq!(
+      parse_error("LIFE: count(neighbors8) -> EMPTY").message,
+      "count needs a neighborhood of trues and falses, not a neighborhood of block types"
+    );
+    assert_eq!(
+      parse_error("LIFE: count(neighbors8 == LIFE) -> EMPTY").message,
+      "the condition must be true or false, but it is a number"
+    );
+    assert_eq!(
+      parse_error("LIFE: type < LIFE -> EMPTY").message,
+      "can't compare a block type and a block type"
+    );
+    assert_eq!(
+      parse_error("LIFE: type == 3 -> EMPTY").message,
+      "can't compare a block type and a number"
+    );
+    assert_eq!(
+      parse_error("LIFE: neighbors8 == type -> EMPTY").message,
+      "a neighborhood can only be compared with a number, block type, true or false"
+    );
+    assert_eq!(
+      parse_error("LIFE: neighbors4(neighbors4) -> EMPTY").message,
+      "can't take the neighbors of a neighborhood of block types, try aggregating it first"
+    );
+    assert_eq!(
+      parse_error("LIFE: type in [LIFE, 3] -> EMPTY").message,
+      "the list holds values that can't be compared with a block type"
+    );
+  }
+}
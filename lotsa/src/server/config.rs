@@ -0,0 +1,134 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use crate::{
+  block_registry::BlockRegistry, chunk_map::ChunkMap, game::Game, life::Life,
+  protocol::DEFAULT_TICK_INTERVAL_MS, sim::Simulator, topology::Topology,
+};
+
+pub type BlockSetup = dyn Fn(&mut BlockRegistry) + Send + Sync;
+pub type WorldLoader = dyn Fn(&BlockRegistry) -> ChunkMap + Send + Sync;
+pub type SimSetup = dyn Fn(&mut Simulator, &BlockRegistry) + Send + Sync;
+
+/// Everything a game can change about how the server runs. The defaults run
+/// Game of Life on port 8000.
+pub struct ServerConfig {
+  pub(super) bind_address: String,
+  pub(super) pkg_dir: PathBuf,
+  pub(super) www_dir: PathBuf,
+  pub(super) tick_interval: Duration,
+  pub(super) log_filter: Option<String>,
+  pub(super) save_dir: Option<PathBuf>,
+  pub(super) autosave_ticks: u64,
+  pub(super) topology: Topology,
+  pub(super) block_setup: Box<BlockSetup>,
+  pub(super) world_loader: Box<WorldLoader>,
+  pub(super) sim_setup: Box<SimSetup>,
+}
+
+impl ServerConfig {
+  pub fn new() -> ServerConfig { ServerConfig::with_game(Life) }
+
+  /// Uses the game's blocks, rules and world. These can still be overridden
+  /// one at a time with `register_blocks` etc.
+  pub fn with_game<G: Game>(game: G) -> ServerConfig {
+    let game = Arc::new(game);
+    let (blocks_game, world_game) = (game.clone(), game.clone());
+    ServerConfig {
+      bind_address: "0.0.0.0:8000".to_string(),
+      pkg_dir: PathBuf::from("pkg/"),
+      www_dir: PathBuf::from("www/"),
+      tick_interval: Duration::from_millis(DEFAULT_TICK_INTERVAL_MS.into()),
+      log_filter: Some("info".to_string()),
+      save_dir: None,
+      autosave_ticks: 600,
+      topology: game.topology(),
+      block_setup: Box::new(move |registry| blocks_game.register_blocks(registry)),
+      world_loader: Box::new(move |registry| world_game.generate_world(registry)),
+      sim_setup: Box::new(move |sim, registry| game.init_simulator(sim, registry)),
+    }
+  }
+
+  pub fn bind_address(mut self, bind_address: &str) -> ServerConfig {
+    self.bind_address = bind_address.to_string();
+    self
+  }
+
+  /// Where the compiled client is served from, at `/pkg/`
+  pub fn pkg_dir<P: Into<PathBuf>>(mut self, pkg_dir: P) -> ServerConfig {
+    self.pkg_dir = pkg_dir.into();
+    self
+  }
+
+  /// Where everything else is served from, with `index.html` at `/`
+  pub fn www_dir<P: Into<PathBuf>>(mut self, www_dir: P) -> ServerConfig {
+    self.www_dir = www_dir.into();
+    self
+  }
+
+  pub fn tick_interval(mut self, tick_interval: Duration) -> ServerConfig {
+    self.tick_interval = tick_interval;
+    self
+  }
+
+  /// Used as the log filter when `RUST_LOG` isn't set. With `None`, only
+  /// `RUST_LOG` is used.
+  pub fn log_filter(mut self, log_filter: Option<&str>) -> ServerConfig {
+    self.log_filter = log_filter.map(|filter| filter.to_string());
+    self
+  }
+
+  /// Where the world is saved. If there's a saved world there at startup, it
+  /// is loaded instead of generating a new one. Without a save directory,
+  /// the world is lost when the server stops.
+  pub fn save_dir<P: Into<PathBuf>>(mut self, save_dir: P) -> ServerConfig {
+    self.save_dir = Some(save_dir.into());
+    self
+  }
+
+  /// How many ticks go by between saves, if there's a save directory. The
+  /// world is also saved when the server shuts down.
+  pub fn autosave_ticks(mut self, autosave_ticks: u64) -> ServerConfig {
+    self.autosave_ticks = autosave_ticks;
+    self
+  }
+
+  /// The shape of the world, which a saved world is loaded into. Should match
+  /// the world from `load_world`.
+  pub fn topology(mut self, topology: Topology) -> ServerConfig {
+    self.topology = topology;
+    self
+  }
+
+  /// Registers the game's block types, before the world is loaded
+  pub fn register_blocks<F>(mut self, block_setup: F) -> ServerConfig
+  where
+    F: Fn(&mut BlockRegistry) + Send + Sync + 'static,
+  {
+    self.block_setup = Box::new(block_setup);
+    self
+  }
+
+  /// Builds the world the server starts with, unless there's a saved world
+  /// to load instead
+  pub fn load_world<F>(mut self, world_loader: F) -> ServerConfig
+  where
+    F: Fn(&BlockRegistry) -> ChunkMap + Send + Sync + 'static,
+  {
+    self.world_loader = Box::new(world_loader);
+    self
+  }
+
+  /// Adds the game's updaters to the simulator, using block types from the
+  /// registry
+  pub fn setup_simulator<F>(mut self, sim_setup: F) -> ServerConfig
+  where
+    F: Fn(&mut Simulator, &BlockRegistry) + Send + Sync + 'static,
+  {
+    self.sim_setup = Box::new(sim_setup);
+    self
+  }
+}
+
+impl Default for ServerConfig {
+  fn default() -> ServerConfig { ServerConfig::new() }
+}
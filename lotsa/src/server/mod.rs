@@ -1,7 +1,7 @@
 use std::{
   collections::{HashMap, VecDeque},
   convert::TryInto,
-  io::Write,
+  sync::Mutex,
   time::{Duration, Instant},
 };
 
@@ -9,13 +9,28 @@ use actix::{prelude::*, *};
 use actix_files as fs;
 use actix_web::{web, HttpRequest};
 use actix_web_actors::ws;
-use bincode::serialize;
-use flate2::{write::ZlibEncoder, Compression};
+use futures::{Future, Stream};
+
+use crate::{
+  block_registry::BlockRegistry,
+  chunk_coord::ChunkCoord,
+  chunk_map::ChunkMap,
+  game::Game,
+  mutation::{BlockChange, BlockWrite},
+  persist::WorldStore,
+  protocol::{ChunkRegion, ChunkUpdate, ClientCommand, ServerEvent, SimControl, SimState},
+  sim::Simulator,
+};
+
+mod config;
 
-use crate::{block::EMPTY, chunk::Chunk, debug::Debugger, life, sim::Simulator};
+pub use config::ServerConfig;
 
 #[derive(Debug, Message)]
-struct ClientMessage {}
+struct ClientMessage {
+  session: SessionId,
+  command: ClientCommand,
+}
 
 #[derive(Debug, Message)]
 struct ServerMessage {
@@ -32,71 +47,287 @@ impl Message for ClientConnected {
   type Result = SessionId;
 }
 
+#[derive(Debug, Message)]
+struct ClientDisconnected {
+  session: SessionId,
+}
+
 #[derive(Debug, Message)]
 struct Tick {}
 
+#[derive(Debug, Message)]
+struct Control(SimControl);
+
+/// Saves the world, then stops the system
+#[derive(Debug, Message)]
+struct Shutdown {}
+
+struct Session {
+  addr: Addr<WebsocketSession>,
+  region: ChunkRegion,
+}
+
 struct World {
-  chunk: Chunk,
+  registry: BlockRegistry,
+  chunk_map: ChunkMap,
   sim: Simulator,
+  seq: u64,
+  state: SimState,
+  tick_handle: Option<SpawnHandle>,
+  store: Option<WorldStore>,
+  autosave_ticks: u64,
+  ticks_since_save: u64,
+  // Changes made by clients since the last tick
+  pending_writes: Vec<BlockWrite>,
   next_id: usize,
   step_durations: VecDeque<Duration>,
-  sessions: HashMap<usize, Addr<WebsocketSession>>,
+  sessions: HashMap<SessionId, Session>,
 }
 
 impl World {
-  fn new() -> World {
-    let mut chunk = Chunk::new();
-    chunk.fill_with_block_type(EMPTY);
-
-    Debugger::new(hashmap!(EMPTY => '.', life::LIFE => 'L')).load(
-      &mut chunk,
-      ".....
-        .LLL.
-        .....
-        .....
-        .....
-        .LLL.
-        .....",
-    );
+  fn new(config: &ServerConfig) -> World {
+    let mut registry = BlockRegistry::new();
+    (config.block_setup)(&mut registry);
+
+    let store = config.save_dir.as_ref().map(WorldStore::new);
+    let saved = store.as_ref().and_then(|store| {
+      // Refusing to start is better than autosaving over a world that
+      // couldn't be read
+      let saved = store
+        .load(config.topology, &registry)
+        .unwrap_or_else(|err| panic!("load world from {}: {}", store.dir().display(), err));
+      if let Some(saved) = &saved {
+        info!(
+          "loaded {} chunks from {}",
+          saved.len(),
+          store.dir().display()
+        );
+      }
+      saved
+    });
+    // Only generated if there's no saved world, since it can be expensive
+    let chunk_map = saved.unwrap_or_else(|| (config.world_loader)(&registry));
 
     let mut sim = Simulator::new();
-    life::init(&mut sim);
+    (config.sim_setup)(&mut sim, &registry);
+
+    let mut state = SimState::new();
+    state.apply(SimControl::SetTickInterval {
+      millis: duration_millis(config.tick_interval),
+    });
 
     World {
-      chunk,
+      registry,
+      chunk_map,
       sim,
+      seq: 0,
+      state,
+      tick_handle: None,
+      store,
+      autosave_ticks: config.autosave_ticks,
+      ticks_since_save: 0,
+      pending_writes: Vec::new(),
       next_id: 1,
       step_durations: VecDeque::new(),
       sessions: HashMap::new(),
     }
   }
 
-  fn encode_chunk_and_step(&mut self) -> Vec<u8> {
-    let serialized = serialize(&self.chunk).expect("serialize chunk");
+  fn send_snapshots(&self, session: &Session, region: ChunkRegion) {
+    for (coord, loaded_chunk) in self.chunk_map.iter() {
+      if region.contains(coord) {
+        send_event(
+          session,
+          &ServerEvent::Chunk(ChunkUpdate::snapshot(
+            self.seq,
+            loaded_chunk,
+            &self.registry,
+          )),
+        );
+      }
+    }
+  }
 
-    self.sim.step(&mut self.chunk);
+  fn broadcast_state(&self) {
+    let event = ServerEvent::SimState(self.state);
+    for session in self.sessions.values() {
+      send_event(session, &event);
+    }
+  }
 
-    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-    encoder.write_all(&serialized).expect("compress message");
-    encoder.finish().expect("finish compressing message")
+  /// (Re)starts the timer that sends ticks, at the current tick interval
+  fn schedule_ticks(&mut self, ctx: &mut Context<Self>) {
+    if let Some(handle) = self.tick_handle.take() {
+      ctx.cancel_future(handle);
+    }
+    let interval = Duration::from_millis(self.state.tick_interval_ms.into());
+    self.tick_handle = Some(ctx.run_interval(interval, |_, ctx| {
+      ctx.address().do_send(Tick {});
+    }));
   }
+
+  fn save(&mut self) {
+    self.ticks_since_save = 0;
+    if let Some(store) = &self.store {
+      match store.save(&self.chunk_map, &self.registry) {
+        Ok(()) => info!(
+          "saved {} chunks to {}",
+          self.chunk_map.len(),
+          store.dir().display()
+        ),
+        Err(err) => warn!("couldn't save world to {}: {}", store.dir().display(), err),
+      }
+    }
+  }
+
+  fn control(&mut self, control: SimControl, ctx: &mut Context<Self>) {
+    let old_interval = self.state.tick_interval_ms;
+    self.state.apply(control);
+    if self.state.tick_interval_ms != old_interval {
+      self.schedule_ticks(ctx);
+    }
+    self.broadcast_state();
+  }
+
+  /// Runs a step unless paused, and sends every subscribed client a delta
+  /// for each of its chunks
+  fn tick(&mut self) {
+    let mut writes = std::mem::replace(&mut self.pending_writes, Vec::new());
+    let stepped = self.state.take_step();
+    if stepped {
+      let step_start = Instant::now();
+      writes.extend(self.sim.step(&mut self.chunk_map).writes);
+      self.record_step_duration(step_start.elapsed());
+      if self.state.paused {
+        // One of the requested steps got used up
+        self.broadcast_state();
+      }
+    }
+    self.seq += 1;
+
+    self.ticks_since_save += 1;
+    if self.autosave_ticks > 0 && self.ticks_since_save >= self.autosave_ticks {
+      self.save();
+    }
+
+    for (coord, loaded_chunk) in self.chunk_map.iter() {
+      let subscribers: Vec<(&SessionId, &Session)> = self
+        .sessions
+        .iter()
+        .filter(|(_, session)| session.region.contains(coord))
+        .collect();
+      if subscribers.is_empty() {
+        continue;
+      }
+      let bytes = ServerEvent::Chunk(ChunkUpdate::delta(self.seq, loaded_chunk, &writes)).encode();
+      for (id, session) in subscribers {
+        // FIXME: Probably inefficient to clone the vec
+        let message = ServerMessage {
+          bytes: bytes.clone(),
+        };
+        if let Err(err) = session.addr.try_send(message) {
+          warn!("couldn't send a delta to client #{}: {}", id, err);
+        }
+      }
+    }
+  }
+
+  fn record_step_duration(&mut self, duration: Duration) {
+    self.step_durations.push_back(duration);
+    let durations_len: u32 = self
+      .step_durations
+      .len()
+      .try_into()
+      .expect("small number of durations");
+    if durations_len >= 50 {
+      let total_duration: Duration = self.step_durations.drain(..).sum();
+      let avg_duration: Duration = total_duration / durations_len;
+      info!("average step duration: {}ms", avg_duration.as_millis());
+    }
+  }
+
+  fn apply_command(&mut self, id: SessionId, command: ClientCommand, ctx: &mut Context<Self>) {
+    match command {
+      ClientCommand::SetBlock { pos, block_type } => {
+        if self.registry.get(block_type).is_none() {
+          warn!(
+            "ignoring unregistered block type {:?} from client {}",
+            block_type, id
+          );
+          return;
+        }
+        if let Some(pos) = self.chunk_map.topology().resolve(pos) {
+          if self.chunk_map.set_block_type(pos, block_type) {
+            self.pending_writes.push(BlockWrite {
+              pos,
+              change: BlockChange::BlockType(block_type),
+            });
+          }
+        }
+      },
+      ClientCommand::Control(control) => self.control(control, ctx),
+      ClientCommand::Subscribe(region) => {
+        if let Some(session) = self.sessions.get_mut(&id) {
+          session.region = region;
+        }
+        if let Some(session) = self.sessions.get(&id) {
+          self.send_snapshots(session, region);
+        }
+      },
+      ClientCommand::Ping { nonce } => {
+        if let Some(session) = self.sessions.get(&id) {
+          send_event(session, &ServerEvent::Pong { nonce });
+        }
+      },
+      ClientCommand::Resync(coord) => {
+        if let Some(session) = self.sessions.get(&id) {
+          if session.region.contains(coord) {
+            self.send_snapshots(session, ChunkRegion::single(coord));
+          }
+        }
+      },
+    }
+  }
+}
+
+fn duration_millis(duration: Duration) -> u32 {
+  duration.as_millis().try_into().unwrap_or(std::u32::MAX)
+}
+
+fn send_event(session: &Session, event: &ServerEvent) {
+  session.addr.do_send(ServerMessage {
+    bytes: event.encode(),
+  });
 }
 
 impl Actor for World {
   type Context = Context<Self>;
 
-  fn started(&mut self, ctx: &mut Self::Context) {
-    ctx.run_interval(Duration::from_millis(100), |_, ctx| {
-      ctx.address().do_send(Tick {});
-    });
-  }
+  fn started(&mut self, ctx: &mut Self::Context) { self.schedule_ticks(ctx); }
 }
 
 impl Handler<ClientMessage> for World {
   type Result = ();
 
-  fn handle(&mut self, msg: ClientMessage, _ctx: &mut Context<Self>) {
+  fn handle(&mut self, msg: ClientMessage, ctx: &mut Context<Self>) {
     info!("got client message {:?}", msg);
+    self.apply_command(msg.session, msg.command, ctx);
+  }
+}
+
+impl Handler<Control> for World {
+  type Result = ();
+
+  fn handle(&mut self, msg: Control, ctx: &mut Context<Self>) { self.control(msg.0, ctx); }
+}
+
+impl Handler<Shutdown> for World {
+  type Result = ();
+
+  fn handle(&mut self, _msg: Shutdown, _ctx: &mut Context<Self>) {
+    info!("shutting down");
+    self.save();
+    System::current().stop();
   }
 }
 
@@ -107,40 +338,31 @@ impl Handler<ClientConnected> for World {
     let id = self.next_id;
     self.next_id = self.next_id + 1;
     info!("client #{} connected", id);
-    self.sessions.insert(id, msg.session);
+    let session = Session {
+      addr: msg.session,
+      region: ChunkRegion::single(ChunkCoord::origin()),
+    };
+    send_event(&session, &ServerEvent::Registry(self.registry.clone()));
+    self.send_snapshots(&session, session.region);
+    send_event(&session, &ServerEvent::SimState(self.state));
+    self.sessions.insert(id, session);
     id
   }
 }
 
-impl Handler<Tick> for World {
+impl Handler<ClientDisconnected> for World {
   type Result = ();
 
-  fn handle(&mut self, _msg: Tick, ctx: &mut Context<Self>) {
-    let step_start = Instant::now();
-
-    let bytes = self.encode_chunk_and_step();
+  fn handle(&mut self, msg: ClientDisconnected, _ctx: &mut Context<Self>) {
+    info!("client #{} disconnected", msg.session);
+    self.sessions.remove(&msg.session);
+  }
+}
 
-    self.step_durations.push_back(step_start.elapsed());
-    let durations_len: u32 = self
-      .step_durations
-      .len()
-      .try_into()
-      .expect("small number of durations");
-    if durations_len >= 50 {
-      let total_duration: Duration = self.step_durations.drain(..).sum();
-      let avg_duration: Duration = total_duration / durations_len;
-      info!("average step duration: {}ms", avg_duration.as_millis());
-    }
+impl Handler<Tick> for World {
+  type Result = ();
 
-    for (_id, session) in self.sessions.iter() {
-      // FIXME: Probably inefficient to clone the vec
-      session
-        .try_send(ServerMessage {
-          bytes: bytes.clone(),
-        })
-        .expect("send message to client session");
-    }
-  }
+  fn handle(&mut self, _msg: Tick, _ctx: &mut Context<Self>) { self.tick(); }
 }
 
 struct WebsocketSession {
@@ -178,16 +400,37 @@ impl Actor for WebsocketSession {
       })
       .wait(ctx);
   }
+
+  fn stopped(&mut self, _ctx: &mut Self::Context) {
+    info!("ws session stopped");
+    if let Some(session) = self.id {
+      self
+        .web_common
+        .world
+        .do_send(ClientDisconnected { session });
+    }
+  }
 }
 
 impl StreamHandler<ws::Message, ws::ProtocolError> for WebsocketSession {
   fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
     info!("got ws message {:?}", msg);
-    self
-      .web_common
-      .world
-      .try_send(ClientMessage {})
-      .expect("send message to world process");
+    let command = match msg {
+      ws::Message::Binary(bytes) => match ClientCommand::decode(&bytes) {
+        Ok(command) => command,
+        Err(err) => {
+          warn!("ignoring bad client message: {}", err);
+          return;
+        },
+      },
+      _ => return,
+    };
+    if let Some(session) = self.id {
+      let message = ClientMessage { session, command };
+      if let Err(err) = self.web_common.world.try_send(message) {
+        warn!("couldn't send a client message to the world: {}", err);
+      }
+    }
   }
 }
 
@@ -212,31 +455,84 @@ struct WebCommon {
   world: Addr<World>,
 }
 
-pub struct Server {}
+pub struct Server {
+  config: ServerConfig,
+  world: Mutex<Option<Addr<World>>>,
+}
 
 impl Server {
-  pub fn new() -> Server { Server {} }
+  pub fn new() -> Server { Server::with_config(ServerConfig::new()) }
+
+  pub fn with_game<G: Game>(game: G) -> Server {
+    Server::with_config(ServerConfig::with_game(game))
+  }
+
+  pub fn with_config(config: ServerConfig) -> Server {
+    Server {
+      config,
+      world: Mutex::new(None),
+    }
+  }
+
+  pub fn pause(&self) { self.control(SimControl::Pause); }
+
+  pub fn resume(&self) { self.control(SimControl::Resume); }
+
+  /// Pauses, then runs `ticks` more steps
+  pub fn step(&self, ticks: u32) { self.control(SimControl::Step { ticks }); }
+
+  pub fn set_tick_interval(&self, interval: Duration) {
+    self.control(SimControl::SetTickInterval {
+      millis: duration_millis(interval),
+    });
+  }
+
+  fn control(&self, control: SimControl) {
+    match self.world.lock().expect("lock world address").as_ref() {
+      Some(world) => world.do_send(Control(control)),
+      None => warn!("ignoring {:?}, server isn't running", control),
+    }
+  }
 
   pub fn start(&self) -> std::io::Result<()> {
-    if let Err(_) = std::env::var("RUST_LOG") {
-      std::env::set_var("RUST_LOG", "info");
+    if let (Err(_), Some(log_filter)) = (std::env::var("RUST_LOG"), &self.config.log_filter) {
+      std::env::set_var("RUST_LOG", log_filter);
     }
     pretty_env_logger::init();
 
     let sys = System::new("lotsa");
 
-    let world = World::new().start();
+    let world = World::new(&self.config).start();
+    *self.world.lock().expect("lock world address") = Some(world.clone());
+
+    // Handled here instead of by actix-web, which would stop the system
+    // without giving the world a chance to save
+    let shutdown_world = world.clone();
+    actix::spawn(
+      tokio_signal::ctrl_c()
+        .flatten_stream()
+        .into_future()
+        .map_err(|(err, _)| error!("couldn't listen for ctrl-c: {}", err))
+        .and_then(move |_| {
+          shutdown_world
+            .send(Shutdown {})
+            .map_err(|err| error!("couldn't shut down world: {}", err))
+        }),
+    );
 
+    let pkg_dir = self.config.pkg_dir.clone();
+    let www_dir = self.config.www_dir.clone();
     actix_web::HttpServer::new(move || {
       actix_web::App::new()
         .data(WebCommon {
           world: world.clone(),
         })
         .service(web::resource("/ws/").to(websockets_route))
-        .service(fs::Files::new("/pkg/", "pkg/"))
-        .service(fs::Files::new("/", "www/").index_file("index.html"))
+        .service(fs::Files::new("/pkg/", &pkg_dir))
+        .service(fs::Files::new("/", &www_dir).index_file("index.html"))
     })
-    .bind("0.0.0.0:8000")?
+    .disable_signals()
+    .bind(&self.config.bind_address)?
     .start();
 
     sys.run()
@@ -7,39 +7,67 @@ use std::{
 
 use crate::{
   block::{BlockType, UNKNOWN},
+  block_field::FieldId,
   chunk::Chunk,
+  chunk_map::ChunkMap,
   chunk_pos::ChunkPos,
-  loaded_chunk::LoadedChunk,
-  query::{BlockInfo, Cacheability, Context, Query},
+  conflict::{self, Conflict, ConflictPolicy, Proposal},
+  mutation::{BlockChange, BlockWrite, IntoMutations, Mutation},
+  query::{BlockInfo, Cacheability, CacheabilitySet, Context, Query},
   relative_pos::RelativePos,
+  world_pos::WorldPos,
 };
 
 pub struct Simulator {
   updaters: Vec<(BlockType, Box<Updater>)>,
+  // Every cacheability that some updater's set contains, so that updaters
+  // with overlapping sets share cache busters
   cacheabilities: HashSet<Cacheability>,
+  neighbor_fallback: NeighborFallback,
+  conflict_policy: ConflictPolicy,
+  report_conflicts: bool,
+}
+
+/// What happened during a step
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StepReport {
+  /// Every write made to the world, in the order they were applied
+  pub writes: Vec<BlockWrite>,
+  /// Only filled in if conflict reporting is turned on
+  pub conflicts: Vec<Conflict>,
+}
+
+/// What updaters see when they look at a position that is outside of the world,
+/// or in a chunk that isn't loaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NeighborFallback {
+  Unknown,
+  /// Wraps around to the opposite side of the chunk doing the lookup
+  WrapAround,
+  Fixed(BlockType),
 }
 
 pub struct Updater {
   // TODO: Use a builder pattern so that updater_fn doesn't need to be wrapped in Option
-  updater_fn: Option<Box<dyn Fn(&UpdaterHandle) -> Option<BlockType>>>,
-  cacheability: Cacheability,
+  updater_fn: Option<Box<dyn Fn(&UpdaterHandle) -> Vec<Mutation>>>,
+  cacheability: CacheabilitySet,
+  priority: i32,
 }
 
 impl Updater {
   fn new() -> Updater {
     Updater {
       updater_fn: None,
-      cacheability: Cacheability::Forever,
+      cacheability: CacheabilitySet::new(),
+      priority: 0,
     }
   }
 
-  fn run(&self, chunk: &Chunk, pos: ChunkPos) -> Option<BlockType> {
-    let handle = UpdaterHandle {
-      context: UpdaterContext {
-        chunk,
-        chunk_pos: pos,
-      },
-    };
+  /// Used by `ConflictPolicy::Priority`, higher priorities win
+  pub fn set_priority(&mut self, priority: i32) { self.priority = priority; }
+
+  fn run(&self, context: UpdaterContext) -> Vec<Mutation> {
+    let handle = UpdaterHandle { context };
     self.updater_fn.as_ref().unwrap()(&handle)
   }
 
@@ -47,12 +75,15 @@ impl Updater {
   where
     Q: Query<'a, T>,
   {
-    self.cacheability = Cacheability::merge(&self.cacheability, &query.cacheability());
+    self.cacheability = CacheabilitySet::union(&self.cacheability, &query.cacheability());
     PreparedQuery::new(query)
   }
 
-  pub fn implement(&mut self, updater_fn: impl Fn(&UpdaterHandle) -> Option<BlockType> + 'static) {
-    self.updater_fn = Some(Box::new(updater_fn))
+  pub fn implement<R: IntoMutations>(
+    &mut self,
+    updater_fn: impl Fn(&UpdaterHandle) -> R + 'static,
+  ) {
+    self.updater_fn = Some(Box::new(move |handle| updater_fn(handle).into_mutations()))
   }
 }
 
@@ -110,26 +141,94 @@ impl<'a> UpdaterHandle<'a> {
   }
 }
 
+#[derive(Clone, Copy)]
 struct UpdaterContext<'a> {
+  chunk_map: &'a ChunkMap,
   chunk: &'a Chunk,
   chunk_pos: ChunkPos,
+  world_pos: WorldPos,
+  neighbor_fallback: NeighborFallback,
 }
 
 impl<'a> Context for UpdaterContext<'a> {
   fn get_block(&self, rel_pos: RelativePos) -> BlockInfo {
-    match self.chunk_pos.offset(rel_pos) {
-      Some(pos) => self.chunk.get_block(pos),
-      None => BlockInfo {
-        block_type: UNKNOWN,
+    let target = self.world_pos.offset(rel_pos);
+    let topology = self.chunk_map.topology();
+
+    if let Some(pos) = self.chunk_pos.offset(rel_pos) {
+      if topology.contains(target) {
+        return self.chunk.get_block(pos);
+      }
+    }
+
+    let loaded_block = topology
+      .resolve(target)
+      .and_then(|resolved| self.chunk_map.get_block(resolved));
+    match loaded_block {
+      Some(block) => block,
+      None => match self.neighbor_fallback {
+        NeighborFallback::Unknown => BlockInfo::new(UNKNOWN),
+        NeighborFallback::WrapAround => self.chunk.get_block(target.chunk_pos()),
+        NeighborFallback::Fixed(block_type) => BlockInfo::new(block_type),
       },
     }
   }
 }
 
-#[derive(Clone, Copy, Debug)]
-struct BlockTypeUpdate {
-  pos: ChunkPos,
-  block_type: BlockType,
+impl<'a> UpdaterContext<'a> {
+  /// Where a mutation at the given offset would land, if anywhere
+  fn resolve(&self, rel_pos: RelativePos) -> Option<WorldPos> {
+    self
+      .chunk_map
+      .topology()
+      .resolve(self.world_pos.offset(rel_pos))
+  }
+
+  /// Converts a mutation into concrete writes. Mutations aimed at positions
+  /// outside of the world or in unloaded chunks are dropped.
+  fn write_mutation(&self, mutation: Mutation, writes: &mut Vec<BlockWrite>) {
+    let here = RelativePos::here();
+    let (at, change) = match mutation {
+      Mutation::SetBlockType(block_type) => (here, BlockChange::BlockType(block_type)),
+      Mutation::SetField(field_id, value) => (here, BlockChange::Field(field_id, value)),
+      Mutation::SetBlockTypeAt(at, block_type) => (at, BlockChange::BlockType(block_type)),
+      Mutation::SetFieldAt(at, field_id, value) => (at, BlockChange::Field(field_id, value)),
+      Mutation::SwapWith(at) => return self.write_swap(at, writes),
+    };
+    if let Some(pos) = self.resolve(at) {
+      if self.chunk_map.is_loaded(pos.chunk_coord()) {
+        writes.push(BlockWrite { pos, change });
+      }
+    }
+  }
+
+  fn write_swap(&self, at: RelativePos, writes: &mut Vec<BlockWrite>) {
+    let other_pos = match self.resolve(at) {
+      Some(pos) => pos,
+      None => return,
+    };
+    let other = match self.chunk_map.get_block(other_pos) {
+      Some(block) => block,
+      None => return,
+    };
+    let this = self.chunk.get_block(self.chunk_pos);
+
+    for &(pos, from, to) in [(self.world_pos, &other, &this), (other_pos, &this, &other)].iter() {
+      writes.push(BlockWrite {
+        pos,
+        change: BlockChange::BlockType(from.block_type),
+      });
+      for field_id in FieldId::all() {
+        let i = field_id.index();
+        if from.fields[i] != to.fields[i] {
+          writes.push(BlockWrite {
+            pos,
+            change: BlockChange::Field(field_id, from.fields[i]),
+          });
+        }
+      }
+    }
+  }
 }
 
 impl Simulator {
@@ -137,36 +236,860 @@ impl Simulator {
     Simulator {
       updaters: Vec::new(),
       cacheabilities: HashSet::new(),
+      neighbor_fallback: NeighborFallback::Unknown,
+      conflict_policy: ConflictPolicy::default(),
+      report_conflicts: false,
     }
   }
 
-  pub fn add_updater(&mut self, target: BlockType, setup_fn: fn(&mut Updater)) {
+  pub fn set_neighbor_fallback(&mut self, neighbor_fallback: NeighborFallback) {
+    self.neighbor_fallback = neighbor_fallback;
+  }
+
+  pub fn set_conflict_policy(&mut self, conflict_policy: ConflictPolicy) {
+    self.conflict_policy = conflict_policy;
+  }
+
+  /// When on, each step logs and reports every block that more than one
+  /// updater run tried to write to
+  pub fn set_report_conflicts(&mut self, report_conflicts: bool) {
+    self.report_conflicts = report_conflicts;
+  }
+
+  pub fn add_updater(&mut self, target: BlockType, setup_fn: impl FnOnce(&mut Updater)) {
     let mut updater = Box::new(Updater::new());
     setup_fn(&mut updater);
-    self.cacheabilities.insert(updater.cacheability.clone());
+    self
+      .cacheabilities
+      .extend(updater.cacheability.iter().cloned());
     self.updaters.push((target, updater));
   }
 
-  pub fn step(&self, loaded_chunk: &mut LoadedChunk) {
-    let mut updates: Vec<BlockTypeUpdate> = Vec::new();
+  pub fn step(&self, chunk_map: &mut ChunkMap) -> StepReport {
+    let mut proposals: Vec<Proposal> = Vec::new();
+    // Updater runs that tried to change something, which get to run again
+    // next step even if nothing they read has changed, e.g. because their
+    // writes lost a conflict
+    let mut rerun: Vec<(WorldPos, &CacheabilitySet)> = Vec::new();
 
-    for (target_block_type, updater) in self.updaters.iter() {
-      for (pos, block) in loaded_chunk.considerable_blocks_iter(&updater.cacheability) {
-        if target_block_type == &block.block_type {
-          if let Some(new_block_type) = updater.run(loaded_chunk.get(), pos) {
-            updates.push(BlockTypeUpdate {
-              pos,
-              block_type: new_block_type,
-            });
+    for (coord, loaded_chunk) in chunk_map.iter() {
+      for (updater_index, (target_block_type, updater)) in self.updaters.iter().enumerate() {
+        let targets =
+          loaded_chunk.considerable_targets_iter(&updater.cacheability, *target_block_type);
+        for (pos, _) in targets {
+          let world_pos = WorldPos::from_parts(coord, pos);
+          if chunk_map.topology().contains(world_pos) {
+            let context = UpdaterContext {
+              chunk_map,
+              chunk: loaded_chunk.get(),
+              chunk_pos: pos,
+              world_pos,
+              neighbor_fallback: self.neighbor_fallback,
+            };
+            let mut writes = Vec::new();
+            for mutation in updater.run(context) {
+              context.write_mutation(mutation, &mut writes);
+            }
+            if !writes.is_empty() {
+              rerun.push((world_pos, &updater.cacheability));
+              proposals.push(Proposal {
+                updater: updater_index,
+                priority: updater.priority,
+                origin: world_pos,
+                writes,
+              });
+            }
           }
         }
       }
     }
 
-    loaded_chunk.reset_cache_busters(self.cacheabilities.iter());
+    let (writes, conflicts) =
+      conflict::resolve(&self.conflict_policy, proposals, self.report_conflicts);
+    for conflict in conflicts.iter() {
+      warn!("Write conflict: {:?}", conflict);
+    }
+
+    for (_, loaded_chunk) in chunk_map.iter_mut() {
+      loaded_chunk.reset_cache_busters(self.cacheabilities.iter());
+    }
+    for (world_pos, cacheability) in rerun {
+      let (coord, pos) = world_pos.split();
+      if let Some(loaded_chunk) = chunk_map.get_mut(coord) {
+        loaded_chunk.mark_considerable(cacheability, pos);
+      }
+    }
+
+    let wrap_around = self.neighbor_fallback == NeighborFallback::WrapAround;
+    for write in writes.iter() {
+      chunk_map.apply_write(write, wrap_around);
+    }
+
+    StepReport { writes, conflicts }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    block::EMPTY,
+    block_field::BlockField,
+    chunk::CHUNK_WIDTH_E3,
+    chunk_coord::ChunkCoord,
+    life::{self, life_registry},
+    query::{
+      At, Chebyshev2DNeighbors, Constant, Count, Equals, GetBlockField, GetBlockType, InSet, Not,
+    },
+    topology::Topology,
+  };
+  use std::{cell::Cell, thread::LocalKey};
+
+  fn life_type() -> BlockType { life::life_block_type(&life_registry()) }
+
+  fn life_chunk_map(coords: &[ChunkCoord], live_cells: &[WorldPos]) -> ChunkMap {
+    life_chunk_map_with_topology(Topology::Infinite, coords, live_cells)
+  }
+
+  fn life_chunk_map_with_topology(
+    topology: Topology,
+    coords: &[ChunkCoord],
+    live_cells: &[WorldPos],
+  ) -> ChunkMap {
+    let mut chunk_map = ChunkMap::with_topology(topology);
+    for &coord in coords {
+      let mut chunk = Chunk::new();
+      chunk.fill_with_block_type(EMPTY);
+      chunk_map.load(coord, chunk);
+    }
+    for &pos in live_cells {
+      chunk_map.set_block_type(pos, life_type());
+    }
+    chunk_map
+  }
+
+  fn life_simulator(neighbor_fallback: NeighborFallback) -> Simulator {
+    let mut sim = Simulator::new();
+    sim.set_neighbor_fallback(neighbor_fallback);
+    life::init(&mut sim, &life_registry());
+    sim
+  }
+
+  fn block_type_at(chunk_map: &ChunkMap, x: i32, y: i32) -> BlockType {
+    chunk_map
+      .get_block(WorldPos::new(x, y, 0))
+      .unwrap()
+      .block_type()
+  }
+
+  #[test]
+  fn test_blinker_across_chunk_border() {
+    let mut chunk_map = life_chunk_map(
+      &[ChunkCoord::new(-1, 0, 0), ChunkCoord::new(0, 0, 0)],
+      &[
+        WorldPos::new(-2, 5, 0),
+        WorldPos::new(-1, 5, 0),
+        WorldPos::new(0, 5, 0),
+      ],
+    );
+    let sim = life_simulator(NeighborFallback::Unknown);
+
+    sim.step(&mut chunk_map);
+
+    assert_eq!(block_type_at(&chunk_map, -1, 4), life_type());
+    assert_eq!(block_type_at(&chunk_map, -1, 5), life_type());
+    assert_eq!(block_type_at(&chunk_map, -1, 6), life_type());
+    assert_eq!(block_type_at(&chunk_map, -2, 5), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 0, 5), EMPTY);
+  }
+
+  #[test]
+  fn test_change_at_border_wakes_neighbor_chunk() {
+    let mut chunk_map = life_chunk_map(&[ChunkCoord::new(-1, 0, 0), ChunkCoord::new(0, 0, 0)], &[]);
+    let sim = life_simulator(NeighborFallback::Unknown);
+
+    // Nothing happens, but afterwards only marked positions are considered
+    sim.step(&mut chunk_map);
+
+    chunk_map.set_block_type(WorldPos::new(-1, 4, 0), life_type());
+    chunk_map.set_block_type(WorldPos::new(-1, 5, 0), life_type());
+    chunk_map.set_block_type(WorldPos::new(-1, 6, 0), life_type());
+
+    sim.step(&mut chunk_map);
+
+    assert_eq!(block_type_at(&chunk_map, 0, 5), life_type());
+    assert_eq!(block_type_at(&chunk_map, -2, 5), life_type());
+    assert_eq!(block_type_at(&chunk_map, -1, 4), EMPTY);
+  }
+
+  #[test]
+  fn test_unknown_fallback() {
+    let mut chunk_map = life_chunk_map(
+      &[ChunkCoord::origin()],
+      &[
+        WorldPos::new(31, 5, 0),
+        WorldPos::new(0, 5, 0),
+        WorldPos::new(1, 5, 0),
+      ],
+    );
+    let sim = life_simulator(NeighborFallback::Unknown);
+
+    sim.step(&mut chunk_map);
+
+    assert_eq!(block_type_at(&chunk_map, 0, 4), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 0, 5), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 31, 5), EMPTY);
+  }
+
+  #[test]
+  fn test_wrap_around_fallback() {
+    let mut chunk_map = life_chunk_map(
+      &[ChunkCoord::origin()],
+      &[
+        WorldPos::new(31, 5, 0),
+        WorldPos::new(0, 5, 0),
+        WorldPos::new(1, 5, 0),
+      ],
+    );
+    let sim = life_simulator(NeighborFallback::WrapAround);
+
+    sim.step(&mut chunk_map);
+
+    assert_eq!(block_type_at(&chunk_map, 0, 4), life_type());
+    assert_eq!(block_type_at(&chunk_map, 0, 5), life_type());
+    assert_eq!(block_type_at(&chunk_map, 0, 6), life_type());
+    assert_eq!(block_type_at(&chunk_map, 31, 5), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 1, 5), EMPTY);
+  }
 
-    for update in updates {
-      loaded_chunk.set_block_type(update.pos, update.block_type);
+  #[test]
+  fn test_wrap_around_across_edges_over_many_steps() {
+    // Wrapping a lone chunk around onto itself should behave just like a torus
+    // the size of the chunk, including the caching
+    let glider = [
+      WorldPos::new(1, 1, 0),
+      WorldPos::new(2, 1, 0),
+      WorldPos::new(3, 1, 0),
+      WorldPos::new(1, 2, 0),
+      WorldPos::new(2, 3, 0),
+    ];
+    let mut wrapped = life_chunk_map(&[ChunkCoord::origin()], &glider);
+    let wrapped_sim = life_simulator(NeighborFallback::WrapAround);
+    let mut torus =
+      life_chunk_map_with_topology(Topology::torus_2d(32, 32), &[ChunkCoord::origin()], &glider);
+    let torus_sim = life_simulator(NeighborFallback::Unknown);
+
+    // The glider moves up and to the left, so it crosses both edges
+    for _ in 0..24 {
+      wrapped_sim.step(&mut wrapped);
+      torus_sim.step(&mut torus);
+      for y in 0..32 {
+        for x in 0..32 {
+          assert_eq!(block_type_at(&wrapped, x, y), block_type_at(&torus, x, y));
+        }
+      }
     }
+    let population = (0..32)
+      .flat_map(|y| (0..32).map(move |x| (x, y)))
+      .filter(|&(x, y)| block_type_at(&wrapped, x, y) == life_type())
+      .count();
+    assert_eq!(population, 5);
+  }
+
+  #[test]
+  fn test_fixed_fallback() {
+    let mut chunk_map = life_chunk_map(&[ChunkCoord::origin()], &[]);
+    let sim = life_simulator(NeighborFallback::Fixed(life_type()));
+
+    sim.step(&mut chunk_map);
+
+    // Three live neighbors from beyond the west edge
+    assert_eq!(block_type_at(&chunk_map, 0, 5), life_type());
+    // Five live neighbors in the corner
+    assert_eq!(block_type_at(&chunk_map, 0, 0), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 1, 5), EMPTY);
+  }
+
+  #[test]
+  fn test_blinker_on_torus() {
+    let mut chunk_map = life_chunk_map_with_topology(
+      Topology::torus_2d(5, 5),
+      &[ChunkCoord::origin()],
+      &[
+        WorldPos::new(4, 0, 0),
+        WorldPos::new(0, 0, 0),
+        WorldPos::new(1, 0, 0),
+      ],
+    );
+    let sim = life_simulator(NeighborFallback::Unknown);
+
+    sim.step(&mut chunk_map);
+
+    assert_eq!(block_type_at(&chunk_map, 0, 4), life_type());
+    assert_eq!(block_type_at(&chunk_map, 0, 0), life_type());
+    assert_eq!(block_type_at(&chunk_map, 0, 1), life_type());
+    assert_eq!(block_type_at(&chunk_map, 4, 0), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 1, 0), EMPTY);
+
+    sim.step(&mut chunk_map);
+
+    assert_eq!(block_type_at(&chunk_map, 4, 0), life_type());
+    assert_eq!(block_type_at(&chunk_map, 0, 0), life_type());
+    assert_eq!(block_type_at(&chunk_map, 1, 0), life_type());
+    assert_eq!(block_type_at(&chunk_map, 0, 4), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 0, 1), EMPTY);
+  }
+
+  #[test]
+  fn test_blinker_at_bounded_edge() {
+    let mut chunk_map = life_chunk_map_with_topology(
+      Topology::bounded(WorldPos::new(0, 0, 0), WorldPos::new(5, 5, 1)),
+      &[ChunkCoord::origin()],
+      &[WorldPos::new(3, 2, 0), WorldPos::new(4, 2, 0)],
+    );
+    chunk_map
+      .get_mut(ChunkCoord::origin())
+      .unwrap()
+      .set_block_type(ChunkPos::new(5, 2, 0), life_type(), &Topology::Infinite);
+    let sim = life_simulator(NeighborFallback::Fixed(EMPTY));
+
+    sim.step(&mut chunk_map);
+
+    // The live cell outside of the world is neither seen nor simulated
+    assert_eq!(
+      chunk_map
+        .get(ChunkCoord::origin())
+        .unwrap()
+        .get()
+        .get_block(ChunkPos::new(5, 2, 0))
+        .block_type(),
+      life_type()
+    );
+    assert_eq!(block_type_at(&chunk_map, 3, 2), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 4, 2), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 4, 1), EMPTY);
+  }
+
+  // Conway's rules written entirely as queries, so the updaters only turn
+  // their answers into writes
+  fn query_life_simulator() -> Simulator {
+    let mut sim = Simulator::new();
+    sim.add_updater(life_type(), |updater| {
+      let is_life = Equals::new(&GetBlockType::new(), &Constant::new(life_type()));
+      // The neighborhood includes the block itself
+      let live_nearby = Count::new(&Chebyshev2DNeighbors::new(1, &is_life));
+      let dies = updater.prepare_query(&Not::new(&InSet::new(&live_nearby, &[3, 4])));
+      updater.implement(move |handle: &UpdaterHandle| {
+        if handle.query(&dies) {
+          Some(EMPTY)
+        } else {
+          None
+        }
+      });
+    });
+    sim.add_updater(EMPTY, |updater| {
+      let is_life = Equals::new(&GetBlockType::new(), &Constant::new(life_type()));
+      let live_nearby = Count::new(&Chebyshev2DNeighbors::new(1, &is_life));
+      let born = updater.prepare_query(&Equals::new(&live_nearby, &Constant::new(3)));
+      updater.implement(move |handle: &UpdaterHandle| {
+        if handle.query(&born) {
+          Some(life_type())
+        } else {
+          None
+        }
+      });
+    });
+    sim
+  }
+
+  #[test]
+  fn test_life_as_queries() {
+    let block = [
+      WorldPos::new(10, 10, 0),
+      WorldPos::new(11, 10, 0),
+      WorldPos::new(10, 11, 0),
+      WorldPos::new(11, 11, 0),
+    ];
+    let blinker = [
+      WorldPos::new(2, 3, 0),
+      WorldPos::new(3, 3, 0),
+      WorldPos::new(4, 3, 0),
+    ];
+    let live_cells: Vec<WorldPos> = block.iter().chain(blinker.iter()).cloned().collect();
+    let mut chunk_map = life_chunk_map(&[ChunkCoord::origin()], &live_cells);
+    let sim = query_life_simulator();
+
+    sim.step(&mut chunk_map);
+    assert_eq!(block_type_at(&chunk_map, 3, 2), life_type());
+    assert_eq!(block_type_at(&chunk_map, 3, 3), life_type());
+    assert_eq!(block_type_at(&chunk_map, 3, 4), life_type());
+    assert_eq!(block_type_at(&chunk_map, 2, 3), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 4, 3), EMPTY);
+
+    sim.step(&mut chunk_map);
+    for &pos in live_cells.iter() {
+      assert_eq!(chunk_map.get_block(pos).unwrap().block_type(), life_type());
+    }
+    assert_eq!(block_type_at(&chunk_map, 3, 2), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 3, 4), EMPTY);
+  }
+
+  #[test]
+  fn test_updater_sets_fields() {
+    const EMBER: BlockType = BlockType(40);
+    const AGE: BlockField<u8> = BlockField::new(0);
+
+    let mut sim = Simulator::new();
+    sim.add_updater(EMBER, |updater| {
+      let age = updater.prepare_query(&GetBlockField::new(AGE));
+      updater.implement(move |handle: &UpdaterHandle| {
+        let age = handle.query(&age);
+        if age >= 2 {
+          vec![Mutation::SetBlockType(EMPTY)]
+        } else {
+          vec![Mutation::set_field(AGE, age + 1)]
+        }
+      });
+    });
+
+    let pos = WorldPos::new(3, 4, 5);
+    let mut chunk_map = life_chunk_map(&[ChunkCoord::origin()], &[]);
+    chunk_map.set_block_type(pos, EMBER);
+
+    sim.step(&mut chunk_map);
+    assert_eq!(chunk_map.get_block(pos).unwrap().field(AGE), 1);
+    sim.step(&mut chunk_map);
+    assert_eq!(chunk_map.get_block(pos).unwrap().field(AGE), 2);
+    assert_eq!(chunk_map.get_block(pos).unwrap().block_type(), EMBER);
+    sim.step(&mut chunk_map);
+    assert_eq!(chunk_map.get_block(pos).unwrap().block_type(), EMPTY);
+  }
+
+  const SAND: BlockType = BlockType(41);
+  const WEIGHT: BlockField<u8> = BlockField::new(1);
+
+  fn falling_sand_simulator() -> Simulator {
+    let mut sim = Simulator::new();
+    sim.add_updater(SAND, |updater| {
+      let neighbors = updater.prepare_query(&Chebyshev2DNeighbors::new(1, &GetBlockType::new()));
+      updater.implement(move |handle: &UpdaterHandle| {
+        // Neighbors are visited row by row, so the block below is the 8th
+        let below = handle.query(&neighbors).nth(7);
+        if below == Some(EMPTY) {
+          Some(Mutation::SwapWith(RelativePos::new(0, 1, 0)))
+        } else {
+          None
+        }
+      });
+    });
+    sim
+  }
+
+  #[test]
+  fn test_swap() {
+    let mut chunk_map = life_chunk_map(&[ChunkCoord::origin()], &[]);
+    chunk_map.set_block_type(WorldPos::new(2, 0, 0), SAND);
+    chunk_map.set_field(WorldPos::new(2, 0, 0), WEIGHT, 9);
+    chunk_map.set_block_type(WorldPos::new(4, 0, 0), SAND);
+    chunk_map.set_block_type(WorldPos::new(4, 1, 0), SAND);
+    chunk_map.set_field(WorldPos::new(4, 1, 0), WEIGHT, 3);
+
+    let sim = falling_sand_simulator();
+    sim.step(&mut chunk_map);
+
+    assert_eq!(block_type_at(&chunk_map, 2, 0), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 2, 1), SAND);
+    assert_eq!(
+      chunk_map
+        .get_block(WorldPos::new(2, 0, 0))
+        .unwrap()
+        .field(WEIGHT),
+      0
+    );
+    assert_eq!(
+      chunk_map
+        .get_block(WorldPos::new(2, 1, 0))
+        .unwrap()
+        .field(WEIGHT),
+      9
+    );
+
+    // The top grain of the stack can't move until the bottom one has
+    assert_eq!(block_type_at(&chunk_map, 4, 0), SAND);
+    assert_eq!(block_type_at(&chunk_map, 4, 1), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 4, 2), SAND);
+    assert_eq!(
+      chunk_map
+        .get_block(WorldPos::new(4, 2, 0))
+        .unwrap()
+        .field(WEIGHT),
+      3
+    );
+
+    for _ in 0..40 {
+      sim.step(&mut chunk_map);
+    }
+
+    // Sand piles up against the unknown blocks beyond the bottom of the chunk
+    assert_eq!(block_type_at(&chunk_map, 2, 31), SAND);
+    assert_eq!(block_type_at(&chunk_map, 4, 30), SAND);
+    assert_eq!(block_type_at(&chunk_map, 4, 31), SAND);
+    assert_eq!(
+      chunk_map
+        .get_block(WorldPos::new(4, 31, 0))
+        .unwrap()
+        .field(WEIGHT),
+      3
+    );
+  }
+
+  #[test]
+  fn test_write_to_neighbor() {
+    const GROWTH: BlockType = BlockType(42);
+
+    let mut sim = Simulator::new();
+    sim.add_updater(GROWTH, |updater| {
+      updater.implement(|_handle: &UpdaterHandle| {
+        vec![
+          Mutation::SetBlockTypeAt(RelativePos::new(1, 0, 0), GROWTH),
+          Mutation::set_field_at(RelativePos::new(1, 0, 0), WEIGHT, 1),
+          Mutation::SetBlockType(EMPTY),
+        ]
+      });
+    });
+
+    let mut chunk_map = life_chunk_map(&[ChunkCoord::origin()], &[]);
+    chunk_map.set_block_type(WorldPos::new(30, 0, 0), GROWTH);
+
+    sim.step(&mut chunk_map);
+    assert_eq!(block_type_at(&chunk_map, 30, 0), EMPTY);
+    assert_eq!(block_type_at(&chunk_map, 31, 0), GROWTH);
+    assert_eq!(
+      chunk_map
+        .get_block(WorldPos::new(31, 0, 0))
+        .unwrap()
+        .field(WEIGHT),
+      1
+    );
+
+    // Writes into unloaded chunks are dropped
+    sim.step(&mut chunk_map);
+    assert_eq!(block_type_at(&chunk_map, 31, 0), EMPTY);
+    assert!(chunk_map.get_block(WorldPos::new(32, 0, 0)).is_none());
+  }
+
+  const RIGHTWARD: BlockType = BlockType(43);
+  const LEFTWARD: BlockType = BlockType(44);
+
+  fn move_right(updater: &mut Updater) {
+    updater.implement(|_handle: &UpdaterHandle| Mutation::SwapWith(RelativePos::new(1, 0, 0)));
+  }
+
+  fn move_left(updater: &mut Updater) {
+    updater.implement(|_handle: &UpdaterHandle| Mutation::SwapWith(RelativePos::new(-1, 0, 0)));
+  }
+
+  // A rightward mover at x = 1 and a leftward mover at x = 3 both try to move
+  // into x = 2
+  fn movers_chunk_map() -> ChunkMap {
+    let mut chunk_map = life_chunk_map(&[ChunkCoord::origin()], &[]);
+    chunk_map.set_block_type(WorldPos::new(1, 0, 0), RIGHTWARD);
+    chunk_map.set_block_type(WorldPos::new(3, 0, 0), LEFTWARD);
+    chunk_map
+  }
+
+  fn row(chunk_map: &ChunkMap) -> Vec<BlockType> {
+    (1..4).map(|x| block_type_at(chunk_map, x, 0)).collect()
+  }
+
+  #[test]
+  fn test_first_wins_conflict() {
+    let mut sim = Simulator::new();
+    sim.add_updater(RIGHTWARD, move_right);
+    sim.add_updater(LEFTWARD, move_left);
+
+    let mut chunk_map = movers_chunk_map();
+    let report = sim.step(&mut chunk_map);
+    assert_eq!(row(&chunk_map), vec![EMPTY, RIGHTWARD, LEFTWARD]);
+    assert!(report.conflicts.is_empty());
+    assert_eq!(
+      report.writes,
+      vec![
+        BlockWrite {
+          pos: WorldPos::new(1, 0, 0),
+          change: BlockChange::BlockType(EMPTY),
+        },
+        BlockWrite {
+          pos: WorldPos::new(2, 0, 0),
+          change: BlockChange::BlockType(RIGHTWARD),
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn test_last_wins_conflict() {
+    let mut sim = Simulator::new();
+    sim.set_conflict_policy(ConflictPolicy::LastWins);
+    sim.add_updater(RIGHTWARD, move_right);
+    sim.add_updater(LEFTWARD, move_left);
+
+    let mut chunk_map = movers_chunk_map();
+    sim.step(&mut chunk_map);
+    assert_eq!(row(&chunk_map), vec![RIGHTWARD, LEFTWARD, EMPTY]);
+  }
+
+  #[test]
+  fn test_priority_conflict() {
+    let mut sim = Simulator::new();
+    sim.set_conflict_policy(ConflictPolicy::Priority);
+    sim.add_updater(RIGHTWARD, move_right);
+    sim.add_updater(LEFTWARD, |updater| {
+      updater.set_priority(1);
+      move_left(updater);
+    });
+
+    let mut chunk_map = movers_chunk_map();
+    sim.step(&mut chunk_map);
+    assert_eq!(row(&chunk_map), vec![RIGHTWARD, LEFTWARD, EMPTY]);
+  }
+
+  #[test]
+  fn test_hash_tie_break_conflict() {
+    let mut sim = Simulator::new();
+    sim.set_conflict_policy(ConflictPolicy::HashTieBreak);
+    sim.add_updater(RIGHTWARD, move_right);
+    sim.add_updater(LEFTWARD, move_left);
+
+    let mut chunk_map = movers_chunk_map();
+    sim.step(&mut chunk_map);
+    let first_result = row(&chunk_map);
+    assert!(
+      first_result == vec![EMPTY, RIGHTWARD, LEFTWARD]
+        || first_result == vec![RIGHTWARD, LEFTWARD, EMPTY]
+    );
+
+    let mut chunk_map = movers_chunk_map();
+    sim.step(&mut chunk_map);
+    assert_eq!(row(&chunk_map), first_result);
+  }
+
+  #[test]
+  fn test_merge_conflict() {
+    let mut sim = Simulator::new();
+    sim.set_conflict_policy(ConflictPolicy::Merge(Box::new(|_pos, _proposals| {
+      vec![BlockChange::BlockType(life_type())]
+    })));
+    sim.add_updater(RIGHTWARD, move_right);
+    sim.add_updater(LEFTWARD, move_left);
+
+    let mut chunk_map = movers_chunk_map();
+    sim.step(&mut chunk_map);
+    assert_eq!(row(&chunk_map), vec![EMPTY, life_type(), EMPTY]);
+  }
+
+  #[test]
+  fn test_report_conflicts() {
+    let mut sim = Simulator::new();
+    sim.set_report_conflicts(true);
+    sim.add_updater(RIGHTWARD, move_right);
+    sim.add_updater(LEFTWARD, move_left);
+
+    let mut chunk_map = movers_chunk_map();
+    let report = sim.step(&mut chunk_map);
+    assert_eq!(report.conflicts.len(), 1);
+
+    let conflict = &report.conflicts[0];
+    assert_eq!(conflict.pos, WorldPos::new(2, 0, 0));
+    assert_eq!(
+      conflict
+        .proposals
+        .iter()
+        .map(|p| (p.updater, p.origin))
+        .collect::<Vec<_>>(),
+      vec![(0, WorldPos::new(1, 0, 0)), (1, WorldPos::new(3, 0, 0))]
+    );
+    assert_eq!(conflict.resolution, vec![BlockChange::BlockType(RIGHTWARD)]);
+  }
+
+  const BUD: BlockType = BlockType(49);
+  const SEED: BlockType = BlockType(50);
+  const SPROUT: BlockType = BlockType(51);
+  const MOSS: BlockType = BlockType(52);
+  const DRIP: BlockType = BlockType(53);
+
+  thread_local! {
+    static BUD_RUNS: Cell<usize> = Cell::new(0);
+    static SEED_RUNS: Cell<usize> = Cell::new(0);
+    static SPROUT_RUNS: Cell<usize> = Cell::new(0);
+    static MOSS_RUNS: Cell<usize> = Cell::new(0);
+    static DRIP_RUNS: Cell<usize> = Cell::new(0);
+  }
+
+  fn runs(counter: &'static LocalKey<Cell<usize>>) -> usize { counter.with(Cell::get) }
+
+  // Neither updater makes any queries, so both are cacheable forever
+  fn seeds_and_sprouts_simulator() -> Simulator {
+    let mut sim = Simulator::new();
+    sim.add_updater(SEED, |updater| {
+      updater.implement(|_handle: &UpdaterHandle| {
+        SEED_RUNS.with(|runs| runs.set(runs.get() + 1));
+        Some(SPROUT)
+      });
+    });
+    sim.add_updater(SPROUT, |updater| {
+      updater.implement(|_handle: &UpdaterHandle| {
+        SPROUT_RUNS.with(|runs| runs.set(runs.get() + 1));
+        None as Option<BlockType>
+      });
+    });
+    sim
+  }
+
+  #[test]
+  fn test_forever_runs_once_per_block() {
+    let sim = seeds_and_sprouts_simulator();
+    let mut chunk_map = life_chunk_map(&[ChunkCoord::origin()], &[]);
+    chunk_map.set_block_type(WorldPos::new(1, 0, 0), SPROUT);
+    chunk_map.set_block_type(WorldPos::new(2, 0, 0), SPROUT);
+
+    sim.step(&mut chunk_map);
+    assert_eq!(runs(&SPROUT_RUNS), 2);
+
+    for _ in 0..3 {
+      sim.step(&mut chunk_map);
+    }
+    assert_eq!(runs(&SPROUT_RUNS), 2);
+
+    // Only the new sprout gets its first run
+    chunk_map.set_block_type(WorldPos::new(5, 5, 0), SPROUT);
+    sim.step(&mut chunk_map);
+    sim.step(&mut chunk_map);
+    assert_eq!(runs(&SPROUT_RUNS), 3);
+  }
+
+  #[test]
+  fn test_forever_runs_on_blocks_it_creates() {
+    let sim = seeds_and_sprouts_simulator();
+    let mut chunk_map = life_chunk_map(&[ChunkCoord::origin()], &[]);
+    chunk_map.set_block_type(WorldPos::new(1, 0, 0), SEED);
+    chunk_map.set_block_type(WorldPos::new(2, 0, 0), SEED);
+    chunk_map.set_block_type(WorldPos::new(3, 0, 0), SPROUT);
+
+    sim.step(&mut chunk_map);
+    assert_eq!((runs(&SEED_RUNS), runs(&SPROUT_RUNS)), (2, 1));
+    assert_eq!(row(&chunk_map), vec![SPROUT, SPROUT, SPROUT]);
+
+    sim.step(&mut chunk_map);
+    assert_eq!((runs(&SEED_RUNS), runs(&SPROUT_RUNS)), (2, 3));
+
+    sim.step(&mut chunk_map);
+    assert_eq!((runs(&SEED_RUNS), runs(&SPROUT_RUNS)), (2, 3));
+  }
+
+  #[test]
+  fn test_forever_conflict_loser_runs_again() {
+    let mut sim = Simulator::new();
+    sim.add_updater(RIGHTWARD, move_right);
+    sim.add_updater(LEFTWARD, move_left);
+
+    let mut chunk_map = movers_chunk_map();
+    sim.step(&mut chunk_map);
+    assert_eq!(row(&chunk_map), vec![EMPTY, RIGHTWARD, LEFTWARD]);
+
+    // Nothing next to the leftward mover changes, but its swap was dropped,
+    // so it gets to try again
+    chunk_map.set_block_type(WorldPos::new(2, 0, 0), EMPTY);
+    sim.step(&mut chunk_map);
+    assert_eq!(row(&chunk_map), vec![EMPTY, LEFTWARD, EMPTY]);
+  }
+
+  #[test]
+  fn test_new_target_blocks_run_without_reading_their_type() {
+    const AGE: BlockField<u8> = BlockField::new(0);
+
+    let mut sim = Simulator::new();
+    // Only reads a field of its own, so no block type change wakes it
+    sim.add_updater(BUD, |updater| {
+      updater.prepare_query(&GetBlockField::new(AGE));
+      updater.implement(|_handle: &UpdaterHandle| {
+        BUD_RUNS.with(|runs| runs.set(runs.get() + 1));
+        None as Option<BlockType>
+      });
+    });
+
+    let mut chunk_map = life_chunk_map(&[ChunkCoord::origin()], &[]);
+    sim.step(&mut chunk_map);
+    assert_eq!(runs(&BUD_RUNS), 0);
+
+    chunk_map.set_block_type(WorldPos::new(5, 5, 0), BUD);
+    sim.step(&mut chunk_map);
+    assert_eq!(runs(&BUD_RUNS), 1);
+
+    sim.step(&mut chunk_map);
+    assert_eq!(runs(&BUD_RUNS), 1);
+  }
+
+  #[test]
+  fn test_mixed_cacheabilities_stay_separate() {
+    const AGE: BlockField<u8> = BlockField::new(0);
+    let mut sim = Simulator::new();
+    // Reads its own age and its neighbors' block types
+    sim.add_updater(MOSS, |updater| {
+      updater.prepare_query(&GetBlockField::new(AGE));
+      updater.prepare_query(&Chebyshev2DNeighbors::new(1, &GetBlockType::new()));
+      updater.implement(|_handle: &UpdaterHandle| {
+        MOSS_RUNS.with(|runs| runs.set(runs.get() + 1));
+      });
+    });
+
+    let mut chunk = Chunk::new();
+    chunk.fill_with_block_type(MOSS);
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.load(ChunkCoord::origin(), chunk);
+    sim.step(&mut chunk_map);
+    assert_eq!(runs(&MOSS_RUNS), CHUNK_WIDTH_E3);
+
+    // A change of age only wakes up the block itself, rather than its whole
+    // neighborhood
+    chunk_map.set_field(WorldPos::new(5, 5, 5), AGE, 3);
+    sim.step(&mut chunk_map);
+    assert_eq!(runs(&MOSS_RUNS), CHUNK_WIDTH_E3 + 1);
+
+    // A change of block type still wakes up the neighbors, minus the block
+    // that is no longer moss
+    chunk_map.set_block_type(WorldPos::new(5, 5, 5), EMPTY);
+    sim.step(&mut chunk_map);
+    assert_eq!(runs(&MOSS_RUNS), CHUNK_WIDTH_E3 + 27);
+  }
+
+  #[test]
+  fn test_offset_reads_only_wake_their_readers() {
+    let mut sim = Simulator::new();
+    // Only reads the block type directly below
+    sim.add_updater(DRIP, |updater| {
+      updater.prepare_query(&At::new(RelativePos::new(0, 0, -1), &GetBlockType::new()));
+      updater.implement(|_handle: &UpdaterHandle| {
+        DRIP_RUNS.with(|runs| runs.set(runs.get() + 1));
+      });
+    });
+
+    let mut chunk = Chunk::new();
+    chunk.fill_with_block_type(DRIP);
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.load(ChunkCoord::origin(), chunk);
+    sim.step(&mut chunk_map);
+    assert_eq!(runs(&DRIP_RUNS), CHUNK_WIDTH_E3);
+
+    // Only the block above reads the change
+    chunk_map.set_block_type(WorldPos::new(5, 5, 5), EMPTY);
+    sim.step(&mut chunk_map);
+    assert_eq!(runs(&DRIP_RUNS), CHUNK_WIDTH_E3 + 1);
+
+    // A block that becomes a target runs too, even though it doesn't read
+    // itself
+    chunk_map.set_block_type(WorldPos::new(5, 5, 5), DRIP);
+    sim.step(&mut chunk_map);
+    assert_eq!(runs(&DRIP_RUNS), CHUNK_WIDTH_E3 + 3);
   }
 }
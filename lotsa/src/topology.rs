@@ -0,0 +1,164 @@
+use crate::world_pos::WorldPos;
+
+/// The shape of a world. Bounds are given as a box from `min` (inclusive) to
+/// `max` (exclusive).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topology {
+  Infinite,
+  Bounded {
+    min: WorldPos,
+    max: WorldPos,
+  },
+  /// Wraps around on the chosen axes, and is bounded on the rest
+  Toroidal {
+    min: WorldPos,
+    max: WorldPos,
+    wrap_x: bool,
+    wrap_y: bool,
+    wrap_z: bool,
+  },
+}
+
+use Topology::*;
+
+impl Topology {
+  pub fn bounded(min: WorldPos, max: WorldPos) -> Topology {
+    debug_assert!(min.x < max.x && min.y < max.y && min.z < max.z);
+    Bounded { min, max }
+  }
+
+  /// A board of the given size on the z = 0 layer, wrapping on x and y
+  pub fn torus_2d(width: i32, height: i32) -> Topology {
+    debug_assert!(width > 0 && height > 0);
+    Toroidal {
+      min: WorldPos::new(0, 0, 0),
+      max: WorldPos::new(width, height, 1),
+      wrap_x: true,
+      wrap_y: true,
+      wrap_z: false,
+    }
+  }
+
+  pub fn contains(&self, pos: WorldPos) -> bool {
+    match *self {
+      Infinite => true,
+      Bounded { min, max } | Toroidal { min, max, .. } => {
+        pos.x >= min.x
+          && pos.x < max.x
+          && pos.y >= min.y
+          && pos.y < max.y
+          && pos.z >= min.z
+          && pos.z < max.z
+      },
+    }
+  }
+
+  /// Maps the position to where it actually is in the world, or returns None
+  /// if the position is outside of the world entirely.
+  pub fn resolve(&self, pos: WorldPos) -> Option<WorldPos> {
+    match *self {
+      Infinite => Some(pos),
+      Bounded { .. } => {
+        if self.contains(pos) {
+          Some(pos)
+        } else {
+          None
+        }
+      },
+      Toroidal {
+        min,
+        max,
+        wrap_x,
+        wrap_y,
+        wrap_z,
+      } => Some(WorldPos::new(
+        resolve_axis(pos.x, min.x, max.x, wrap_x)?,
+        resolve_axis(pos.y, min.y, max.y, wrap_y)?,
+        resolve_axis(pos.z, min.z, max.z, wrap_z)?,
+      )),
+    }
+  }
+}
+
+impl Default for Topology {
+  fn default() -> Topology { Infinite }
+}
+
+fn resolve_axis(n: i32, min: i32, max: i32, wrap: bool) -> Option<i32> {
+  if wrap {
+    Some(min + (n - min).rem_euclid(max - min))
+  } else if n >= min && n < max {
+    Some(n)
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_infinite() {
+    let topology = Topology::Infinite;
+    let pos = WorldPos::new(-1000, 5, 1000);
+    assert!(topology.contains(pos));
+    assert_eq!(topology.resolve(pos), Some(pos));
+  }
+
+  #[test]
+  fn test_bounded() {
+    let topology = Topology::bounded(WorldPos::new(-2, 0, 0), WorldPos::new(2, 4, 1));
+
+    assert!(topology.contains(WorldPos::new(-2, 0, 0)));
+    assert!(topology.contains(WorldPos::new(1, 3, 0)));
+    assert!(!topology.contains(WorldPos::new(2, 3, 0)));
+    assert!(!topology.contains(WorldPos::new(0, 0, 1)));
+
+    assert_eq!(
+      topology.resolve(WorldPos::new(1, 3, 0)),
+      Some(WorldPos::new(1, 3, 0))
+    );
+    assert_eq!(topology.resolve(WorldPos::new(-3, 0, 0)), None);
+  }
+
+  #[test]
+  fn test_torus_2d() {
+    let topology = Topology::torus_2d(10, 5);
+
+    assert!(topology.contains(WorldPos::new(9, 4, 0)));
+    assert!(!topology.contains(WorldPos::new(10, 4, 0)));
+
+    assert_eq!(
+      topology.resolve(WorldPos::new(10, 5, 0)),
+      Some(WorldPos::new(0, 0, 0))
+    );
+    assert_eq!(
+      topology.resolve(WorldPos::new(-1, -1, 0)),
+      Some(WorldPos::new(9, 4, 0))
+    );
+    assert_eq!(
+      topology.resolve(WorldPos::new(-21, 12, 0)),
+      Some(WorldPos::new(9, 2, 0))
+    );
+    assert_eq!(topology.resolve(WorldPos::new(3, 3, 1)), None);
+    assert_eq!(topology.resolve(WorldPos::new(3, 3, -1)), None);
+  }
+
+  #[test]
+  fn test_toroidal_on_one_axis() {
+    let topology = Topology::Toroidal {
+      min: WorldPos::new(0, 0, 0),
+      max: WorldPos::new(32, 32, 32),
+      wrap_x: false,
+      wrap_y: false,
+      wrap_z: true,
+    };
+
+    assert_eq!(
+      topology.resolve(WorldPos::new(5, 6, 32)),
+      Some(WorldPos::new(5, 6, 0))
+    );
+    assert_eq!(topology.resolve(WorldPos::new(32, 6, 7)), None);
+  }
+}
@@ -3,6 +3,10 @@ pub trait UniqueDescrip {
   fn unique_descrip(&self) -> String;
 }
 
+impl UniqueDescrip for bool {
+  fn unique_descrip(&self) -> String { format!("{}bool", self) }
+}
+
 impl UniqueDescrip for u8 {
   fn unique_descrip(&self) -> String { format!("{}u8", self) }
 }
@@ -15,10 +19,23 @@ impl UniqueDescrip for u32 {
   fn unique_descrip(&self) -> String { format!("{}u32", self) }
 }
 
+impl UniqueDescrip for i8 {
+  fn unique_descrip(&self) -> String { format!("{}i8", self) }
+}
+
+impl UniqueDescrip for i16 {
+  fn unique_descrip(&self) -> String { format!("{}i16", self) }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
 
+  #[test]
+  fn test_descrip_bool() {
+    assert_eq!("truebool", true.unique_descrip());
+  }
+
   #[test]
   fn test_descrip_u8() {
     assert_eq!("42u8", 42u8.unique_descrip());
@@ -33,4 +50,14 @@ mod tests {
   fn test_descrip_u32() {
     assert_eq!("42u32", 42u32.unique_descrip());
   }
+
+  #[test]
+  fn test_descrip_i8() {
+    assert_eq!("-42i8", (-42i8).unique_descrip());
+  }
+
+  #[test]
+  fn test_descrip_i16() {
+    assert_eq!("-42i16", (-42i16).unique_descrip());
+  }
 }
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  chunk::CHUNK_WIDTH, chunk_coord::ChunkCoord, chunk_pos::ChunkPos, relative_pos::RelativePos,
+};
+
+#[derive(
+  Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct WorldPos {
+  pub x: i32,
+  pub y: i32,
+  pub z: i32,
+}
+
+impl WorldPos {
+  pub fn new(x: i32, y: i32, z: i32) -> WorldPos { WorldPos { x, y, z } }
+
+  pub fn from_parts(coord: ChunkCoord, pos: ChunkPos) -> WorldPos {
+    let w = i32::from(CHUNK_WIDTH);
+    WorldPos::new(
+      coord.x * w + i32::from(pos.x()),
+      coord.y * w + i32::from(pos.y()),
+      coord.z * w + i32::from(pos.z()),
+    )
+  }
+
+  pub fn split(self) -> (ChunkCoord, ChunkPos) {
+    let w = i32::from(CHUNK_WIDTH);
+    (
+      ChunkCoord::new(
+        self.x.div_euclid(w),
+        self.y.div_euclid(w),
+        self.z.div_euclid(w),
+      ),
+      ChunkPos::new(
+        self.x.rem_euclid(w) as u8,
+        self.y.rem_euclid(w) as u8,
+        self.z.rem_euclid(w) as u8,
+      ),
+    )
+  }
+
+  pub fn chunk_coord(self) -> ChunkCoord { self.split().0 }
+
+  pub fn chunk_pos(self) -> ChunkPos { self.split().1 }
+
+  pub fn offset(self, r: RelativePos) -> WorldPos {
+    WorldPos::new(
+      self.x + i32::from(r.x),
+      self.y + i32::from(r.y),
+      self.z + i32::from(r.z),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_split() {
+    let (coord, pos) = WorldPos::new(1, 2, 3).split();
+    assert_eq!(coord, ChunkCoord::new(0, 0, 0));
+    assert_eq!(pos, ChunkPos::new(1, 2, 3));
+
+    let (coord, pos) = WorldPos::new(33, 64, 95).split();
+    assert_eq!(coord, ChunkCoord::new(1, 2, 2));
+    assert_eq!(pos, ChunkPos::new(1, 0, 31));
+
+    let (coord, pos) = WorldPos::new(-1, -32, -33).split();
+    assert_eq!(coord, ChunkCoord::new(-1, -1, -2));
+    assert_eq!(pos, ChunkPos::new(31, 0, 31));
+  }
+
+  #[test]
+  fn test_from_parts() {
+    for &p in [
+      WorldPos::new(0, 0, 0),
+      WorldPos::new(5, 40, 70),
+      WorldPos::new(-1, -32, -33),
+      WorldPos::new(-100, 100, -7),
+    ]
+    .iter()
+    {
+      let (coord, pos) = p.split();
+      assert_eq!(WorldPos::from_parts(coord, pos), p);
+    }
+  }
+
+  #[test]
+  fn test_offset() {
+    let p = WorldPos::new(0, 31, -5);
+    assert_eq!(
+      p.offset(RelativePos::new(-1, 1, 2)),
+      WorldPos::new(-1, 32, -3)
+    );
+    assert_eq!(
+      p.offset(RelativePos::new(-1, 1, 2)).chunk_coord(),
+      ChunkCoord::new(-1, 1, -1)
+    );
+  }
+}